@@ -3,30 +3,84 @@
 // There's a little bit of duplicated code going around for each endpoint
 // it could in theory be shortned once again with macros, but at the cost of flexibility
 use actix_web::{
-    delete, error::BlockingError, get, http::StatusCode, post, put, web, HttpResponse, Responder,
+    delete,
+    dev::{Payload, RequestHead},
+    error::BlockingError,
+    get,
+    http::{header, StatusCode},
+    patch, post, put,
+    web::{self, Bytes},
+    FromRequest, HttpRequest, HttpResponse, Responder, ResponseError,
 };
-use diesel::PgConnection;
+use futures_util::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    future::{ready, Ready},
+    time::Duration,
+};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    models::{self, Error, InventoryItem, Warehouse},
+    auth::{self, Principal},
+    batch::{Batch, OperationResult},
+    conditional::{etag, IfMatch, IfNoneMatch},
+    metrics,
+    models::{
+        self, Error, InventoryItem, NewApiKey, NewInventoryItem, NewWarehouse, Trash, Warehouse,
+    },
+    pagination::{limits, ItemParams, Page, Paged, WarehouseParams},
     service,
-    util::{format_item_csv, format_warehouse_csv},
-    DbPool,
+    util::{CsvFormat, CsvPayload, CsvRow},
+    versions::{ApiVersion, Body, ItemUpdate, ItemV1, Patch, Versioned},
 };
-
-/// Default limit for batch queries
-const DEFAULT_LIMIT: i64 = 100;
+use warehouser_core::repository::{Pool, Repository};
 
 // Payloads
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
 pub struct IdPayload {
     id: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StockPayload {
+    id: i32,
+    quantity: Option<i32>,
+}
+
+impl StockPayload {
+    // Convenience method, one unit unless told otherwise
+    fn quantity(&self) -> i32 {
+        self.quantity.unwrap_or(1)
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TransferPayload {
+    to: i32,
+    items: Vec<i32>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportPayload {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BatchPayload {
+    /// Keep going after an operation fails, keeping the ones that succeed
+    #[serde(default)]
+    continue_on_error: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct LimitPayload {
     limit: Option<i64>,
 }
@@ -34,18 +88,25 @@ pub struct LimitPayload {
 impl LimitPayload {
     // Convenience method
     fn limit(&self) -> i64 {
-        self.limit.unwrap_or(DEFAULT_LIMIT)
+        self.limit.unwrap_or(limits().default)
     }
 }
 
-/// Implements a lot of default behaviour for api endpoints
+/// Who is making a request, as recorded in the movement ledger
 ///
-/// ## Parameters
-/// * `pool` - The `DbPool` for this request
-/// * `ser` - A function ptr that serializes the `Output` of your requester
-/// * `req` - A function that produces a serializable result,
-/// usually a database action. This function is executed in a blocking context.
-/// * `status` - The status to return on success
+/// This is the name of the request's API key
+pub struct Actor(String);
+
+impl FromRequest for Actor {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let principal = Principal::from_request(req, payload).into_inner();
+        ready(principal.map(|principal| Actor(principal.name)))
+    }
+}
 
 // A note on efficiency
 // The Rust compiler is *pretty smart*
@@ -54,8 +115,17 @@ impl LimitPayload {
 // inline a few things, like the function pointers
 // and closures, and may even inline this function
 // into the calling location.
+
+/// Implements a lot of default behaviour for api endpoints
+///
+/// ## Parameters
+/// * `pool` - The `Pool` for this request
+/// * `ser` - A function ptr that serializes the `Output` of your requester
+/// * `req` - A function that produces a serializable result,
+///   usually a database action. This function is executed in a blocking context.
+/// * `status` - The status to return on success
 pub async fn request<ErrorType, Output, Requester>(
-    pool: web::Data<DbPool>,
+    pool: web::Data<Pool>,
     ser: fn(&Output) -> Result<String, ErrorType>,
     req: Requester,
     status: StatusCode,
@@ -63,227 +133,1248 @@ pub async fn request<ErrorType, Output, Requester>(
 where
     ErrorType: Debug,
     Output: 'static + Serialize + Send,
-    Requester: 'static + Fn(&PgConnection) -> models::Result<Output> + Send,
+    Requester: 'static + Fn(&dyn Repository) -> models::Result<Output> + Send,
+{
+    match execute(pool, req).await {
+        Ok(out) => {
+            // Success, serialize the body
+            let formatted = ser(&out).expect("Failed to serialize!");
+            HttpResponse::build(status).body(formatted)
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// Like `request`, but for endpoints that create a resource
+///
+/// Responds with `201 Created`, the created resource as json,
+/// and a `Location` header produced by `location`
+pub async fn create<Output, Requester>(
+    pool: web::Data<Pool>,
+    location: fn(&Output) -> String,
+    req: Requester,
+) -> impl Responder
+where
+    Output: 'static + Serialize + Send,
+    Requester: 'static + Fn(&dyn Repository) -> models::Result<Output> + Send,
+{
+    match execute(pool, req).await {
+        Ok(out) => {
+            let formatted = serde_json::to_string_pretty(&out).expect("Failed to serialize!");
+            HttpResponse::Created()
+                .header(header::LOCATION, location(&out))
+                .body(formatted)
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// Like `request`, but for endpoints that respond with a single item or warehouse
+///
+/// `req` gives the row's `ETag` along with it, see `ApiVersion::etag` for an item's.
+/// When `cached` shows the client already has that copy,
+/// responds with `304 Not Modified` and no body
+pub async fn tagged<Output, Requester>(
+    pool: web::Data<Pool>,
+    cached: IfNoneMatch,
+    req: Requester,
+) -> impl Responder
+where
+    Output: 'static + Serialize + Send,
+    Requester: 'static + Fn(&dyn Repository) -> models::Result<(String, Output)> + Send,
+{
+    match execute(pool, req).await {
+        Ok((tag, _)) if cached.matches(&tag) => HttpResponse::NotModified()
+            .header(header::ETAG, tag)
+            .finish(),
+        Ok((tag, out)) => {
+            let formatted = serde_json::to_string_pretty(&out).expect("Failed to serialize!");
+            HttpResponse::Ok().header(header::ETAG, tag).body(formatted)
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// Marks a response whose shape was picked by the `ApiVersion` in `Accept`,
+/// so caches keep each version's copy apart
+pub fn versioned(resp: impl Responder) -> impl Responder {
+    resp.with_header(header::VARY, "Accept")
+}
+
+/// Like `request`, but for endpoints that list a page of rows
+///
+/// Responds with `200 OK`, and when there are more rows,
+/// a `Link` header pointing at the next page
+pub async fn page<ErrorType, Output, Requester>(
+    pool: web::Data<Pool>,
+    http: HttpRequest,
+    ser: fn(&Output) -> Result<String, ErrorType>,
+    req: Requester,
+) -> impl Responder
+where
+    ErrorType: Debug,
+    Output: 'static + Serialize + Send,
+    Requester: 'static + Fn(&dyn Repository) -> models::Result<Page<Output>> + Send,
+{
+    match execute(pool, req).await {
+        Ok(page) => {
+            let formatted = ser(&page.items).expect("Failed to serialize!");
+            let mut resp = HttpResponse::Ok();
+            if let Some(cursor) = page.next {
+                resp.header(header::LINK, next_link(&http, &cursor));
+            }
+            resp.body(formatted)
+        }
+        Err(resp) => resp,
+    }
+}
+
+// The same request with its cursor swapped for `cursor`,
+// the cursor is url safe base64 so it needs no escaping
+fn next_link(http: &HttpRequest, cursor: &str) -> String {
+    let cursor = format!("cursor={cursor}");
+    let mut query: Vec<&str> = http
+        .query_string()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("cursor="))
+        .collect();
+    query.push(&cursor);
+
+    format!("<{}?{}>; rel=\"next\"", http.path(), query.join("&"))
+}
+
+/// Responds with a csv export of a list endpoint
+///
+/// When the client gave a `limit`, the response is that one page,
+/// with a `Link` to the next one like the json lists.
+/// Otherwise every matching row is streamed, fetched from the database a page at a time
+pub async fn export<Row, Params, Requester>(
+    pool: web::Data<Pool>,
+    http: HttpRequest,
+    filename: &'static str,
+    format: models::Result<CsvFormat>,
+    params: Params,
+    req: Requester,
+) -> HttpResponse
+where
+    Row: 'static + CsvRow + Send,
+    Params: 'static + Paged + Send,
+    Requester:
+        'static + Fn(&dyn Repository, &Params) -> models::Result<Page<Vec<Row>>> + Clone + Send,
+{
+    let format = match format {
+        Ok(format) => format,
+        Err(e) => return e.error_response(),
+    };
+
+    let streaming = params.requested_limit().is_none();
+    let first = if streaming {
+        params.at(params.cursor(), limits().max)
+    } else {
+        params.clone()
+    };
+
+    let fetch = req.clone();
+    let page = match execute(pool.clone(), move |repo| fetch(repo, &first)).await {
+        Ok(page) => page,
+        Err(resp) => return resp,
+    };
+
+    let body = match format.write(&page.items, true) {
+        Ok(body) => body,
+        Err(e) => return e.error_response(),
+    };
+
+    let mut resp = HttpResponse::Ok();
+    resp.content_type("text/csv; charset=utf-8; header=present")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+        );
+
+    if !streaming {
+        if let Some(cursor) = page.next {
+            resp.header(header::LINK, next_link(&http, &cursor));
+        }
+        return resp.body(body);
+    }
+
+    // The status and headers are already sent by the time a later page fails,
+    // so all we can do is cut the response short
+    let rest = stream::unfold(page.next, move |next| {
+        let (pool, req, format) = (pool.clone(), req.clone(), format.clone());
+        let params = params.at(next.clone(), limits().max);
+
+        async move {
+            next.as_ref()?;
+
+            let result = run(pool, move |repo| req(repo, &params))
+                .await
+                .and_then(|page| Ok((format.write(&page.items, false)?, page.next)));
+
+            match result {
+                Ok((rows, next)) => Some((Ok(Bytes::from(rows)), next)),
+                Err(e) => Some((Err(actix_web::Error::from(e)), None)),
+            }
+        }
+    });
+
+    let first = stream::once(ready(Ok(Bytes::from(body))));
+    resp.streaming(Box::pin(first.chain(rest)))
+}
+
+/// Runs `req` on a pooled connection in a blocking context
+/// on failure, produces the error response to send back
+async fn execute<Output, Requester>(
+    pool: web::Data<Pool>,
+    req: Requester,
+) -> Result<Output, HttpResponse>
+where
+    Output: 'static + Send,
+    Requester: 'static + Fn(&dyn Repository) -> models::Result<Output> + Send,
 {
+    run(pool, req).await.map_err(|e| e.error_response())
+}
+
+/// Runs `req` on a pooled connection in a blocking context
+pub async fn run<Output, Requester>(pool: web::Data<Pool>, req: Requester) -> models::Result<Output>
+where
+    Output: 'static + Send,
+    Requester: 'static + Fn(&dyn Repository) -> models::Result<Output> + Send,
+{
+    let _timer = metrics::blocking();
+
     // When Diesel is updated to support async, this can be moved out
     let future: Result<Output, BlockingError<Error>> = web::block(move || {
         // Get a db handle from the connection pool
-        let conn = pool.get().map_err(|_| Error {
-            code: StatusCode::INTERNAL_SERVER_ERROR,
-            msg: "Couldn't get a db connection".to_owned(),
+        let waiting = metrics::pool_wait();
+        let conn = pool.get(None).map_err(|_| Error::Internal {
+            detail: "Couldn't get a db connection".to_owned(),
         })?;
+        waiting.observe_duration();
 
         // Execute the user request
-        req(&conn)
+        req(conn.repo())
     })
     .await;
 
     match future {
-        Ok(out) => {
-            // Success, serialize the body
-            let formatted = ser(&out).expect("Failed to serialize!");
-            HttpResponse::build(status).body(formatted)
+        Ok(out) => Ok(out),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(Error::Internal {
+            detail: "Unexpected error: Blocking operating cancelled".to_owned(),
+        }),
+    }
+}
+
+/// How long a readiness probe waits for a database connection
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The process is up, nothing else is checked
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    security(()),
+    responses((status = 200, description = "The process is up"))
+)]
+#[get("/healthz")]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// The server can take requests, the database is reachable and has every migration
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    security(()),
+    responses(
+        (status = 200, description = "Ready for requests"),
+        (status = 503, description = "The database is unreachable or out of date", body = Problem),
+    )
+)]
+#[get("/readyz")]
+pub async fn readyz(pool: web::Data<Pool>) -> HttpResponse {
+    let _timer = metrics::blocking();
+
+    // Not `run`, a probe shouldn't wait the pool's whole timeout for a connection
+    let ready = web::block(move || {
+        let conn = pool.get(Some(READY_TIMEOUT)).map_err(|e| Error::NotReady {
+            detail: format!("Couldn't get a db connection: {e}"),
+            pending_migrations: Vec::new(),
+        })?;
+        service::check_migrations(conn.repo())
+    })
+    .await;
+
+    match ready {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "status": "ready" })),
+        Err(BlockingError::Error(e)) => e.error_response(),
+        Err(BlockingError::Canceled) => Error::Internal {
+            detail: "Unexpected error: Blocking operating cancelled".to_owned(),
         }
-        Err(BlockingError::Error(e)) => HttpResponse::build(e.code).body(e.msg),
-        Err(BlockingError::Canceled) => HttpResponse::InternalServerError()
-            .body("Unexpected error: Blocking operating cancelled"),
+        .error_response(),
     }
 }
 
+/// Every metric, for Prometheus to scrape
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    security(()),
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"))
+)]
+#[get("/metrics")]
+pub async fn get_metrics(pool: web::Data<Pool>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics::render(&pool))
+}
+
+fn item_location(item: &Versioned<InventoryItem, ItemV1>) -> String {
+    let id = match item {
+        Versioned::V1(item) => item.id,
+        Versioned::V2(item) => item.id,
+    };
+    format!("/api/item/{}", id)
+}
+
+fn warehouse_location(whouse: &Warehouse) -> String {
+    format!("/api/warehouse/{}", whouse.id)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/item",
+    tag = "items",
+    request_body = NewItemV1,
+    responses(
+        (status = 201, description = "The created item", body = ItemV1),
+        (status = 400, description = "The warehouse can't take the item", body = Problem),
+        (status = 422, description = "Fields break the rules", body = Problem),
+    )
+)]
 #[post("")]
 pub async fn create_item(
-    pool: web::Data<DbPool>,
-    query: web::Json<InventoryItem>,
+    pool: web::Data<Pool>,
+    actor: Actor,
+    version: ApiVersion,
+    query: Body<NewInventoryItem>,
 ) -> impl Responder {
-    request(
-        pool,
-        serde_json::to_string_pretty,
-        move |conn| service::create_item(conn, &query),
-        StatusCode::CREATED,
+    versioned(
+        create(pool, item_location, move |repo| {
+            service::create_item(repo, &actor.0, &query)
+                .map(|item| version.item(item))
+                .map_err(|e| query.version().error(e))
+        })
+        .await,
     )
-    .await
 }
 
-#[get("/{id}")]
-pub async fn get_item(pool: web::Data<DbPool>, path: web::Path<IdPayload>) -> impl Responder {
+// Routes csv bodies of the import endpoints to the bulk importers,
+// everything else goes on to the json importers
+fn csv_body(req: &RequestHead) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media| media.trim().eq_ignore_ascii_case("text/csv"))
+}
+
+/// Create every item of a csv file, or none of them
+// Documented with the json importer, it shares the path and method
+#[post("/import", guard = "csv_body")]
+pub async fn import_items_csv(
+    pool: web::Data<Pool>,
+    actor: Actor,
+    query: web::Query<ImportPayload>,
+    csv: web::Query<CsvPayload>,
+    body: web::Bytes,
+) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::get_item(conn, path.id),
+        move |repo| {
+            let rows = CsvFormat::new::<InventoryItem>(&csv)?.read(&body)?;
+            service::import_items_csv(repo, &actor.0, rows, query.dry_run)
+        },
         StatusCode::OK,
     )
     .await
 }
 
+/// Create an item with an id picked by the client
+///
+/// A `text/csv` body in the export's layout imports every row, or none of them,
+/// and responds with an `ImportReport`
+#[utoipa::path(
+    post,
+    path = "/api/item/import",
+    tag = "items",
+    params(ImportPayload, CsvPayload),
+    request_body = ItemV1,
+    responses(
+        (status = 201, description = "The created item", body = ItemV1),
+        (status = 200, description = "Every csv row was imported", body = ImportReport),
+        (status = 400, description = "The id is taken", body = Problem),
+        (status = 422, description = "Fields or csv rows break the rules", body = Problem),
+    )
+)]
+#[post("/import")]
+pub async fn import_item(
+    pool: web::Data<Pool>,
+    actor: Actor,
+    version: ApiVersion,
+    data: Body<InventoryItem>,
+) -> impl Responder {
+    versioned(
+        create(pool, item_location, move |repo| {
+            service::import_item(repo, &actor.0, &data)
+                .map(|item| version.item(item))
+                .map_err(|e| data.version().error(e))
+        })
+        .await,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/item/{id}",
+    tag = "items",
+    params(IdPayload, ("If-None-Match" = Option<String>, Header, description = "The `ETag`s of the versions the client has")),
+    responses(
+        (status = 200, description = "The item", body = ItemV1, headers(("ETag" = String, description = "Its version"))),
+        (status = 304, description = "The client's copy, named in `If-None-Match`, is current", headers(("ETag" = String, description = "Its version"))),
+        (status = 404, description = "No such item", body = Problem),
+    )
+)]
+#[get("/{id}")]
+pub async fn get_item(
+    pool: web::Data<Pool>,
+    version: ApiVersion,
+    cached: IfNoneMatch,
+    path: web::Path<IdPayload>,
+) -> impl Responder {
+    versioned(
+        tagged(pool, cached, move |repo| {
+            service::get_item(repo, path.id)
+                .map(|item| (version.etag(item.version), version.item(item)))
+        })
+        .await,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/item",
+    tag = "items",
+    params(ItemParams),
+    responses(
+        (status = 200, description = "A page of items, `Link` points at the next one", body = [ItemV1]),
+        (status = 422, description = "Bad limit, sort, or filter", body = Problem),
+    )
+)]
 #[get("")]
-pub async fn get_items(pool: web::Data<DbPool>, query: web::Query<LimitPayload>) -> impl Responder {
-    request(
-        pool,
-        serde_json::to_string_pretty,
-        move |conn| service::get_items(conn, query.limit()),
-        StatusCode::OK,
+pub async fn get_items(
+    pool: web::Data<Pool>,
+    http: HttpRequest,
+    version: ApiVersion,
+    query: web::Query<ItemParams>,
+) -> impl Responder {
+    versioned(
+        page(pool, http, serde_json::to_string_pretty, move |repo| {
+            service::list_items(repo, &query).map(|page| page.map(|items| version.items(items)))
+        })
+        .await,
     )
-    .await
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/item",
+    tag = "items",
+    params(("If-Match" = String, Header, description = "The `ETag` of the version being changed, or `*` for any")),
+    request_body = ItemV1,
+    responses(
+        (status = 200, description = "The updated item", body = ItemV1, headers(("ETag" = String, description = "Its version"))),
+        (status = 400, description = "Stock can't be changed here", body = Problem),
+        (status = 404, description = "No such item", body = Problem),
+        (status = 412, description = "The item has changed since the version in `If-Match`", body = Problem),
+        (status = 422, description = "Fields break the rules", body = Problem),
+        (status = 428, description = "No `If-Match`", body = Problem),
+    )
+)]
 #[put("")]
 pub async fn update_item(
-    pool: web::Data<DbPool>,
-    data: web::Json<InventoryItem>,
+    pool: web::Data<Pool>,
+    version: ApiVersion,
+    if_match: IfMatch,
+    data: Body<ItemUpdate>,
 ) -> impl Responder {
-    request(
-        pool,
-        serde_json::to_string_pretty,
-        move |conn| service::update_item(conn, &data),
-        StatusCode::OK,
+    versioned(
+        tagged(pool, IfNoneMatch::default(), move |repo| {
+            service::update_item(repo, &data, &if_match)
+                .map(|item| (version.etag(item.version), version.item(item)))
+                .map_err(|e| data.version().error(e))
+        })
+        .await,
+    )
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/item/{id}",
+    tag = "items",
+    params(IdPayload, ("If-Match" = String, Header, description = "The `ETag` of the version being changed, or `*` for any")),
+    request_body(
+        content = ItemV1,
+        description = "A JSON Merge Patch of the item, with only the fields to change. \
+            `application/merge-patch+json` is read as version 1"
+    ),
+    responses(
+        (status = 200, description = "The updated item", body = ItemV1, headers(("ETag" = String, description = "Its version"))),
+        (status = 400, description = "Stock can't be changed here, or the patch isn't an item", body = Problem),
+        (status = 404, description = "No such item", body = Problem),
+        (status = 412, description = "The item has changed since the version in `If-Match`", body = Problem),
+        (status = 422, description = "Fields break the rules", body = Problem),
+        (status = 428, description = "No `If-Match`", body = Problem),
+    )
+)]
+#[patch("/{id}")]
+pub async fn patch_item(
+    pool: web::Data<Pool>,
+    version: ApiVersion,
+    if_match: IfMatch,
+    path: web::Path<IdPayload>,
+    data: Patch,
+) -> impl Responder {
+    versioned(
+        tagged(pool, IfNoneMatch::default(), move |repo| {
+            service::patch_item(repo, path.id, &if_match, |item| data.apply(item))
+                .map(|item| (version.etag(item.version), version.item(item)))
+                .map_err(|e| data.version.error(e))
+        })
+        .await,
     )
-    .await
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/item/{id}",
+    tag = "items",
+    params(IdPayload, ("If-Match" = String, Header, description = "The `ETag` of the version being changed, or `*` for any")),
+    responses(
+        (status = 200, description = "The deleted item, now in the trash", body = ItemV1),
+        (status = 404, description = "No such item", body = Problem),
+        (status = 412, description = "The item has changed since the version in `If-Match`", body = Problem),
+        (status = 428, description = "No `If-Match`", body = Problem),
+    )
+)]
 #[delete("/{id}")]
-pub async fn delete_item(pool: web::Data<DbPool>, path: web::Path<IdPayload>) -> impl Responder {
-    request(
-        pool,
-        serde_json::to_string_pretty,
-        move |conn| service::delete_item(conn, path.id),
-        StatusCode::OK,
+pub async fn delete_item(
+    pool: web::Data<Pool>,
+    actor: Actor,
+    version: ApiVersion,
+    if_match: IfMatch,
+    path: web::Path<IdPayload>,
+) -> impl Responder {
+    versioned(
+        request(
+            pool,
+            serde_json::to_string_pretty,
+            move |repo| {
+                service::delete_item(repo, &actor.0, path.id, &if_match)
+                    .map(|item| version.item(item))
+            },
+            StatusCode::OK,
+        )
+        .await,
     )
-    .await
 }
 
+/// Take an item out of the trash
+///
+/// It's put back in the warehouse it was deleted from, unless that warehouse has been deleted too
+#[utoipa::path(
+    post,
+    path = "/api/item/{id}/restore",
+    tag = "trash",
+    params(IdPayload),
+    responses(
+        (status = 200, description = "The restored item", body = ItemV1, headers(("ETag" = String, description = "Its version"))),
+        (status = 400, description = "The item isn't in the trash", body = Problem),
+        (status = 404, description = "No such item", body = Problem),
+    )
+)]
+#[post("/{id}/restore")]
+pub async fn restore_item(
+    pool: web::Data<Pool>,
+    actor: Actor,
+    version: ApiVersion,
+    path: web::Path<IdPayload>,
+) -> impl Responder {
+    versioned(
+        tagged(pool, IfNoneMatch::default(), move |repo| {
+            service::restore_item(repo, &actor.0, path.id)
+                .map(|item| (version.etag(item.version), version.item(item)))
+        })
+        .await,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/item/csv",
+    tag = "items",
+    params(ItemParams, CsvPayload),
+    responses(
+        (status = 200, description = "Items as csv", body = String, content_type = "text/csv"),
+        (status = 422, description = "Bad limit, sort, filter, or column", body = Problem),
+    )
+)]
 #[get("/csv")]
-pub async fn item_csv(pool: web::Data<DbPool>, query: web::Query<LimitPayload>) -> impl Responder {
-    request(
+pub async fn item_csv(
+    pool: web::Data<Pool>,
+    http: HttpRequest,
+    query: web::Query<ItemParams>,
+    csv: web::Query<CsvPayload>,
+) -> impl Responder {
+    export(
         pool,
-        format_item_csv,
-        move |conn| service::get_items(conn, query.limit()),
-        StatusCode::OK,
+        http,
+        "items.csv",
+        CsvFormat::new::<InventoryItem>(&csv),
+        query.into_inner(),
+        |repo, params| service::list_items(repo, params),
     )
     .await
 }
 
-#[get("/{id}/items")]
-pub async fn warehouse_get_items(
-    pool: web::Data<DbPool>,
+#[utoipa::path(
+    get,
+    path = "/api/item/{id}/history",
+    tag = "items",
+    params(IdPayload, LimitPayload),
+    responses((status = 200, description = "The item's movements, oldest first", body = [Movement]))
+)]
+#[get("/{id}/history")]
+pub async fn item_history(
+    pool: web::Data<Pool>,
     path: web::Path<IdPayload>,
     query: web::Query<LimitPayload>,
 ) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::warehouse_get_items(conn, path.id, query.limit()),
+        move |repo| service::item_history(repo, path.id, query.limit()),
         StatusCode::OK,
     )
     .await
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/warehouse/{id}/items",
+    tag = "warehouses",
+    params(IdPayload, ItemParams),
+    responses(
+        (status = 200, description = "A page of the warehouse's items", body = [ItemV1]),
+        (status = 404, description = "No such warehouse", body = Problem),
+        (status = 422, description = "Bad limit, sort, or filter", body = Problem),
+    )
+)]
+#[get("/{id}/items")]
+pub async fn warehouse_get_items(
+    pool: web::Data<Pool>,
+    http: HttpRequest,
+    version: ApiVersion,
+    path: web::Path<IdPayload>,
+    query: web::Query<ItemParams>,
+) -> impl Responder {
+    versioned(
+        page(pool, http, serde_json::to_string_pretty, move |repo| {
+            service::warehouse_get_items(repo, path.id, &query)
+                .map(|page| page.map(|items| version.items(items)))
+        })
+        .await,
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/warehouse/{id}/add",
+    tag = "warehouses",
+    params(IdPayload, StockPayload),
+    responses(
+        (status = 200, description = "The warehouse", body = Warehouse),
+        (status = 400, description = "The item is in another warehouse, or the quantity is bad", body = Problem),
+        (status = 404, description = "No such item or warehouse", body = Problem),
+    )
+)]
 #[post("/{id}/add")]
 pub async fn warehouse_add_item(
-    pool: web::Data<DbPool>,
+    pool: web::Data<Pool>,
+    actor: Actor,
     path: web::Path<IdPayload>,
-    query: web::Query<IdPayload>,
+    query: web::Query<StockPayload>,
 ) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::warehouse_add_item(conn, path.id, query.id),
+        move |repo| {
+            service::warehouse_add_item(repo, &actor.0, path.id, query.id, query.quantity())
+        },
         StatusCode::OK,
     )
     .await
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/warehouse/{id}/remove",
+    tag = "warehouses",
+    params(IdPayload, StockPayload),
+    responses(
+        (status = 200, description = "The warehouse", body = Warehouse),
+        (status = 400, description = "The warehouse doesn't have enough of the item", body = Problem),
+        (status = 404, description = "No such item or warehouse", body = Problem),
+    )
+)]
 #[post("/{id}/remove")]
 pub async fn warehouse_remove_item(
-    pool: web::Data<DbPool>,
+    pool: web::Data<Pool>,
+    actor: Actor,
+    path: web::Path<IdPayload>,
+    query: web::Query<StockPayload>,
+) -> impl Responder {
+    request(
+        pool,
+        serde_json::to_string_pretty,
+        move |repo| {
+            service::warehouse_remove_item(repo, &actor.0, path.id, query.id, query.quantity())
+        },
+        StatusCode::OK,
+    )
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/warehouse/{id}/transfer",
+    tag = "warehouses",
+    params(IdPayload),
+    request_body = TransferPayload,
+    responses(
+        (status = 200, description = "Both warehouses", body = Transfer),
+        (status = 400, description = "An item can't be moved, so none were", body = Problem),
+        (status = 404, description = "No such item or warehouse", body = Problem),
+    )
+)]
+#[post("/{id}/transfer")]
+pub async fn warehouse_transfer(
+    pool: web::Data<Pool>,
+    actor: Actor,
     path: web::Path<IdPayload>,
-    query: web::Query<IdPayload>,
+    data: web::Json<TransferPayload>,
 ) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::warehouse_remove_item(conn, path.id, query.id),
+        move |repo| service::transfer_items(repo, &actor.0, path.id, data.to, &data.items),
         StatusCode::OK,
     )
     .await
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/warehouse",
+    tag = "warehouses",
+    request_body = NewWarehouse,
+    responses(
+        (status = 201, description = "The created warehouse", body = Warehouse),
+        (status = 400, description = "An item can't be added", body = Problem),
+        (status = 422, description = "Fields break the rules", body = Problem),
+    )
+)]
 #[post("")]
 pub async fn create_warehouse(
-    pool: web::Data<DbPool>,
-    data: web::Json<Warehouse>,
+    pool: web::Data<Pool>,
+    actor: Actor,
+    data: web::Json<NewWarehouse>,
+) -> impl Responder {
+    create(pool, warehouse_location, move |repo| {
+        service::create_warehouse(repo, &actor.0, &data)
+    })
+    .await
+}
+
+/// Create every warehouse of a csv file, or none of them
+// Documented with the json importer, it shares the path and method
+#[post("/import", guard = "csv_body")]
+pub async fn import_warehouses_csv(
+    pool: web::Data<Pool>,
+    actor: Actor,
+    query: web::Query<ImportPayload>,
+    csv: web::Query<CsvPayload>,
+    body: web::Bytes,
 ) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::create_warehouse(conn, &data),
-        StatusCode::CREATED,
+        move |repo| {
+            let rows = CsvFormat::new::<Warehouse>(&csv)?.read(&body)?;
+            service::import_warehouses_csv(repo, &actor.0, rows, query.dry_run)
+        },
+        StatusCode::OK,
+    )
+    .await
+}
+
+/// Create a warehouse with an id picked by the client
+///
+/// A `text/csv` body in the export's layout imports every row, or none of them,
+/// and responds with an `ImportReport`
+#[utoipa::path(
+    post,
+    path = "/api/warehouse/import",
+    tag = "warehouses",
+    params(ImportPayload, CsvPayload),
+    request_body = Warehouse,
+    responses(
+        (status = 201, description = "The created warehouse", body = Warehouse),
+        (status = 200, description = "Every csv row was imported", body = ImportReport),
+        (status = 400, description = "The id is taken", body = Problem),
+        (status = 422, description = "Fields or csv rows break the rules", body = Problem),
     )
+)]
+#[post("/import")]
+pub async fn import_warehouse(
+    pool: web::Data<Pool>,
+    actor: Actor,
+    data: web::Json<Warehouse>,
+) -> impl Responder {
+    create(pool, warehouse_location, move |repo| {
+        service::import_warehouse(repo, &actor.0, &data)
+    })
     .await
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/warehouse",
+    tag = "warehouses",
+    params(WarehouseParams),
+    responses(
+        (status = 200, description = "A page of warehouses, `Link` points at the next one", body = [Warehouse]),
+        (status = 422, description = "Bad limit or sort", body = Problem),
+    )
+)]
 #[get("")]
 pub async fn get_warehouses(
-    pool: web::Data<DbPool>,
-    query: web::Query<LimitPayload>,
+    pool: web::Data<Pool>,
+    http: HttpRequest,
+    query: web::Query<WarehouseParams>,
+) -> impl Responder {
+    page(pool, http, serde_json::to_string_pretty, move |repo| {
+        service::list_warehouses(repo, &query)
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/warehouse/{id}",
+    tag = "warehouses",
+    params(IdPayload, ("If-None-Match" = Option<String>, Header, description = "The `ETag`s of the versions the client has")),
+    responses(
+        (status = 200, description = "The warehouse", body = Warehouse, headers(("ETag" = String, description = "Its version"))),
+        (status = 304, description = "The client's copy, named in `If-None-Match`, is current", headers(("ETag" = String, description = "Its version"))),
+        (status = 404, description = "No such warehouse", body = Problem),
+    )
+)]
+#[get("/{id}")]
+pub async fn get_warehouse(
+    pool: web::Data<Pool>,
+    cached: IfNoneMatch,
+    path: web::Path<IdPayload>,
+) -> impl Responder {
+    tagged(pool, cached, move |repo| {
+        service::get_warehouse(repo, path.id).map(|whouse| (etag(whouse.version), whouse))
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/warehouse/csv",
+    tag = "warehouses",
+    params(WarehouseParams, CsvPayload),
+    responses(
+        (status = 200, description = "Warehouses as csv", body = String, content_type = "text/csv"),
+        (status = 422, description = "Bad limit, sort, or column", body = Problem),
+    )
+)]
+#[get("/csv")]
+pub async fn warehouse_csv(
+    pool: web::Data<Pool>,
+    http: HttpRequest,
+    query: web::Query<WarehouseParams>,
+    csv: web::Query<CsvPayload>,
+) -> impl Responder {
+    export(
+        pool,
+        http,
+        "warehouses.csv",
+        CsvFormat::new::<Warehouse>(&csv),
+        query.into_inner(),
+        |repo, params| service::list_warehouses(repo, params),
+    )
+    .await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/warehouse/{id}",
+    tag = "warehouses",
+    params(IdPayload, ("If-Match" = String, Header, description = "The `ETag` of the version being changed, or `*` for any")),
+    responses(
+        (status = 200, description = "The deleted warehouse, now in the trash, with the items it had", body = Warehouse),
+        (status = 404, description = "No such warehouse", body = Problem),
+        (status = 412, description = "The warehouse has changed since the version in `If-Match`", body = Problem),
+        (status = 428, description = "No `If-Match`", body = Problem),
+    )
+)]
+#[delete("/{id}")]
+pub async fn delete_warehouse(
+    pool: web::Data<Pool>,
+    actor: Actor,
+    if_match: IfMatch,
+    path: web::Path<IdPayload>,
 ) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::get_warehouses(conn, query.limit()),
+        move |repo| service::delete_warehouse(repo, &actor.0, path.id, &if_match),
         StatusCode::OK,
     )
     .await
 }
 
-#[get("/{id}")]
-pub async fn get_warehouse(pool: web::Data<DbPool>, path: web::Path<IdPayload>) -> impl Responder {
+/// Take a warehouse out of the trash
+///
+/// The items it held come back with it, unless they've been deleted or stocked elsewhere since
+#[utoipa::path(
+    post,
+    path = "/api/warehouse/{id}/restore",
+    tag = "trash",
+    params(IdPayload),
+    responses(
+        (status = 200, description = "The restored warehouse", body = Warehouse, headers(("ETag" = String, description = "Its version"))),
+        (status = 400, description = "The warehouse isn't in the trash", body = Problem),
+        (status = 404, description = "No such warehouse", body = Problem),
+    )
+)]
+#[post("/{id}/restore")]
+pub async fn restore_warehouse(
+    pool: web::Data<Pool>,
+    actor: Actor,
+    path: web::Path<IdPayload>,
+) -> impl Responder {
+    tagged(pool, IfNoneMatch::default(), move |repo| {
+        service::restore_warehouse(repo, &actor.0, path.id)
+            .map(|whouse| (etag(whouse.version), whouse))
+    })
+    .await
+}
+
+/// What's been deleted and can still be restored, most recently deleted first
+#[utoipa::path(
+    get,
+    path = "/api/trash",
+    tag = "trash",
+    params(LimitPayload),
+    responses((status = 200, description = "Up to `limit` items and `limit` warehouses", body = Trash))
+)]
+#[get("/trash")]
+pub async fn get_trash(
+    pool: web::Data<Pool>,
+    version: ApiVersion,
+    query: web::Query<LimitPayload>,
+) -> impl Responder {
+    versioned(
+        request(
+            pool,
+            serde_json::to_string_pretty,
+            move |repo| {
+                service::get_trash(repo, query.limit()).map(|trash| Trash {
+                    items: version.items(trash.items),
+                    warehouses: trash.warehouses,
+                })
+            },
+            StatusCode::OK,
+        )
+        .await,
+    )
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/warehouse",
+    tag = "warehouses",
+    request_body = Warehouse,
+    responses((status = 501, description = "Not supported, use add, remove, and transfer", body = Problem))
+)]
+#[put("")]
+pub async fn update_warehouse(pool: web::Data<Pool>, data: web::Json<Warehouse>) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::get_warehouse(conn, path.id),
+        move |repo| service::update_warehouse(repo, &data),
         StatusCode::OK,
     )
     .await
 }
 
-#[get("/csv")]
-pub async fn warehouse_csv(
-    pool: web::Data<DbPool>,
+#[utoipa::path(
+    get,
+    path = "/api/warehouse/{id}/movements",
+    tag = "warehouses",
+    params(IdPayload, LimitPayload),
+    responses((status = 200, description = "Movements into or out of the warehouse, oldest first", body = [Movement]))
+)]
+#[get("/{id}/movements")]
+pub async fn warehouse_movements(
+    pool: web::Data<Pool>,
+    path: web::Path<IdPayload>,
     query: web::Query<LimitPayload>,
 ) -> impl Responder {
     request(
         pool,
-        format_warehouse_csv,
-        move |conn| service::get_warehouses(conn, query.limit()),
+        serde_json::to_string_pretty,
+        move |repo| service::warehouse_movements(repo, path.id, query.limit()),
         StatusCode::OK,
     )
     .await
 }
 
-#[delete("/{id}")]
-pub async fn delete_warehouse(
-    pool: web::Data<DbPool>,
-    path: web::Path<IdPayload>,
+/// Run several changes at once, in order, in one transaction
+///
+/// Each operation stands in for a request to another endpoint, and needs the role that request
+/// would. Items are read and written in the versions named by `Content-Type` and `Accept`.
+/// The first operation to fail undoes the whole batch, unless `continue_on_error` is set
+#[utoipa::path(
+    post,
+    path = "/api/batch",
+    tag = "batches",
+    params(BatchPayload),
+    request_body = [Operation],
+    responses(
+        (status = 200, description = "How each operation went, in order", body = [OperationResult]),
+        (status = 422, description = "An operation failed, so none were kept", body = Problem),
+    )
+)]
+#[post("/batch")]
+pub async fn run_batch(
+    pool: web::Data<Pool>,
+    principal: Principal,
+    version: ApiVersion,
+    query: web::Query<BatchPayload>,
+    batch: Body<Batch>,
 ) -> impl Responder {
+    // The first operation the key's role isn't enough for
+    let forbidden = batch.0 .0.iter().find_map(|op| {
+        let (method, path) = op.endpoint();
+        auth::required_role(&method, &path).filter(|required| principal.role < *required)
+    });
+
+    versioned(
+        request(
+            pool,
+            serde_json::to_string_pretty,
+            move |repo| {
+                if let Some(required) = forbidden {
+                    return Err(Error::Forbidden {
+                        role: principal.role,
+                        required,
+                    });
+                }
+
+                let sent = batch.version();
+                let results =
+                    service::run_batch(repo, &principal.name, &batch, query.continue_on_error)
+                        .map_err(|e| sent.error(e))?;
+                Ok(results
+                    .into_iter()
+                    .map(|result| OperationResult::new(version, result.map_err(|e| sent.error(e))))
+                    .collect::<Vec<_>>())
+            },
+            StatusCode::OK,
+        )
+        .await,
+    )
+}
+
+fn api_key_location(issued: &models::IssuedKey) -> String {
+    format!("/api/admin/keys/{}", issued.api_key.id)
+}
+
+/// Issue an API key, the response is the only time the key is shown
+#[utoipa::path(
+    post,
+    path = "/api/admin/keys",
+    tag = "api keys",
+    request_body = NewApiKey,
+    responses(
+        (status = 201, description = "The issued key, the only time `key` is shown", body = IssuedKey),
+        (status = 422, description = "Fields break the rules", body = Problem),
+    )
+)]
+#[post("")]
+pub async fn issue_api_key(pool: web::Data<Pool>, data: web::Json<NewApiKey>) -> impl Responder {
+    create(pool, api_key_location, move |repo| {
+        service::issue_api_key(repo, &data)
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/keys",
+    tag = "api keys",
+    responses((status = 200, description = "Every key, revoked ones included", body = [ApiKey]))
+)]
+#[get("")]
+pub async fn get_api_keys(pool: web::Data<Pool>) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::delete_warehouse(conn, path.id),
+        |repo| service::get_api_keys(repo),
         StatusCode::OK,
     )
     .await
 }
 
-#[put("")]
-pub async fn update_warehouse(
-    pool: web::Data<DbPool>,
-    data: web::Json<Warehouse>,
-) -> impl Responder {
+/// Revoke an API key, it's kept for the record but no longer accepted
+#[utoipa::path(
+    delete,
+    path = "/api/admin/keys/{id}",
+    tag = "api keys",
+    params(IdPayload),
+    responses(
+        (status = 200, description = "The revoked key", body = ApiKey),
+        (status = 404, description = "No such key", body = Problem),
+    )
+)]
+#[delete("/{id}")]
+pub async fn revoke_api_key(pool: web::Data<Pool>, path: web::Path<IdPayload>) -> impl Responder {
     request(
         pool,
         serde_json::to_string_pretty,
-        move |conn| service::update_warehouse(conn, &data),
+        move |repo| service::revoke_api_key(repo, path.id),
         StatusCode::OK,
     )
     .await
 }
+
+/// Check every item's stock against its movements, nothing is changed
+///
+/// To repair what's found, run `warehouser fsck --repair <policy>`
+#[utoipa::path(
+    get,
+    path = "/api/admin/consistency",
+    tag = "consistency",
+    responses((status = 200, description = "Every item the stock and the movements disagree about", body = ConsistencyReport))
+)]
+#[get("/consistency")]
+pub async fn check_consistency(pool: web::Data<Pool>) -> impl Responder {
+    request(
+        pool,
+        serde_json::to_string_pretty,
+        |repo| service::check_consistency(repo),
+        StatusCode::OK,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{rt::System, test, App};
+    use warehouser_core::{
+        memory::MemoryStore,
+        models::{NewApiKey, Role},
+        versions::V2_MEDIA_TYPE,
+    };
+
+    #[test]
+    fn invalid_fields_are_named_as_the_version_they_were_sent_in() {
+        System::new("test").block_on(async {
+            let pool = Pool::Memory(MemoryStore::new());
+            let issued = {
+                let conn = pool.get(None).unwrap();
+                let key = NewApiKey {
+                    name: "operator".to_string(),
+                    role: Role::Operator,
+                };
+                service::issue_api_key(conn.repo(), &key).unwrap()
+            };
+
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(auth::Auth::new(pool.clone()))
+                    .app_data(web::Data::new(pool))
+                    .service(web::scope("/api/item").service(create_item)),
+            )
+            .await;
+
+            let v1 = serde_json::json!({
+                "warehouse": null,
+                "weight": -1,
+                "value": -5,
+                "transport": "Land",
+                "dimensions": {"width": 0, "height": 1, "depth": 1},
+            });
+            let v2 = serde_json::json!({
+                "warehouse": null,
+                "weight_g": -1,
+                "value": {"amount": "-5", "currency": "USD"},
+                "transport": "Land",
+                "dimensions_mm": {"width": 0, "height": 1, "depth": 1},
+            });
+
+            for (content_type, body, fields) in [
+                (
+                    "application/json",
+                    v1,
+                    ["dimensions.width", "value", "weight"],
+                ),
+                (
+                    V2_MEDIA_TYPE,
+                    v2,
+                    ["dimensions_mm.width", "value.amount", "weight_g"],
+                ),
+            ] {
+                let req = test::TestRequest::post()
+                    .uri("/api/item")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", issued.key))
+                    .header(header::CONTENT_TYPE, content_type)
+                    .set_payload(body.to_string())
+                    .to_request();
+                let resp = test::call_service(&mut app, req).await;
+                assert_eq!(resp.status(), 422, "{content_type}");
+
+                let problem: serde_json::Value = test::read_body_json(resp).await;
+                let named: Vec<_> = problem["errors"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|error| error["field"].as_str().unwrap())
+                    .collect();
+                assert_eq!(named, fields, "{content_type}");
+            }
+        });
+    }
+}
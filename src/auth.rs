@@ -0,0 +1,261 @@
+/// Authentication and authorization
+/// Every request must carry an API key, and the key's role decides what it may do
+// Keys are made and hashed by `keys`, in the core crate.
+// The rules live in `required_role`, in one place, rather than on each handler
+use std::{
+    cell::RefCell,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    dev::{Payload, ResourceDef, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, HeaderMap, Method},
+    web, FromRequest, HttpMessage, HttpRequest,
+};
+
+use crate::{
+    api,
+    models::{ApiKey, Error, Role},
+    service,
+};
+use warehouser_core::repository::Pool;
+
+/// Header a key can be sent in, for clients that can't set `Authorization`
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Probed by the orchestrator and scraped by Prometheus, which don't have keys,
+/// and the API's documentation, so it can be read before asking for one
+const PUBLIC_PATHS: &[&str] = &["/healthz", "/readyz", "/metrics", "/api/openapi.json"];
+
+/// Who made a request, as found by `Auth`
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub key_id: i32,
+    pub name: String,
+    pub role: Role,
+}
+
+impl From<ApiKey> for Principal {
+    fn from(key: ApiKey) -> Self {
+        Principal {
+            key_id: key.id,
+            name: key.name,
+            role: key.role,
+        }
+    }
+}
+
+/// Handlers that need to know who is calling take a `Principal`
+impl FromRequest for Principal {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let principal = req.extensions().get::<Principal>().cloned();
+        ready(principal.ok_or_else(|| Error::Unauthenticated.into()))
+    }
+}
+
+/// The least role allowed to make a request, `None` when it needs no key at all
+pub fn required_role(method: &Method, path: &str) -> Option<Role> {
+    if PUBLIC_PATHS.contains(&path) || ResourceDef::prefix("/api/docs").is_match(path) {
+        return None;
+    }
+
+    let admin_only = ResourceDef::prefix("/api/admin").is_match(path)
+        // Deleting a warehouse puts all of its stock back in no warehouse,
+        // and restoring one takes back whatever of it is still free
+        || (*method == Method::DELETE && ResourceDef::new("/api/warehouse/{id}").is_match(path))
+        || (*method == Method::POST
+            && ResourceDef::new("/api/warehouse/{id}/restore").is_match(path));
+
+    let role = if admin_only {
+        Role::Admin
+    } else if matches!(*method, Method::GET | Method::HEAD) {
+        Role::ReadOnly
+    } else {
+        Role::Operator
+    };
+    Some(role)
+}
+
+// `Authorization: Bearer <key>`, or `X-Api-Key: <key>`
+fn credentials(headers: &HeaderMap) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, key)| key);
+
+    let key = bearer.or_else(|| {
+        headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+    })?;
+
+    Some(key.trim().to_string())
+}
+
+/// Middleware that rejects requests without a valid key, or with a key whose role isn't enough
+///
+/// The key's `Principal` is put in the request's extensions for the handlers
+pub struct Auth {
+    pool: Pool,
+}
+
+impl Auth {
+    pub fn new(pool: Pool) -> Self {
+        Auth { pool }
+    }
+}
+
+impl<S> Transform<S> for Auth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>
+        + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = AuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            pool: self.pool.clone(),
+        }))
+    }
+}
+
+pub struct AuthMiddleware<S> {
+    // Shared with the future of each request, which calls it once the key is checked
+    service: Rc<RefCell<S>>,
+    pool: Pool,
+}
+
+impl<S> Service for AuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>
+        + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let pool = self.pool.clone();
+
+        Box::pin(async move {
+            // The path as the router sees it, with its percent-encoding undone,
+            // so `/api/%61dmin` needs the same role as the `/api/admin` it's routed to
+            let required = match required_role(req.method(), req.match_info().path()) {
+                Some(required) => required,
+                None => {
+                    let fut = service.borrow_mut().call(req);
+                    return fut.await;
+                }
+            };
+
+            let principal = match authenticate(pool, credentials(req.headers())).await {
+                Ok(principal) => principal,
+                // Answered here rather than as an `Err`, so the logger still sees the response
+                Err(e) => return Ok(req.error_response(e)),
+            };
+
+            if principal.role < required {
+                let e = Error::Forbidden {
+                    role: principal.role,
+                    required,
+                };
+                return Ok(req.error_response(e));
+            }
+
+            req.extensions_mut().insert(principal);
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
+
+async fn authenticate(pool: Pool, key: Option<String>) -> Result<Principal, Error> {
+    let key = key.ok_or(Error::Unauthenticated)?;
+
+    let found = api::run(web::Data::new(pool), move |repo| {
+        service::authenticate(repo, &key)
+    })
+    .await?;
+
+    Ok(found.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{rt::System, test, App, HttpResponse};
+    use warehouser_core::{memory::MemoryStore, models::NewApiKey};
+
+    #[test]
+    fn roles_follow_the_method_and_path() {
+        let role = |method: Method, path: &str| required_role(&method, path);
+
+        assert_eq!(role(Method::GET, "/healthz"), None);
+        assert_eq!(role(Method::GET, "/api/item/1"), Some(Role::ReadOnly));
+        assert_eq!(role(Method::POST, "/api/item"), Some(Role::Operator));
+        assert_eq!(role(Method::GET, "/api/admin/keys"), Some(Role::Admin));
+        assert_eq!(role(Method::DELETE, "/api/warehouse/1"), Some(Role::Admin));
+        assert_eq!(
+            role(Method::POST, "/api/warehouse/1/restore"),
+            Some(Role::Admin)
+        );
+    }
+
+    #[test]
+    fn encoded_admin_paths_still_need_an_admin() {
+        System::new("test").block_on(async {
+            let store = MemoryStore::new();
+            let pool = Pool::Memory(store.clone());
+            let issued = {
+                let conn = pool.get(None).unwrap();
+                let key = NewApiKey {
+                    name: "operator".to_string(),
+                    role: Role::Operator,
+                };
+                service::issue_api_key(conn.repo(), &key).unwrap()
+            };
+
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(Auth::new(pool))
+                    .default_service(web::to(HttpResponse::Ok)),
+            )
+            .await;
+
+            for (method, path) in [
+                (Method::GET, "/api/%61dmin/keys"),
+                (Method::POST, "/api/%61dmin/keys"),
+                (Method::DELETE, "/api/w%61rehouse/1"),
+                (Method::POST, "/api/w%61rehouse/1/restore"),
+            ] {
+                let req = test::TestRequest::with_uri(path)
+                    .method(method)
+                    .header(header::AUTHORIZATION, format!("Bearer {}", issued.key))
+                    .to_request();
+                let resp = test::call_service(&mut app, req).await;
+                assert_eq!(resp.status(), 403, "{path}");
+            }
+        });
+    }
+}
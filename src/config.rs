@@ -0,0 +1,609 @@
+/// Runtime configuration
+/// Settings come from a TOML file, then environment variables, then command line flags,
+/// each overriding the one before it. Anything left unset keeps its default
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgEnum, Parser, Subcommand};
+use serde::Deserialize;
+use validator::{Validate, ValidationError};
+
+use crate::{
+    models::{Error, FieldError},
+    pagination::{DEFAULT_LIMIT, MAX_LIMIT},
+};
+
+/// Read when no config file is named, it's fine for it not to exist
+const DEFAULT_CONFIG_FILE: &str = "warehouser.toml";
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub backend: Backend,
+    pub database_url: Option<String>, // Usually given as DATABASE_URL, a file's path for SQLite
+    pub auto_migrate: bool, // Run pending migrations at startup, rather than refusing to start
+    #[validate(custom = "validate_binds")]
+    pub bind: Vec<String>, // Addresses to listen on, like `127.0.0.1:8087`
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub workers: Option<usize>, // One per CPU when missing
+    pub log: String,        // An env_logger filter, like `actix_web=info`
+    #[validate(range(
+        min = 1,
+        max = 2592000,
+        message = "must be between 1 and 2592000 (30 days)"
+    ))]
+    pub idempotency_window_secs: u64, // How long the response to an `Idempotency-Key` is kept
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub idempotency_abandoned_secs: u64, // How long a change can take before a retry makes it again
+    #[validate(range(min = 1, max = 3650, message = "must be between 1 and 3650 (10 years)"))]
+    pub trash_retention_days: u64, // How long deleted items and warehouses can be restored
+    #[validate]
+    pub pool: PoolConfig,
+    #[validate]
+    pub limits: LimitsConfig,
+}
+
+/// Where the data is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ArgEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Postgres,
+    Sqlite, // A single file, for edge sites and laptops, needs the `sqlite` feature
+    Memory, // Nothing is saved, for demos and trying out the API
+}
+
+/// The database connection pool
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub max_size: u32,
+    pub min_idle: Option<u32>, // Connections kept open while idle, `max_size` when missing
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub connection_timeout_secs: u64, // How long a request waits for a connection
+    pub idle_timeout_secs: Option<u64>, // Idle connections are closed after this, never when missing
+    pub max_lifetime_secs: Option<u64>, // Connections are replaced after this, never when missing
+}
+
+/// Page and request body sizes
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub default_page: i64, // Rows in a page when the client doesn't ask for a `limit`
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub max_page: i64, // The most rows a client can ask for
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub json_payload: usize, // Largest json body, in bytes
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub import_payload: usize, // Largest csv import, in bytes
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backend: Backend::Postgres,
+            database_url: None,
+            auto_migrate: false,
+            bind: vec!["127.0.0.1:8087".to_string()],
+            workers: None,
+            log: "actix_web=info".to_string(),
+            idempotency_window_secs: 24 * 60 * 60,
+            idempotency_abandoned_secs: 10 * 60,
+            trash_retention_days: 30,
+            pool: PoolConfig::default(),
+            limits: LimitsConfig::default(),
+        }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 1, // Mitigation for buggy behaviour with Postgresql 14
+            min_idle: None,
+            connection_timeout_secs: 30,
+            idle_timeout_secs: Some(10 * 60),
+            max_lifetime_secs: Some(30 * 60),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            default_page: DEFAULT_LIMIT,
+            max_page: MAX_LIMIT,
+            json_payload: 32 * 1024,
+            import_payload: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Command line flags, each can also be set with the environment variable next to it
+#[derive(Debug, Parser)]
+#[clap(version, about = "Inventory tracking for warehouses")]
+pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    /// TOML file to read settings from [default: warehouser.toml, if it exists]
+    #[clap(long, env = "WAREHOUSER_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Where to keep the data [default: postgres]
+    #[clap(long, env = "WAREHOUSER_BACKEND", arg_enum, global = true)]
+    pub backend: Option<Backend>,
+    #[clap(long, env = "DATABASE_URL", hide_env_values = true, global = true)]
+    pub database_url: Option<String>,
+    /// Run any pending migrations before starting, instead of refusing to start
+    #[clap(long, env = "WAREHOUSER_AUTO_MIGRATE")]
+    pub auto_migrate: bool,
+    /// Address to listen on, repeat the flag (or separate with commas) for more than one
+    #[clap(long, env = "WAREHOUSER_BIND", value_delimiter = ',')]
+    pub bind: Vec<String>,
+    /// Worker threads [default: one per CPU]
+    #[clap(long, env = "WAREHOUSER_WORKERS")]
+    pub workers: Option<usize>,
+    /// Log filter, like `actix_web=info,warehouser=debug`
+    #[clap(long, env = "RUST_LOG")]
+    pub log: Option<String>,
+    /// How long a retry with the same `Idempotency-Key` is sent the first response, in seconds
+    #[clap(long, env = "WAREHOUSER_IDEMPOTENCY_WINDOW_SECS")]
+    pub idempotency_window_secs: Option<u64>,
+    /// How long a change made with an `Idempotency-Key` can go without a response before
+    /// it's taken to be abandoned and a retry makes it again, in seconds
+    #[clap(long, env = "WAREHOUSER_IDEMPOTENCY_ABANDONED_SECS")]
+    pub idempotency_abandoned_secs: Option<u64>,
+    /// How long deleted items and warehouses stay in the trash before they're purged, in days
+    #[clap(long, env = "WAREHOUSER_TRASH_RETENTION_DAYS")]
+    pub trash_retention_days: Option<u64>,
+    #[clap(long, env = "WAREHOUSER_POOL_MAX_SIZE")]
+    pub pool_max_size: Option<u32>,
+    #[clap(long, env = "WAREHOUSER_POOL_MIN_IDLE")]
+    pub pool_min_idle: Option<u32>,
+    #[clap(long, env = "WAREHOUSER_POOL_CONNECTION_TIMEOUT_SECS")]
+    pub pool_connection_timeout_secs: Option<u64>,
+    #[clap(long, env = "WAREHOUSER_POOL_IDLE_TIMEOUT_SECS")]
+    pub pool_idle_timeout_secs: Option<u64>,
+    #[clap(long, env = "WAREHOUSER_POOL_MAX_LIFETIME_SECS")]
+    pub pool_max_lifetime_secs: Option<u64>,
+    #[clap(long, env = "WAREHOUSER_DEFAULT_PAGE_LIMIT")]
+    pub default_page_limit: Option<i64>,
+    #[clap(long, env = "WAREHOUSER_MAX_PAGE_LIMIT")]
+    pub max_page_limit: Option<i64>,
+    /// Largest json body, in bytes
+    #[clap(long, env = "WAREHOUSER_JSON_PAYLOAD_LIMIT")]
+    pub json_payload_limit: Option<usize>,
+    /// Largest csv import, in bytes
+    #[clap(long, env = "WAREHOUSER_IMPORT_PAYLOAD_LIMIT")]
+    pub import_payload_limit: Option<usize>,
+}
+
+/// Something to do instead of serving requests
+#[derive(Debug, Clone, Copy, Subcommand)]
+pub enum Command {
+    /// Manage the database's schema, with the migrations built into this binary
+    Migrate {
+        #[clap(subcommand)]
+        action: MigrateAction,
+    },
+    /// Check every item's stock against its movements, and list the items they disagree about
+    Fsck {
+        /// Fix what's found, believing the ledger of movements, or the stock
+        #[clap(long, arg_enum, value_name = "POLICY")]
+        repair: Option<Repair>,
+    },
+}
+
+/// Which side `fsck --repair` believes
+#[derive(Debug, Clone, Copy, ArgEnum)]
+pub enum Repair {
+    Ledger, // Change the stock to match the movements
+    Stock,  // Record movements that bring the ledger in line with the stock
+}
+
+#[derive(Debug, Clone, Copy, Subcommand)]
+pub enum MigrateAction {
+    /// Run every migration that hasn't been run yet
+    Up,
+    /// Undo the most recent migration
+    Down,
+    /// List the migrations, and whether each has been run
+    Status,
+}
+
+/// Why the configuration couldn't be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    Invalid(Error),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => {
+                write!(f, "Couldn't read {}: {error}", path.display())
+            }
+            ConfigError::Parse { path, error } => {
+                write!(f, "Couldn't parse {}: {error}", path.display())
+            }
+            ConfigError::Invalid(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration for this process, from its file, environment, and flags,
+    /// along with the subcommand it was started with, if any
+    ///
+    /// Bad flags exit the process with clap's usage message
+    pub fn load() -> Result<(Self, Option<Command>), ConfigError> {
+        let mut args = Args::parse();
+        let command = args.command.take();
+        Ok((Config::from_args(args)?, command))
+    }
+
+    pub fn from_args(args: Args) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Config::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::read(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+
+        config.apply(args);
+        config.check().map_err(ConfigError::Invalid)?;
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|error| ConfigError::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        toml::from_str(&text).map_err(|error| ConfigError::Parse {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    // Flags and environment variables win over the file
+    fn apply(&mut self, args: Args) {
+        fn set<T>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+
+        if !args.bind.is_empty() {
+            self.bind = args.bind;
+        }
+        set(&mut self.backend, args.backend);
+        set(&mut self.database_url, args.database_url.map(Some));
+        if args.auto_migrate {
+            self.auto_migrate = true;
+        }
+        set(&mut self.workers, args.workers.map(Some));
+        set(&mut self.log, args.log);
+        set(
+            &mut self.idempotency_window_secs,
+            args.idempotency_window_secs,
+        );
+        set(
+            &mut self.idempotency_abandoned_secs,
+            args.idempotency_abandoned_secs,
+        );
+        set(&mut self.trash_retention_days, args.trash_retention_days);
+        set(&mut self.pool.max_size, args.pool_max_size);
+        set(&mut self.pool.min_idle, args.pool_min_idle.map(Some));
+        set(
+            &mut self.pool.connection_timeout_secs,
+            args.pool_connection_timeout_secs,
+        );
+        set(
+            &mut self.pool.idle_timeout_secs,
+            args.pool_idle_timeout_secs.map(Some),
+        );
+        set(
+            &mut self.pool.max_lifetime_secs,
+            args.pool_max_lifetime_secs.map(Some),
+        );
+        set(&mut self.limits.default_page, args.default_page_limit);
+        set(&mut self.limits.max_page, args.max_page_limit);
+        set(&mut self.limits.json_payload, args.json_payload_limit);
+        set(&mut self.limits.import_payload, args.import_payload_limit);
+    }
+
+    // Every broken rule is reported at once, like a request body
+    fn check(&self) -> Result<(), Error> {
+        let mut errors = match self.validate() {
+            Ok(()) => Vec::new(),
+            Err(e) => match Error::from(e) {
+                Error::Validation { errors } => errors,
+                _ => Vec::new(),
+            },
+        };
+
+        let mut broken = |field: &str, message: &str| {
+            errors.push(FieldError {
+                field: field.to_string(),
+                code: "config".to_string(),
+                message: message.to_string(),
+            })
+        };
+
+        let no_url = self.database_url.as_deref().unwrap_or("").is_empty();
+        if self.backend != Backend::Memory && no_url {
+            broken(
+                "database_url",
+                "must be set for the postgres and sqlite backends, with DATABASE_URL or --database-url",
+            );
+        }
+        if self.backend == Backend::Sqlite && !cfg!(feature = "sqlite") {
+            broken(
+                "backend",
+                "this build has no sqlite support, rebuild with `--features sqlite`",
+            );
+        }
+        // The key would be forgotten first, and a retry would make the change again anyway
+        if self.idempotency_abandoned_secs > self.idempotency_window_secs {
+            broken(
+                "idempotency_abandoned_secs",
+                "must not be more than idempotency_window_secs",
+            );
+        }
+        if self.pool.min_idle > Some(self.pool.max_size) {
+            broken("pool.min_idle", "must not be more than pool.max_size");
+        }
+        if self.limits.default_page > self.limits.max_page {
+            broken(
+                "limits.default_page",
+                "must not be more than limits.max_page",
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation { errors })
+        }
+    }
+}
+
+// Every address needs a host and a port, actix resolves the host when it binds
+fn validate_binds(binds: &[String]) -> Result<(), ValidationError> {
+    let valid = |bind: &String| {
+        bind.rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+    };
+
+    let message = if binds.is_empty() {
+        "must list at least one address"
+    } else if !binds.iter().all(valid) {
+        "must be addresses with a port, like 127.0.0.1:8087"
+    } else {
+        return Ok(());
+    };
+
+    let mut err = ValidationError::new("address");
+    err.message = Some(message.into());
+    Err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    // The environment belongs to the whole process, so only one test can use it at a time
+    static ENV: Mutex<()> = Mutex::new(());
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Load a config from `toml`, the environment variables in `env`, and `flags`,
+    /// with every other variable the flags read unset
+    fn load(toml: &str, env: &[(&str, &str)], flags: &[&str]) -> Result<Config, ConfigError> {
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let path = std::env::temp_dir().join(format!(
+            "warehouser-config-{}-{}.toml",
+            std::process::id(),
+            FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, toml).unwrap();
+
+        let names: Vec<_> = Args::command()
+            .get_arguments()
+            .filter_map(|arg| arg.get_env().map(|name| name.to_os_string()))
+            .collect();
+        let saved: Vec<_> = names
+            .iter()
+            .map(|name| (name, std::env::var_os(name)))
+            .collect();
+        for name in &names {
+            std::env::remove_var(name);
+        }
+        for (name, value) in env {
+            std::env::set_var(name, value);
+        }
+
+        let args = Args::try_parse_from(
+            ["warehouser", "--config", path.to_str().unwrap()]
+                .into_iter()
+                .chain(flags.iter().copied()),
+        );
+
+        for (name, value) in saved {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+
+        let config = Config::from_args(args.unwrap());
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn invalid_fields(result: Result<Config, ConfigError>) -> Vec<String> {
+        match result {
+            Err(ConfigError::Invalid(Error::Validation { errors })) => {
+                errors.into_iter().map(|error| error.field).collect()
+            }
+            other => panic!("expected invalid settings, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn the_environment_overrides_the_file_and_flags_override_both() {
+        let toml = r#"
+            backend = "memory"
+            workers = 2
+            log = "warn"
+            bind = ["0.0.0.0:80"]
+
+            [pool]
+            max_size = 4
+            min_idle = 1
+
+            [limits]
+            max_page = 5000
+        "#;
+        let env = [
+            ("WAREHOUSER_WORKERS", "3"),
+            ("WAREHOUSER_POOL_MAX_SIZE", "6"),
+            ("WAREHOUSER_BIND", "127.0.0.1:81,127.0.0.1:82"),
+        ];
+        let config = load(toml, &env, &["--workers", "5"]).unwrap();
+
+        assert_eq!(config.workers, Some(5));
+        assert_eq!(config.pool.max_size, 6);
+        assert_eq!(config.bind, ["127.0.0.1:81", "127.0.0.1:82"]);
+        assert_eq!(config.log, "warn");
+        assert_eq!(config.pool.min_idle, Some(1));
+        assert_eq!(config.limits.max_page, 5000);
+
+        // Anything no one set keeps its default
+        assert_eq!(config.limits.default_page, DEFAULT_LIMIT);
+        assert_eq!(config.pool.connection_timeout_secs, 30);
+        assert!(!config.auto_migrate);
+    }
+
+    #[test]
+    fn an_empty_file_is_all_defaults() {
+        let config = load("", &[("DATABASE_URL", "postgres://localhost/db")], &[]).unwrap();
+
+        assert_eq!(config.backend, Backend::Postgres);
+        assert_eq!(
+            config.database_url.as_deref(),
+            Some("postgres://localhost/db")
+        );
+        assert_eq!(config.bind, ["127.0.0.1:8087"]);
+        assert_eq!(config.pool.max_size, 1);
+        assert_eq!(config.limits.max_page, MAX_LIMIT);
+    }
+
+    #[test]
+    fn every_invalid_pool_and_limit_setting_is_reported_at_once() {
+        let toml = r#"
+            backend = "memory"
+
+            [pool]
+            max_size = 0
+            connection_timeout_secs = 0
+
+            [limits]
+            default_page = 0
+            max_page = 0
+            json_payload = 0
+            import_payload = 0
+        "#;
+
+        assert_eq!(
+            invalid_fields(load(toml, &[], &[])),
+            [
+                "limits.default_page",
+                "limits.import_payload",
+                "limits.json_payload",
+                "limits.max_page",
+                "pool.connection_timeout_secs",
+                "pool.max_size",
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_that_only_conflict_with_each_other_are_reported() {
+        let toml = r#"
+            backend = "memory"
+
+            [pool]
+            max_size = 2
+            min_idle = 3
+
+            [limits]
+            default_page = 200
+            max_page = 100
+        "#;
+        let env = [("WAREHOUSER_IDEMPOTENCY_ABANDONED_SECS", "7200")];
+        assert_eq!(
+            invalid_fields(load(toml, &env, &["--idempotency-window-secs", "3600"])),
+            [
+                "idempotency_abandoned_secs",
+                "pool.min_idle",
+                "limits.default_page"
+            ]
+        );
+
+        // A flag can fix what the file got wrong
+        let flags = ["--pool-max-size", "3", "--max-page-limit", "200"];
+        assert!(load(toml, &[], &flags).is_ok());
+    }
+
+    #[test]
+    fn invalid_flags_are_checked_like_the_file() {
+        let env = [("WAREHOUSER_POOL_MAX_SIZE", "0")];
+        let flags = [
+            "--backend",
+            "memory",
+            "--workers",
+            "0",
+            "--bind",
+            "localhost",
+        ];
+
+        assert_eq!(
+            invalid_fields(load("", &env, &flags)),
+            ["bind", "pool.max_size", "workers"]
+        );
+    }
+
+    #[test]
+    fn the_database_url_is_needed_unless_nothing_is_saved() {
+        assert_eq!(invalid_fields(load("", &[], &[])), ["database_url"]);
+        assert!(load("", &[], &["--backend", "memory"]).is_ok());
+    }
+
+    #[test]
+    fn an_unknown_setting_in_the_file_is_turned_down() {
+        let toml = "backend = \"memory\"\n[pool]\nsize = 4\n";
+        assert!(matches!(
+            load(toml, &[], &[]),
+            Err(ConfigError::Parse { .. })
+        ));
+    }
+}
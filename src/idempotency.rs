@@ -0,0 +1,262 @@
+/// Idempotency keys
+/// Scanners on flaky networks retry, so a change sent with an `Idempotency-Key` is only made once.
+/// Retries with the same key are sent the response the first attempt got, until the key expires
+// Runs inside `Auth`, keys belong to the API key that sent them.
+// What's kept, and the rules for reusing a key, are in `service::begin_idempotent`
+use std::{
+    cell::RefCell,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use actix_web::{
+    dev::{Body, Payload, ResponseBody, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::{HeaderName, HeaderValue, Method, StatusCode},
+    web::{self, Bytes, BytesMut},
+    HttpMessage, HttpResponse,
+};
+use futures_util::StreamExt;
+
+use crate::{
+    api,
+    auth::Principal,
+    models::{Error, StoredResponse},
+    service,
+};
+use warehouser_core::repository::Pool;
+
+/// Header a client names its change with, a new key for each change and the same key for its retries
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Set on a response that was sent before, to a request with the same key
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// The methods that change things, the others are safe to repeat already
+pub fn is_change(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+/// Middleware that keeps the response to each change made with an `Idempotency-Key`,
+/// and sends it again, rather than making the change again, for retries
+pub struct Idempotency {
+    pool: Pool,
+    window: Duration,    // How long a key is remembered
+    abandoned: Duration, // How long a change can take before its retries make it again
+    body_limit: usize,   // Largest body it will hold on to, to tell retries from new requests
+}
+
+impl Idempotency {
+    pub fn new(pool: Pool, window: Duration, abandoned: Duration, body_limit: usize) -> Self {
+        Idempotency {
+            pool,
+            window,
+            abandoned,
+            body_limit,
+        }
+    }
+}
+
+impl<S> Transform<S> for Idempotency
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>
+        + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = IdempotencyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            pool: self.pool.clone(),
+            window: self.window,
+            abandoned: self.abandoned,
+            body_limit: self.body_limit,
+        }))
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    pool: Pool,
+    window: Duration,
+    abandoned: Duration,
+    body_limit: usize,
+}
+
+impl<S> Service for IdempotencyMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>
+        + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let pool = web::Data::new(self.pool.clone());
+        let (window, abandoned, body_limit) = (self.window, self.abandoned, self.body_limit);
+
+        Box::pin(async move {
+            let key = req
+                .headers()
+                .get(IDEMPOTENCY_KEY_HEADER)
+                .map(|value| value.to_str().unwrap_or_default().to_string());
+            let principal = req.extensions().get::<Principal>().cloned();
+
+            // Requests without a key, or that change nothing, are handled as usual
+            let (key, key_id) = match (key, principal) {
+                (Some(key), Some(principal)) if is_change(req.method()) => (key, principal.key_id),
+                _ => {
+                    let fut = service.borrow_mut().call(req);
+                    return fut.await;
+                }
+            };
+
+            // The body is part of what makes a retry the same request, it's put back for the handler
+            let body = match read_payload(req.take_payload(), body_limit).await {
+                Ok(body) => body,
+                Err(e) => return Ok(req.error_response(e)),
+            };
+            // A header sent more than once counts as its values joined, like a list would be
+            let headers = service::FINGERPRINT_HEADERS.map(|name| {
+                let values = req.headers().get_all(name);
+                let values: Vec<_> = values
+                    .map(|value| String::from_utf8_lossy(value.as_bytes()))
+                    .collect();
+                values.join(", ")
+            });
+            let headers = headers.each_ref().map(String::as_str);
+            let fingerprint = service::request_fingerprint(
+                req.method().as_str(),
+                &req.uri().to_string(),
+                &headers,
+                &body,
+            );
+            req.set_payload(payload(body));
+
+            let claimed = {
+                let key = key.clone();
+                api::run(pool.clone(), move |repo| {
+                    service::begin_idempotent(repo, key_id, &key, &fingerprint, window, abandoned)
+                })
+                .await
+            };
+            match claimed {
+                Ok(None) => {}
+                Ok(Some(stored)) => return Ok(req.into_response(replay(stored))),
+                Err(e) => return Ok(req.error_response(e)),
+            }
+
+            let forget = |pool: web::Data<Pool>, key: String| {
+                api::run(pool, move |repo| {
+                    service::forget_idempotent_request(repo, key_id, &key)
+                })
+            };
+
+            let fut = service.borrow_mut().call(req);
+            let mut res = match fut.await {
+                Ok(res) => res,
+                Err(e) => {
+                    // Nothing was sent, so a retry should be handled afresh
+                    let _ = forget(pool, key).await;
+                    return Err(e);
+                }
+            };
+
+            let body = match read_body(res.take_body()).await {
+                Ok(body) => body,
+                Err(e) => {
+                    let _ = forget(pool, key).await;
+                    return Err(e);
+                }
+            };
+
+            // A server error may have been a passing one, so it's worth retrying for real.
+            // The change has been made either way, if its response can't be kept
+            // a retry is told the key is in use, until it's taken to be abandoned
+            let _ = if res.status().is_server_error() {
+                forget(pool, key).await
+            } else {
+                let stored = StoredResponse {
+                    status: res.status().as_u16(),
+                    headers: res
+                        .headers()
+                        .iter()
+                        .filter_map(|(name, value)| {
+                            Some((name.to_string(), value.to_str().ok()?.to_string()))
+                        })
+                        .collect(),
+                    body: body.to_vec(),
+                };
+                api::run(pool, move |repo| {
+                    service::save_idempotent_response(repo, key_id, &key, &stored)
+                })
+                .await
+            };
+
+            Ok(res.map_body(|_, _| ResponseBody::Body(Body::from(body))))
+        })
+    }
+}
+
+async fn read_payload(mut payload: Payload, limit: usize) -> Result<Bytes, Error> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| Error::InvalidRequest {
+            detail: e.to_string(),
+        })?;
+        if body.len() + chunk.len() > limit {
+            return Err(Error::InvalidRequest {
+                detail: format!("The body is larger than the {limit} byte limit"),
+            });
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+// The body as read, for the handler to read again
+fn payload(body: Bytes) -> Payload {
+    let stream = futures_util::stream::once(ready(Ok::<_, PayloadError>(body)));
+    Payload::Stream(Box::pin(stream))
+}
+
+async fn read_body(mut body: ResponseBody<Body>) -> Result<Bytes, actix_web::Error> {
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        bytes.extend_from_slice(&chunk?);
+    }
+    Ok(bytes.freeze())
+}
+
+// A response as it was sent the first time
+fn replay(stored: StoredResponse) -> HttpResponse {
+    let status = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    let mut resp = HttpResponse::build(status);
+    for (name, value) in &stored.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            resp.header(name, value);
+        }
+    }
+    resp.header(REPLAYED_HEADER, "true").body(stored.body)
+}
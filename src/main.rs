@@ -3,62 +3,317 @@
 //! Copyright reserved
 //! For Shopify's Backend Challenge Summer 2022
 
-#[macro_use]
-extern crate diesel;
-extern crate serde;
-
 pub mod api;
-pub mod db;
-pub mod models;
-pub mod schema;
-pub mod service;
-pub mod util;
+pub mod auth;
+pub mod config;
+pub mod idempotency;
+pub mod metrics;
+pub mod openapi;
+
+// The models and business logic live in the core crate, shared with the client
+pub use warehouser_core::{
+    batch, conditional, db, migrations, models, pagination, service, util, versions, DbPool,
+};
 
-use actix_web::{error::InternalError, middleware::Logger, web, App, HttpResponse, HttpServer};
+use actix_web::{error::InternalError, middleware::Logger, web, App, HttpServer, ResponseError};
 
 use diesel::{pg::PgConnection, r2d2::ConnectionManager};
 use dotenv::dotenv;
-use r2d2::Pool;
+use std::time::Duration;
+#[cfg(feature = "sqlite")]
+use warehouser_core::sqlite;
+use warehouser_core::{memory::MemoryStore, repository::Pool};
 
 use api::*;
+use config::{Backend, Command, Config, MigrateAction, Repair};
+use pagination::Limits;
+
+/// Report input that actix couldn't extract in the same format as our own errors
+fn invalid_request(err: impl ResponseError + 'static) -> actix_web::Error {
+    let resp = models::Error::InvalidRequest {
+        detail: err.to_string(),
+    }
+    .error_response();
+    InternalError::from_response(err, resp).into()
+}
+
+// The pool settings are the same whichever database it connects to
+fn pool_builder<M: r2d2::ManageConnection>(config: &Config) -> r2d2::Builder<M> {
+    r2d2::Pool::builder()
+        .max_size(config.pool.max_size)
+        .min_idle(config.pool.min_idle)
+        .connection_timeout(Duration::from_secs(config.pool.connection_timeout_secs))
+        .idle_timeout(config.pool.idle_timeout_secs.map(Duration::from_secs))
+        .max_lifetime(config.pool.max_lifetime_secs.map(Duration::from_secs))
+}
+
+// `check` makes sure the url is set
+fn database_url(config: &Config) -> String {
+    config.database_url.clone().unwrap_or_default()
+}
+
+fn db_pool(config: &Config) -> DbPool {
+    let manager = ConnectionManager::<PgConnection>::new(database_url(config));
+    pool_builder(config)
+        .build(manager)
+        .expect("Couldn't create db pool")
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_pool(config: &Config) -> warehouser_core::SqlitePool {
+    let manager = ConnectionManager::new(database_url(config));
+    pool_builder(config)
+        .connection_customizer(Box::new(sqlite::Pragmas))
+        .build(manager)
+        .expect("Couldn't create db pool")
+}
+
+fn pool(config: &Config) -> Pool {
+    match config.backend {
+        Backend::Postgres => Pool::Postgres(db_pool(config)),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => Pool::Sqlite(sqlite_pool(config)),
+        // `check` turns it down when the feature is missing
+        #[cfg(not(feature = "sqlite"))]
+        Backend::Sqlite => unreachable!("built without sqlite support"),
+        Backend::Memory => Pool::Memory(MemoryStore::new()),
+    }
+}
+
+// `warehouser migrate`, runs instead of the server
+fn migrate(pool: &Pool, action: MigrateAction) -> Result<(), models::Error> {
+    let conn = pool
+        .get(None)
+        .map_err(|detail| models::Error::Database { detail })?;
+
+    match action {
+        MigrateAction::Up => {
+            migrations::run_pending(&conn, &mut std::io::stdout())?;
+            println!("The database is up to date");
+        }
+        MigrateAction::Down => match migrations::revert_latest(&conn, &mut std::io::stdout())? {
+            Some(_) => {}
+            None => println!("No migrations have been run, there's nothing to revert"),
+        },
+        MigrateAction::Status => {
+            for migration in migrations::status(&conn)? {
+                let mark = if migration.applied { "X" } else { " " };
+                println!("[{mark}] {}", migration.name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// `warehouser fsck`, runs instead of the server, returns whether anything is left inconsistent
+fn fsck(pool: &Pool, repair: Option<Repair>) -> Result<bool, models::Error> {
+    let conn = pool
+        .get(None)
+        .map_err(|detail| models::Error::Database { detail })?;
+    service::check_migrations(conn.repo())?;
+
+    let report = match repair {
+        None => service::check_consistency(conn.repo())?,
+        Some(repair) => {
+            let policy = match repair {
+                Repair::Ledger => models::RepairPolicy::Ledger,
+                Repair::Stock => models::RepairPolicy::Stock,
+            };
+            // Shows up as the actor of the movements it records
+            service::repair_consistency(conn.repo(), "warehouser fsck", policy)?
+        }
+    };
 
-type DbPool = Pool<ConnectionManager<PgConnection>>;
+    let held = |holding: Option<models::Holding>| match holding {
+        Some(models::Holding {
+            warehouse_id,
+            quantity,
+        }) => format!("{quantity} in warehouse {warehouse_id}"),
+        None => "none".to_string(),
+    };
+    for inconsistency in &report.inconsistencies {
+        println!(
+            "item {}: {:?}, stock has {}, ledger has {}",
+            inconsistency.item_id,
+            inconsistency.kind,
+            held(inconsistency.stock),
+            held(inconsistency.ledger),
+        );
+    }
+
+    let found = report.inconsistencies.len();
+    // Repairing leaves the items whose movements don't add up as they are
+    let unbalanced = report
+        .inconsistencies
+        .iter()
+        .filter(|inconsistency| inconsistency.kind == models::InconsistencyKind::Unbalanced)
+        .count();
+    match report.repaired {
+        _ if found == 0 => println!("The stock and the movements agree"),
+        Some(policy) => println!(
+            "Repaired {} items, believing the {}",
+            found - unbalanced,
+            format!("{policy:?}").to_lowercase()
+        ),
+        None => println!("Found {found} inconsistent items, run with --repair to fix them"),
+    }
+    if unbalanced > 0 {
+        println!("The movements of {unbalanced} items don't add up, those need fixing by hand");
+    }
+
+    Ok(match report.repaired {
+        None => found > 0,
+        Some(_) => unbalanced > 0,
+    })
+}
+
+// Everything deleted longer than `retention` ago goes for good, checked once an hour
+fn purge_trash_hourly(pool: Pool, retention: Duration) {
+    let pool = web::Data::new(pool);
+    actix_web::rt::spawn(async move {
+        let mut hourly = actix_web::rt::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            hourly.tick().await;
+            let purged = api::run(pool.clone(), move |repo| {
+                service::purge_trash(repo, retention)
+            })
+            .await;
+            match purged {
+                Ok((0, 0)) => {}
+                Ok((items, warehouses)) => {
+                    println!("Purged {items} items and {warehouses} warehouses from the trash")
+                }
+                Err(e) => eprintln!("Couldn't purge the trash: {e}"),
+            }
+        }
+    });
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    // A .env file is handy in development, but containers set the environment directly
+    dotenv().ok();
+
+    let (config, command) = Config::load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(2);
+    });
+
+    if let Some(Command::Migrate { action }) = command {
+        if let Err(e) = migrate(&pool(&config), action) {
+            eprintln!("Migration failed: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Fsck { repair }) = command {
+        match fsck(&pool(&config), repair) {
+            Ok(false) => return Ok(()),
+            Ok(true) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Consistency check failed: {e}");
+                std::process::exit(2);
+            }
+        }
+    }
+
     println!("Warehouser Startup!");
 
-    dotenv().expect("Couldn't load .env");
-    std::env::set_var("RUST_LOG", "actix_web=info");
+    env_logger::Builder::new().parse_filters(&config.log).init();
 
-    env_logger::init();
+    pagination::set_limits(Limits {
+        default: config.limits.default_page,
+        max: config.limits.max_page,
+    });
 
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    if config.backend == Backend::Memory {
+        println!("Keeping data in memory, it's lost when the server stops");
+    }
+    let pool = pool(&config);
+    let conn = pool.get(None).expect("Couldn't get a db connection");
 
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    let pool: DbPool = r2d2::Pool::builder()
-        .max_size(1) // Mitigation for buggy behaviour with Postgresql 14
-        .build(manager)
-        .expect("Couldn't create db pool");
+    if config.auto_migrate {
+        migrations::run_pending(&conn, &mut std::io::stdout()).unwrap_or_else(|e| {
+            eprintln!("Migration failed: {e}");
+            std::process::exit(1);
+        });
+    }
 
-    HttpServer::new(move || {
+    // Requests against an older schema would fail in confusing ways, so don't take any
+    if let Err(e) = service::check_migrations(conn.repo()) {
+        eprintln!("The database isn't ready: {e}");
+        if let models::Error::NotReady {
+            pending_migrations, ..
+        } = &e
+        {
+            eprintln!("Pending migrations: {}", pending_migrations.join(", "));
+        }
+        eprintln!("Run `warehouser migrate up`, or start with --auto-migrate");
+        std::process::exit(1);
+    }
+
+    // A fresh database has no keys, so hand out the first one
+    let issued = service::bootstrap_api_key(conn.repo()).expect("Couldn't bootstrap API keys");
+    if let Some(issued) = issued {
+        println!("No API keys found, issued an admin key, it won't be shown again:");
+        println!("{}", issued.key);
+    }
+    drop(conn);
+
+    purge_trash_hourly(
+        pool.clone(),
+        Duration::from_secs(config.trash_retention_days * 24 * 60 * 60),
+    );
+
+    let (json_limit, import_limit) = (config.limits.json_payload, config.limits.import_payload);
+    let idempotency_window = Duration::from_secs(config.idempotency_window_secs);
+    let idempotency_abandoned = Duration::from_secs(config.idempotency_abandoned_secs);
+
+    let mut server = HttpServer::new(move || {
         App::new()
+            // Runs inside `Auth`, keys are kept per API key. Its body limit is the largest of the limits
+            .wrap(idempotency::Idempotency::new(
+                pool.clone(),
+                idempotency_window,
+                idempotency_abandoned,
+                import_limit,
+            ))
+            // Runs inside the logger, rejected requests are still logged
+            .wrap(auth::Auth::new(pool.clone()))
+            .wrap_fn(metrics::track)
             .wrap(Logger::default())
             .data(pool.clone())
-            .app_data(web::JsonConfig::default().error_handler(|err, _| {
-                let fmt = format!("{}", &err);
-                let resp = HttpResponse::InternalServerError().body(fmt);
-                InternalError::from_response(err, resp).into()
-            }))
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(json_limit)
+                    .error_handler(|err, _| invalid_request(err)),
+            )
+            .app_data(web::QueryConfig::default().error_handler(|err, _| invalid_request(err)))
+            .app_data(web::PathConfig::default().error_handler(|err, _| invalid_request(err)))
+            // Bulk imports can be a lot bigger than the 256kB default
+            .app_data(web::PayloadConfig::new(import_limit))
+            .service(healthz)
+            .service(readyz)
+            .service(get_metrics)
+            // Before the `/api` scope, which would answer anything under it with a 404
+            .service(openapi::get_openapi)
+            .service(openapi::get_docs)
             .service(
                 web::scope("/api")
                     .service(
                         web::scope("/item")
                             .service(create_item) // C
+                            .service(import_items_csv)
+                            .service(import_item)
                             .service(get_items) // R
                             .service(update_item) // U
+                            .service(patch_item)
                             .service(delete_item) // D
+                            .service(restore_item)
                             .service(item_csv)
+                            .service(item_history)
                             .service(get_item),
                     )
                     .service(
@@ -66,17 +321,39 @@ async fn main() -> std::io::Result<()> {
                             .service(warehouse_csv)
                             .service(warehouse_add_item)
                             .service(warehouse_remove_item)
+                            .service(warehouse_transfer)
                             .service(warehouse_get_items)
+                            .service(warehouse_movements)
                             .service(create_warehouse)
+                            .service(import_warehouses_csv)
+                            .service(import_warehouse)
                             .service(get_warehouse)
                             .service(get_warehouses)
                             .service(delete_warehouse)
+                            .service(restore_warehouse)
                             .service(update_warehouse),
-                    ),
+                    )
+                    .service(get_trash)
+                    .service(run_batch)
+                    .service(
+                        web::scope("/admin/keys")
+                            .service(issue_api_key)
+                            .service(get_api_keys)
+                            .service(revoke_api_key),
+                    )
+                    .service(web::scope("/admin").service(check_consistency)),
             )
-    })
-    .bind("127.0.0.1:8087")
-    .expect("Couldn't bind")
-    .run()
-    .await
+    });
+
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    for addr in &config.bind {
+        server = server.bind(addr).unwrap_or_else(|e| {
+            eprintln!("Couldn't bind to {addr}: {e}");
+            std::process::exit(1);
+        });
+    }
+
+    server.run().await
 }
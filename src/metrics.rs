@@ -0,0 +1,187 @@
+/// Prometheus metrics
+/// Requests are counted by their route pattern, like `/api/item/{id}`, rather than their path,
+/// so the number of series doesn't grow with the number of items
+use std::{
+    future::Future,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::{Method, StatusCode},
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::models::ErrorCode;
+use warehouser_core::repository::Pool;
+
+struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_seconds: HistogramVec,
+    errors: IntCounterVec,
+    pool_connections: IntGauge,
+    pool_idle_connections: IntGauge,
+    pool_max_size: IntGauge,
+    pool_wait_seconds: Histogram,
+    blocking_seconds: Histogram,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("warehouser".to_string()), None)
+            .expect("Invalid metrics prefix");
+
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let request_seconds = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time taken to respond"),
+            &["method", "route"],
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new("errors_total", "Error responses, by status and error code"),
+            &["status", "code"],
+        )
+        .unwrap();
+        let pool_connections =
+            IntGauge::new("db_pool_connections", "Connections held by the pool").unwrap();
+        let pool_idle_connections =
+            IntGauge::new("db_pool_idle_connections", "Connections not in use").unwrap();
+        let pool_max_size =
+            IntGauge::new("db_pool_max_size", "Most connections the pool will hold").unwrap();
+        let pool_wait_seconds = Histogram::with_opts(HistogramOpts::new(
+            "db_pool_wait_seconds",
+            "Time spent waiting for a connection",
+        ))
+        .unwrap();
+        let blocking_seconds = Histogram::with_opts(HistogramOpts::new(
+            "blocking_seconds",
+            "Time spent in the blocking thread pool, database work included",
+        ))
+        .unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry
+            .register(Box::new(request_seconds.clone()))
+            .unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry
+            .register(Box::new(pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_idle_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(pool_max_size.clone())).unwrap();
+        registry
+            .register(Box::new(pool_wait_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(blocking_seconds.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            requests,
+            request_seconds,
+            errors,
+            pool_connections,
+            pool_idle_connections,
+            pool_max_size,
+            pool_wait_seconds,
+            blocking_seconds,
+        }
+    }
+}
+
+/// Count a request once it's been answered
+///
+/// `route` is the pattern the request matched, or `None` when it matched nothing
+pub fn observe_request(method: &Method, route: Option<&str>, status: StatusCode, took: Duration) {
+    let route = route.unwrap_or("unmatched");
+    METRICS
+        .requests
+        .with_label_values(&[method.as_str(), route, status.as_str()])
+        .inc();
+    METRICS
+        .request_seconds
+        .with_label_values(&[method.as_str(), route])
+        .observe(took.as_secs_f64());
+}
+
+/// Count an error sent to a client, by its status and `code`
+pub fn observe_error(status: StatusCode, code: &str) {
+    METRICS
+        .errors
+        .with_label_values(&[status.as_str(), code])
+        .inc();
+}
+
+/// Times a wait for a database connection, until it's dropped
+pub fn pool_wait() -> HistogramTimer {
+    METRICS.pool_wait_seconds.start_timer()
+}
+
+/// Times a call to `web::block`, until it's dropped
+pub fn blocking() -> HistogramTimer {
+    METRICS.blocking_seconds.start_timer()
+}
+
+/// Every metric, in the Prometheus text format
+pub fn render(pool: &Pool) -> String {
+    // The pool's state is only sampled when it's asked for,
+    // the in-memory backend has no connections to report
+    let state = match pool {
+        Pool::Postgres(pool) => Some((pool.state(), pool.max_size())),
+        #[cfg(feature = "sqlite")]
+        Pool::Sqlite(pool) => Some((pool.state(), pool.max_size())),
+        Pool::Memory(_) => None,
+    };
+    if let Some((state, max_size)) = state {
+        METRICS.pool_connections.set(state.connections.into());
+        METRICS
+            .pool_idle_connections
+            .set(state.idle_connections.into());
+        METRICS.pool_max_size.set(max_size.into());
+    }
+
+    let mut out = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut out)
+        .expect("Failed to encode metrics!");
+    String::from_utf8(out).expect("Metrics aren't utf-8")
+}
+
+/// Middleware, used with `wrap_fn`, that counts and times every request
+pub fn track<S>(
+    req: ServiceRequest,
+    srv: &mut S,
+) -> impl Future<Output = Result<ServiceResponse, actix_web::Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let start = Instant::now();
+    let method = req.method().clone();
+    // Found from the routes rather than the match, so requests turned away before routing have one
+    let route = req.match_pattern();
+    let fut = srv.call(req);
+
+    async move {
+        let res = fut.await?;
+        observe_request(&method, route.as_deref(), res.status(), start.elapsed());
+        // Put there by `Error::error_response`
+        if let Some(ErrorCode(code)) = res.response().extensions().get::<ErrorCode>() {
+            observe_error(res.status(), code);
+        }
+        Ok(res)
+    }
+}
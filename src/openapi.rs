@@ -0,0 +1,459 @@
+/// OpenAPI document
+/// Built from the `#[utoipa::path]` annotations on the handlers and the schemas of the models,
+/// so it changes along with them. Served at `/api/openapi.json`, with Swagger UI at `/api/docs/`
+// What the annotations can't say, the version 2 media type, the role each operation needs,
+// and the `Idempotency-Key` every change takes, is filled in by modifiers that read it
+// from `versions`, `auth::required_role` and `idempotency`
+use std::sync::{Arc, LazyLock};
+
+use actix_web::{get, http::Method, web, HttpResponse, Responder};
+use utoipa::{
+    openapi::{
+        path::{ParameterBuilder, ParameterIn, PathItemType},
+        security::{
+            ApiKey as ApiKeyScheme, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme,
+        },
+        Content, ObjectBuilder, OpenApi as Document, Ref, RefOr, Required, Response, Schema,
+        SchemaType,
+    },
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::Config;
+
+use crate::{
+    api::{self, TransferPayload},
+    auth,
+    batch::{Operation, OperationResult},
+    idempotency,
+    models::{
+        ApiKey, ConsistencyReport, Dimensions, Error, FieldError, Holding, ImportReport,
+        Inconsistency, InconsistencyKind, InventoryItem, IssuedKey, LineError, Money, Movement,
+        MovementReason, NewApiKey, NewInventoryItem, NewWarehouse, OperationError, Problem,
+        RepairPolicy, Role, Transfer, Transport, Trash, Warehouse,
+    },
+    versions::{DimensionsV1, ItemV1, NewItemV1, V2_MEDIA_TYPE},
+};
+
+/// Where the document is served, Swagger UI loads it from here
+const DOCUMENT_PATH: &str = "/api/openapi.json";
+
+/// Version 1 schemas, and the models that version 2 sends in their place
+const V2_SCHEMAS: &[(&str, &str)] = &[
+    ("ItemV1", "InventoryItem"),
+    ("NewItemV1", "NewInventoryItem"),
+];
+
+/// Schemas with version 1 items inside of them, and the name of the same schema with
+/// version 2 items in their place, which is made from it
+const V2_CONTAINERS: &[(&str, &str)] = &[("Operation", "OperationV2"), ("Trash", "TrashV2")];
+
+/// Errors are sent as problem documents, see `Error::error_response`
+const PROBLEM_MEDIA_TYPE: &str = "application/problem+json";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Warehouser", description = "Inventory tracking for warehouses"),
+    paths(
+        api::create_item,
+        api::import_item,
+        api::get_item,
+        api::get_items,
+        api::update_item,
+        api::patch_item,
+        api::delete_item,
+        api::item_csv,
+        api::item_history,
+        api::create_warehouse,
+        api::import_warehouse,
+        api::get_warehouse,
+        api::get_warehouses,
+        api::update_warehouse,
+        api::delete_warehouse,
+        api::warehouse_csv,
+        api::warehouse_get_items,
+        api::warehouse_add_item,
+        api::warehouse_remove_item,
+        api::warehouse_transfer,
+        api::warehouse_movements,
+        api::restore_item,
+        api::restore_warehouse,
+        api::get_trash,
+        api::run_batch,
+        api::issue_api_key,
+        api::get_api_keys,
+        api::revoke_api_key,
+        api::check_consistency,
+        api::healthz,
+        api::readyz,
+        api::get_metrics,
+    ),
+    components(schemas(
+        ItemV1,
+        NewItemV1,
+        DimensionsV1,
+        InventoryItem,
+        NewInventoryItem,
+        Dimensions,
+        Money,
+        Transport,
+        Warehouse,
+        NewWarehouse,
+        Transfer,
+        TransferPayload,
+        Trash,
+        Movement,
+        MovementReason,
+        ImportReport,
+        LineError,
+        Operation,
+        OperationResult,
+        OperationError,
+        ApiKey,
+        NewApiKey,
+        IssuedKey,
+        Role,
+        ConsistencyReport,
+        Inconsistency,
+        InconsistencyKind,
+        Holding,
+        RepairPolicy,
+        Problem,
+        Error,
+        FieldError,
+    )),
+    tags(
+        (name = "items", description = "Inventory items, and their stock history"),
+        (name = "warehouses", description = "Warehouses, and the stock moving in and out of them"),
+        (name = "trash", description = "Deleted items and warehouses, until they're purged"),
+        (name = "batches", description = "Several changes made at once, in one transaction"),
+        (name = "api keys", description = "Issuing and revoking API keys, for admins"),
+        (name = "consistency", description = "Checking the stock against the movements, for admins"),
+        (name = "operations", description = "Health, readiness, and metrics, no key needed"),
+    ),
+    security(("bearer" = []), ("api_key" = [])),
+    modifiers(&SecuritySchemes, &VersionTwo, &Problems, &Roles, &IdempotencyKeys)
+)]
+pub struct ApiDoc;
+
+// The two ways to send a key, see `auth::credentials`
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut Document) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKeyScheme::Header(ApiKeyValue::new(auth::API_KEY_HEADER))),
+        );
+    }
+}
+
+// Every body given as a version 1 item can also be sent or asked for as version 2
+struct VersionTwo;
+
+impl Modify for VersionTwo {
+    fn modify(&self, openapi: &mut Document) {
+        if let Some(components) = &mut openapi.components {
+            for (v1, v2) in V2_CONTAINERS {
+                if let Some(schema) = components.schemas.get(*v1).and_then(with_v2_refs) {
+                    components.schemas.insert(v2.to_string(), schema);
+                }
+            }
+        }
+
+        fn add_v2<'a>(
+            content: impl IntoIterator<Item = (&'a String, &'a Content)>,
+        ) -> Option<Content> {
+            content
+                .into_iter()
+                .find(|(media, _)| *media == "application/json")
+                .and_then(|(_, json)| v2_schema(&json.schema))
+                .map(Content::new)
+        }
+
+        for operation in operations(openapi) {
+            if let Some(body) = &mut operation.request_body {
+                if let Some(v2) = add_v2(&body.content) {
+                    body.content.insert(V2_MEDIA_TYPE.to_string(), v2);
+                }
+            }
+            for response in operation.responses.responses.values_mut() {
+                if let RefOr::T(response) = response {
+                    if let Some(v2) = add_v2(&response.content) {
+                        response.content.insert(V2_MEDIA_TYPE.to_string(), v2);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A copy of a schema, with its references to version 1 schemas pointed at version 2 ones
+fn with_v2_refs(schema: &RefOr<Schema>) -> Option<RefOr<Schema>> {
+    fn replace(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(object) => {
+                for (key, value) in object.iter_mut() {
+                    let v2 = V2_SCHEMAS.iter().find(|(v1, _)| {
+                        key == "$ref" && *value == Ref::from_schema_name(*v1).ref_location
+                    });
+                    match v2 {
+                        Some((_, v2)) => *value = Ref::from_schema_name(*v2).ref_location.into(),
+                        None => replace(value),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(replace),
+            _ => {}
+        }
+    }
+
+    let mut value = serde_json::to_value(schema).ok()?;
+    replace(&mut value);
+    serde_json::from_value(value).ok()
+}
+
+// The version 2 schema for a version 1 one, or an array of them
+fn v2_schema(schema: &RefOr<Schema>) -> Option<RefOr<Schema>> {
+    match schema {
+        RefOr::Ref(reference) => V2_SCHEMAS
+            .iter()
+            .chain(V2_CONTAINERS)
+            .find(|(v1, _)| reference.ref_location == Ref::from_schema_name(*v1).ref_location)
+            .map(|(_, v2)| RefOr::Ref(Ref::from_schema_name(*v2))),
+        RefOr::T(Schema::Array(array)) => {
+            let mut array = array.clone();
+            array.items = Box::new(v2_schema(&array.items)?);
+            Some(RefOr::T(Schema::Array(array)))
+        }
+        _ => None,
+    }
+}
+
+// The annotations say `body = Problem`, which utoipa takes to be plain json
+struct Problems;
+
+impl Modify for Problems {
+    fn modify(&self, openapi: &mut Document) {
+        let problem = RefOr::Ref(Ref::from_schema_name("Problem"));
+        for operation in operations(openapi) {
+            for response in operation.responses.responses.values_mut() {
+                if let RefOr::T(response) = response {
+                    let is_problem = response
+                        .content
+                        .get("application/json")
+                        .is_some_and(|json| json.schema == problem);
+                    if is_problem {
+                        let json = response.content.shift_remove("application/json");
+                        response
+                            .content
+                            .extend(json.map(|json| (PROBLEM_MEDIA_TYPE.to_string(), json)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The role each operation needs, and the responses for keys that are missing or not enough
+struct Roles;
+
+impl Modify for Roles {
+    fn modify(&self, openapi: &mut Document) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            for (kind, operation) in item.operations.iter_mut() {
+                let required = match auth::required_role(&method(kind), path) {
+                    Some(required) => required,
+                    None => continue,
+                };
+
+                let needs = format!("Needs a key with the `{required}` role, or a role above it");
+                operation.description = Some(match operation.description.take() {
+                    Some(description) if !description.is_empty() => {
+                        format!("{description}\n\n{needs}")
+                    }
+                    _ => needs,
+                });
+
+                for (status, description) in [
+                    ("401", "No API key, or one that's unknown or revoked"),
+                    ("403", "The API key's role isn't allowed to do this"),
+                ] {
+                    let problem = Content::new(Ref::from_schema_name("Problem"));
+                    let mut response = Response::new(description);
+                    response
+                        .content
+                        .insert(PROBLEM_MEDIA_TYPE.to_string(), problem);
+                    operation
+                        .responses
+                        .responses
+                        .entry(status.to_string())
+                        .or_insert(RefOr::T(response));
+                }
+            }
+        }
+    }
+}
+
+// Every change can be made with an `Idempotency-Key`, see `idempotency`
+struct IdempotencyKeys;
+
+impl Modify for IdempotencyKeys {
+    fn modify(&self, openapi: &mut Document) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            for (kind, operation) in item.operations.iter_mut() {
+                let method = method(kind);
+                if !idempotency::is_change(&method) || auth::required_role(&method, path).is_none()
+                {
+                    continue;
+                }
+
+                let key = ParameterBuilder::new()
+                    .name(idempotency::IDEMPOTENCY_KEY_HEADER)
+                    .parameter_in(ParameterIn::Header)
+                    .required(Required::False)
+                    .description(Some(
+                        "Names the change, so a retry isn't made twice. \
+                         A retry with the same key is sent the first response again, \
+                         with `Idempotent-Replayed: true`. Up to 255 visible characters",
+                    ))
+                    .schema(Some(ObjectBuilder::new().schema_type(SchemaType::String)))
+                    .build();
+                operation.parameters.get_or_insert_with(Vec::new).push(key);
+
+                for (status, description) in [
+                    (
+                        "409",
+                        "A request with the same `Idempotency-Key` is still being handled",
+                    ),
+                    (
+                        "422",
+                        "The `Idempotency-Key` was already used for a different request",
+                    ),
+                ] {
+                    let problem = Content::new(Ref::from_schema_name("Problem"));
+                    let mut response = Response::new(description);
+                    response
+                        .content
+                        .insert(PROBLEM_MEDIA_TYPE.to_string(), problem);
+                    operation
+                        .responses
+                        .responses
+                        .entry(status.to_string())
+                        .or_insert(RefOr::T(response));
+                }
+            }
+        }
+    }
+}
+
+fn operations(
+    openapi: &mut Document,
+) -> impl Iterator<Item = &mut utoipa::openapi::path::Operation> {
+    openapi
+        .paths
+        .paths
+        .values_mut()
+        .flat_map(|item| item.operations.values_mut())
+}
+
+fn method(kind: &PathItemType) -> Method {
+    match kind {
+        PathItemType::Get => Method::GET,
+        PathItemType::Post => Method::POST,
+        PathItemType::Put => Method::PUT,
+        PathItemType::Delete => Method::DELETE,
+        PathItemType::Options => Method::OPTIONS,
+        PathItemType::Head => Method::HEAD,
+        PathItemType::Patch => Method::PATCH,
+        PathItemType::Trace => Method::TRACE,
+        PathItemType::Connect => Method::CONNECT,
+    }
+}
+
+// Built once, the handlers can't change while the server runs
+static DOCUMENT: LazyLock<String> = LazyLock::new(|| {
+    ApiDoc::openapi()
+        .to_pretty_json()
+        .expect("Couldn't serialize the OpenAPI document")
+});
+
+/// The OpenAPI document
+#[get("/api/openapi.json")]
+pub async fn get_openapi() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(DOCUMENT.as_str())
+}
+
+/// Swagger UI, bundled into the binary
+#[get("/api/docs{tail:.*}")]
+pub async fn get_docs(tail: web::Path<String>) -> HttpResponse {
+    // The page loads its files relative to itself, so it has to be served from a directory
+    if tail.is_empty() {
+        return HttpResponse::PermanentRedirect()
+            .header("Location", "/api/docs/")
+            .finish();
+    }
+
+    let file = tail.trim_start_matches('/');
+    match utoipa_swagger_ui::serve(file, Arc::new(Config::from(DOCUMENT_PATH))) {
+        Ok(Some(file)) => HttpResponse::Ok()
+            .content_type(file.content_type)
+            .body(file.bytes.into_owned()),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Handlers that share a path and method with another, and are documented with it
+    const SHARED: &[&str] = &["import_items_csv", "import_warehouses_csv"];
+
+    #[test]
+    fn every_route_is_documented() {
+        let document = ApiDoc::openapi();
+        let documented: Vec<&str> = document
+            .paths
+            .paths
+            .values()
+            .flat_map(|item| item.operations.values())
+            .filter_map(|operation| operation.operation_id.as_deref())
+            .collect();
+
+        // Every handler in api.rs has a route attribute, and the name of the function after it
+        let source = include_str!("api.rs");
+        let mut lines = source.lines().map(str::trim);
+        let mut handlers = Vec::new();
+        while let Some(line) = lines.next() {
+            let is_route = ["#[get(", "#[post(", "#[put(", "#[delete(", "#[patch("]
+                .iter()
+                .any(|attribute| line.starts_with(attribute));
+            if !is_route {
+                continue;
+            }
+            let name = lines
+                .find_map(|line| line.strip_prefix("pub async fn "))
+                .and_then(|rest| rest.split('(').next())
+                .expect("A route attribute without a handler after it");
+            handlers.push(name);
+        }
+
+        assert!(!handlers.is_empty(), "Found no handlers in api.rs");
+        let missing: Vec<&str> = handlers
+            .into_iter()
+            .filter(|name| !documented.contains(name) && !SHARED.contains(name))
+            .collect();
+        assert!(
+            missing.is_empty(),
+            "Handlers missing from the OpenAPI document, annotate them with #[utoipa::path] and list them in ApiDoc: {missing:?}"
+        );
+    }
+}
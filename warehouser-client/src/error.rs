@@ -0,0 +1,142 @@
+/// What can go wrong while calling the API
+use std::fmt::Display;
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use warehouser_core::models::Error;
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server turned the request down, `error` is the same `Error` it was made from
+    Api {
+        status: StatusCode,
+        message: String,
+        error: Error,
+    },
+    /// The server answered with something we didn't expect, like a proxy's error page
+    UnexpectedResponse {
+        status: StatusCode,
+        body: String,
+    },
+    /// The request couldn't be sent, or its response couldn't be read
+    Http(reqwest::Error),
+    InvalidUrl(url::ParseError),
+}
+
+impl ClientError {
+    /// The server's error, when it sent one
+    pub fn api_error(&self) -> Option<&Error> {
+        match self {
+            ClientError::Api { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+
+    /// The error for a response that wasn't a success
+    pub(crate) fn from_response(status: StatusCode, body: &[u8]) -> Self {
+        match serde_json::from_slice::<Problem>(body) {
+            Ok(problem) => ClientError::Api {
+                status,
+                message: problem.message,
+                error: problem.error,
+            },
+            // From a newer server with codes we don't know, or not from the server at all
+            Err(_) => ClientError::UnexpectedResponse {
+                status,
+                body: String::from_utf8_lossy(body).into_owned(),
+            },
+        }
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Api {
+                status, message, ..
+            } => write!(f, "{status}: {message}"),
+            ClientError::UnexpectedResponse { status, body } => {
+                write!(f, "Unexpected response ({status}): {body}")
+            }
+            ClientError::Http(e) => write!(f, "{e}"),
+            ClientError::InvalidUrl(e) => write!(f, "Invalid base url: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<url::ParseError> for ClientError {
+    fn from(e: url::ParseError) -> Self {
+        ClientError::InvalidUrl(e)
+    }
+}
+
+// The parts of a problem document we keep, the error's own fields sit alongside them
+#[derive(Deserialize)]
+struct Problem {
+    message: String,
+    #[serde(flatten)]
+    error: Error,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warehouser_core::models::Problem as ServerProblem;
+
+    #[test]
+    fn a_problem_document_becomes_the_error_it_was_made_from() {
+        let sent = Error::InsufficientStock {
+            item_id: 3,
+            warehouse_id: 1,
+            requested: 5,
+            on_hand: 2,
+        };
+        let body = serde_json::to_vec(&ServerProblem::from(&sent)).unwrap();
+
+        match ClientError::from_response(StatusCode::BAD_REQUEST, &body) {
+            ClientError::Api {
+                status,
+                message,
+                error,
+            } => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert_eq!(message, sent.message());
+                assert!(matches!(
+                    error,
+                    Error::InsufficientStock {
+                        item_id: 3,
+                        on_hand: 2,
+                        ..
+                    }
+                ));
+            }
+            other => panic!("expected an api error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn anything_else_is_an_unexpected_response() {
+        for body in [
+            &b"<html>Bad Gateway</html>"[..],
+            br#"{"message": "From a newer server", "code": "not_a_code_we_know"}"#,
+        ] {
+            let error = ClientError::from_response(StatusCode::BAD_GATEWAY, body);
+            assert!(matches!(
+                error,
+                ClientError::UnexpectedResponse { status: StatusCode::BAD_GATEWAY, body: ref text }
+                    if text.as_bytes() == body
+            ));
+        }
+    }
+}
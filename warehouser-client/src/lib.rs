@@ -0,0 +1,432 @@
+//! Warehouser client
+//! A typed, async client for the Warehouser API, built on the same models as the server.
+//! Items are sent and received in version 2, the shape of `InventoryItem` itself
+
+pub mod error;
+
+pub use error::{ClientError, Result};
+pub use warehouser_core::{
+    batch::{Operation, OperationResult},
+    models,
+    pagination::{ItemParams, Page, WarehouseFilter, WarehouseParams},
+    util::CsvPayload,
+};
+
+use reqwest::{
+    header::{self, HeaderMap},
+    Method, RequestBuilder, Response,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use url::Url;
+use warehouser_core::{
+    conditional::etag,
+    models::{
+        ApiKey, ConsistencyReport, ImportReport, InventoryItem, IssuedKey, Movement, NewApiKey,
+        NewInventoryItem, NewWarehouse, Transfer, Trash, Warehouse,
+    },
+    versions::V2_MEDIA_TYPE,
+};
+
+/// A connection to a Warehouser server, cheap to clone and share
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base: String, // Without a trailing `/`, paths are appended to it
+    key: String,
+}
+
+impl Client {
+    /// A client for the server at `base_url`, like `http://127.0.0.1:8087`, using `api_key`
+    pub fn new(base_url: &str, api_key: impl Into<String>) -> Result<Self> {
+        Client::with_http(reqwest::Client::new(), base_url, api_key)
+    }
+
+    /// The same, sending requests with `http`, for clients with their own timeouts or proxies
+    pub fn with_http(
+        http: reqwest::Client,
+        base_url: &str,
+        api_key: impl Into<String>,
+    ) -> Result<Self> {
+        let base = Url::parse(base_url)?;
+        Ok(Client {
+            http,
+            base: base.as_str().trim_end_matches('/').to_string(),
+            key: api_key.into(),
+        })
+    }
+
+    // Items
+
+    pub async fn create_item(&self, item: &NewInventoryItem) -> Result<InventoryItem> {
+        self.json(item_body(self.request(Method::POST, "/api/item"), item))
+            .await
+    }
+
+    /// Create an item with an id picked by the client
+    pub async fn import_item(&self, item: &InventoryItem) -> Result<InventoryItem> {
+        self.json(item_body(
+            self.request(Method::POST, "/api/item/import"),
+            item,
+        ))
+        .await
+    }
+
+    /// Create every item of a csv file in the export's layout, or none of them
+    pub async fn import_items_csv(
+        &self,
+        csv: impl Into<Vec<u8>>,
+        options: &CsvPayload,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        self.import_csv("/api/item/import", csv.into(), options, dry_run)
+            .await
+    }
+
+    pub async fn get_item(&self, id: i32) -> Result<InventoryItem> {
+        self.json(self.request(Method::GET, &format!("/api/item/{id}")))
+            .await
+    }
+
+    /// A page of items, pass its `next` as the `cursor` of `params` for the page after it
+    pub async fn get_items(&self, params: &ItemParams) -> Result<Page<Vec<InventoryItem>>> {
+        self.page(self.request(Method::GET, "/api/item").query(params))
+            .await
+    }
+
+    /// Update an item, everything but its stock, which is moved with the warehouse methods
+    ///
+    /// Fails with `PreconditionFailed` if the item has changed since `item` was read
+    pub async fn update_item(&self, item: &InventoryItem) -> Result<InventoryItem> {
+        let req = if_match(self.request(Method::PUT, "/api/item"), item.version);
+        self.json(item_body(req, item)).await
+    }
+
+    /// Change only the fields in `patch`, a JSON Merge Patch in the shape of `InventoryItem`,
+    /// as long as the item is still at `version`
+    pub async fn patch_item(
+        &self,
+        id: i32,
+        version: i32,
+        patch: &serde_json::Value,
+    ) -> Result<InventoryItem> {
+        let req = self.request(Method::PATCH, &format!("/api/item/{id}"));
+        self.json(item_body(if_match(req, version), patch)).await
+    }
+
+    /// Delete an item, as long as it's still at `version`, it goes to the trash
+    pub async fn delete_item(&self, id: i32, version: i32) -> Result<InventoryItem> {
+        let req = self.request(Method::DELETE, &format!("/api/item/{id}"));
+        self.json(if_match(req, version)).await
+    }
+
+    /// Items as csv, every one of them unless `params` has a `limit`
+    pub async fn item_csv(
+        &self,
+        params: &ItemParams,
+        options: &CsvPayload,
+    ) -> Result<Page<String>> {
+        let req = self.request(Method::GET, "/api/item/csv");
+        self.csv(req.query(params).query(options)).await
+    }
+
+    /// The item's movements, oldest first
+    pub async fn item_history(&self, id: i32, limit: Option<i64>) -> Result<Vec<Movement>> {
+        let req = self.request(Method::GET, &format!("/api/item/{id}/history"));
+        self.json(req.query(&[("limit", limit)])).await
+    }
+
+    // Warehouses
+
+    pub async fn create_warehouse(&self, warehouse: &NewWarehouse) -> Result<Warehouse> {
+        self.json(self.request(Method::POST, "/api/warehouse").json(warehouse))
+            .await
+    }
+
+    /// Create a warehouse with an id picked by the client
+    pub async fn import_warehouse(&self, warehouse: &Warehouse) -> Result<Warehouse> {
+        let req = self.request(Method::POST, "/api/warehouse/import");
+        self.json(req.json(warehouse)).await
+    }
+
+    /// Create every warehouse of a csv file in the export's layout, or none of them
+    pub async fn import_warehouses_csv(
+        &self,
+        csv: impl Into<Vec<u8>>,
+        options: &CsvPayload,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        self.import_csv("/api/warehouse/import", csv.into(), options, dry_run)
+            .await
+    }
+
+    pub async fn get_warehouse(&self, id: i32) -> Result<Warehouse> {
+        self.json(self.request(Method::GET, &format!("/api/warehouse/{id}")))
+            .await
+    }
+
+    /// A page of warehouses, pass its `next` as the `cursor` of `params` for the page after it
+    pub async fn get_warehouses(&self, params: &WarehouseParams) -> Result<Page<Vec<Warehouse>>> {
+        self.page(self.request(Method::GET, "/api/warehouse").query(params))
+            .await
+    }
+
+    /// Replace a warehouse, the server turns this down with `Unsupported` for now,
+    /// what a warehouse holds is changed with the add, remove, and transfer methods
+    pub async fn update_warehouse(&self, warehouse: &Warehouse) -> Result<Warehouse> {
+        self.json(self.request(Method::PUT, "/api/warehouse").json(warehouse))
+            .await
+    }
+
+    /// Delete a warehouse, as long as it's still at `version`, it goes to the trash
+    /// and its items are left in no warehouse
+    pub async fn delete_warehouse(&self, id: i32, version: i32) -> Result<Warehouse> {
+        let req = self.request(Method::DELETE, &format!("/api/warehouse/{id}"));
+        self.json(if_match(req, version)).await
+    }
+
+    /// Warehouses as csv, every one of them unless `params` has a `limit`
+    pub async fn warehouse_csv(
+        &self,
+        params: &WarehouseParams,
+        options: &CsvPayload,
+    ) -> Result<Page<String>> {
+        let req = self.request(Method::GET, "/api/warehouse/csv");
+        self.csv(req.query(params).query(options)).await
+    }
+
+    /// A page of the items a warehouse stocks
+    pub async fn warehouse_get_items(
+        &self,
+        id: i32,
+        params: &ItemParams,
+    ) -> Result<Page<Vec<InventoryItem>>> {
+        let req = self.request(Method::GET, &format!("/api/warehouse/{id}/items"));
+        self.page(req.query(params)).await
+    }
+
+    /// Stock `quantity` more of an item in a warehouse
+    pub async fn warehouse_add_item(
+        &self,
+        id: i32,
+        item_id: i32,
+        quantity: i32,
+    ) -> Result<Warehouse> {
+        let req = self.request(Method::POST, &format!("/api/warehouse/{id}/add"));
+        self.json(req.query(&[("id", item_id), ("quantity", quantity)]))
+            .await
+    }
+
+    /// Take `quantity` of an item out of a warehouse
+    pub async fn warehouse_remove_item(
+        &self,
+        id: i32,
+        item_id: i32,
+        quantity: i32,
+    ) -> Result<Warehouse> {
+        let req = self.request(Method::POST, &format!("/api/warehouse/{id}/remove"));
+        self.json(req.query(&[("id", item_id), ("quantity", quantity)]))
+            .await
+    }
+
+    /// Move all of `items` from one warehouse to another, or none of them
+    pub async fn warehouse_transfer(&self, id: i32, to: i32, items: &[i32]) -> Result<Transfer> {
+        let req = self.request(Method::POST, &format!("/api/warehouse/{id}/transfer"));
+        let body = serde_json::json!({ "to": to, "items": items });
+        self.json(req.json(&body)).await
+    }
+
+    /// Movements into or out of a warehouse, oldest first
+    pub async fn warehouse_movements(&self, id: i32, limit: Option<i64>) -> Result<Vec<Movement>> {
+        let req = self.request(Method::GET, &format!("/api/warehouse/{id}/movements"));
+        self.json(req.query(&[("limit", limit)])).await
+    }
+
+    // Trash
+
+    /// What's been deleted and can still be restored, up to `limit` items and `limit` warehouses
+    pub async fn get_trash(&self, limit: Option<i64>) -> Result<Trash> {
+        let req = self.request(Method::GET, "/api/trash");
+        self.json(req.query(&[("limit", limit)])).await
+    }
+
+    /// Take an item out of the trash, back in the warehouse it was deleted from if it's still there
+    pub async fn restore_item(&self, id: i32) -> Result<InventoryItem> {
+        self.json(self.request(Method::POST, &format!("/api/item/{id}/restore")))
+            .await
+    }
+
+    /// Take a warehouse out of the trash, along with the items it had that are still free,
+    /// this needs an admin key
+    pub async fn restore_warehouse(&self, id: i32) -> Result<Warehouse> {
+        self.json(self.request(Method::POST, &format!("/api/warehouse/{id}/restore")))
+            .await
+    }
+
+    // Batches
+
+    /// Runs the operations in one transaction, and says how each one went, in order.
+    /// Unless `continue_on_error` is set, one failing undoes them all, and is `BatchFailed`
+    pub async fn run_batch(
+        &self,
+        operations: &[Operation],
+        continue_on_error: bool,
+    ) -> Result<Vec<OperationResult>> {
+        let req = self
+            .request(Method::POST, "/api/batch")
+            .query(&[("continue_on_error", continue_on_error)]);
+        self.json(item_body(req, &operations)).await
+    }
+
+    // API keys, these need an admin key
+
+    /// Issue a key, the response is the only time it's shown
+    pub async fn issue_api_key(&self, key: &NewApiKey) -> Result<IssuedKey> {
+        self.json(self.request(Method::POST, "/api/admin/keys").json(key))
+            .await
+    }
+
+    pub async fn get_api_keys(&self) -> Result<Vec<ApiKey>> {
+        self.json(self.request(Method::GET, "/api/admin/keys"))
+            .await
+    }
+
+    pub async fn revoke_api_key(&self, id: i32) -> Result<ApiKey> {
+        self.json(self.request(Method::DELETE, &format!("/api/admin/keys/{id}")))
+            .await
+    }
+
+    // Consistency, this needs an admin key too
+
+    /// Every item whose stock disagrees with its movements, nothing is repaired
+    pub async fn check_consistency(&self) -> Result<ConsistencyReport> {
+        self.json(self.request(Method::GET, "/api/admin/consistency"))
+            .await
+    }
+
+    // Helpers
+
+    // Every request carries the key, and asks for items in version 2
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{path}", self.base))
+            .bearer_auth(&self.key)
+            .header(header::ACCEPT, V2_MEDIA_TYPE)
+    }
+
+    async fn import_csv(
+        &self,
+        path: &str,
+        csv: Vec<u8>,
+        options: &CsvPayload,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        let req = self
+            .request(Method::POST, path)
+            .header(header::CONTENT_TYPE, "text/csv")
+            .query(options)
+            .query(&[("dry_run", dry_run)])
+            .body(csv);
+        self.json(req).await
+    }
+
+    async fn json<T: DeserializeOwned>(&self, req: RequestBuilder) -> Result<T> {
+        let (body, _) = self.send(req).await?;
+        Ok(body)
+    }
+
+    async fn page<T: DeserializeOwned>(&self, req: RequestBuilder) -> Result<Page<T>> {
+        let (items, headers) = self.send(req).await?;
+        Ok(Page {
+            items,
+            next: next_cursor(&headers),
+        })
+    }
+
+    async fn csv(&self, req: RequestBuilder) -> Result<Page<String>> {
+        let resp = checked(req.send().await?).await?;
+        let next = next_cursor(resp.headers());
+        Ok(Page {
+            items: resp.text().await?,
+            next,
+        })
+    }
+
+    async fn send<T: DeserializeOwned>(&self, req: RequestBuilder) -> Result<(T, HeaderMap)> {
+        let resp = checked(req.send().await?).await?;
+        let (status, headers) = (resp.status(), resp.headers().clone());
+        let bytes = resp.bytes().await?;
+
+        let body = serde_json::from_slice(&bytes).map_err(|_| ClientError::UnexpectedResponse {
+            status,
+            body: String::from_utf8_lossy(&bytes).into_owned(),
+        })?;
+        Ok((body, headers))
+    }
+}
+
+// Sends an item in version 2, `json` leaves a `Content-Type` that's already set alone
+fn item_body(req: RequestBuilder, item: &impl Serialize) -> RequestBuilder {
+    req.header(header::CONTENT_TYPE, V2_MEDIA_TYPE).json(item)
+}
+
+// Changes are only made to the version the caller last saw
+fn if_match(req: RequestBuilder, version: i32) -> RequestBuilder {
+    req.header(header::IF_MATCH, etag(version))
+}
+
+// The response if it was a success, otherwise the error it describes
+async fn checked(resp: Response) -> Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        Ok(resp)
+    } else {
+        let body = resp.bytes().await?;
+        Err(ClientError::from_response(status, &body))
+    }
+}
+
+// The cursor in a `Link: <...>; rel="next"` header, if there is one
+fn next_cursor(headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(header::LINK)?.to_str().ok()?;
+    let next = link.split(',').find(|link| link.contains("rel=\"next\""))?;
+    let target = next.split_once('<')?.1.split_once('>')?.0;
+    let query = target.split_once('?')?.1;
+
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(name, _)| name == "cursor")
+        .map(|(_, cursor)| cursor.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn link(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::LINK, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn the_next_cursor_comes_from_the_next_link() {
+        let headers = link(
+            "</api/item?sort=id&cursor=first>; rel=\"prev\", \
+             </api/item?sort=-weight&cursor=a%2Bb%3D%3D&limit=2>; rel=\"next\"",
+        );
+        assert_eq!(next_cursor(&headers).as_deref(), Some("a+b=="));
+    }
+
+    #[test]
+    fn theres_no_next_cursor_without_a_next_link() {
+        assert_eq!(next_cursor(&HeaderMap::new()), None);
+        assert_eq!(
+            next_cursor(&link("</api/item?cursor=first>; rel=\"prev\"")),
+            None
+        );
+        assert_eq!(
+            next_cursor(&link("</api/item?limit=2>; rel=\"next\"")),
+            None
+        );
+    }
+}
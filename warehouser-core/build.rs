@@ -0,0 +1,67 @@
+//! Embeds the migrations in this crate's `migrations/` and `migrations_sqlite/`,
+//! so the server can set up its own database and tell whether it's up to date
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+fn main() {
+    println!("cargo:rerun-if-changed=../migrations");
+    println!("cargo:rerun-if-changed=../migrations_sqlite");
+
+    let (postgres, sqlite) = (migrations("migrations"), migrations("migrations_sqlite"));
+    let code = format!(
+        "pub const MIGRATIONS: &[&str] = &{:?};\n\
+         pub const SQLITE_MIGRATIONS: &[&str] = &{:?};\n\
+         pub const POSTGRES: &[EmbeddedMigration] = &[\n{}];\n\
+         pub const SQLITE: &[EmbeddedMigration] = &[\n{}];\n",
+        versions(&postgres),
+        versions(&sqlite),
+        embedded(&postgres),
+        embedded(&sqlite),
+    );
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("migrations.rs");
+    fs::write(out, code).expect("Couldn't write the migration list");
+}
+
+// Every directory with an `up.sql`, oldest first
+fn migrations(dir: &str) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("Couldn't read migrations")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("up.sql").exists())
+        .map(|path| fs::canonicalize(path).expect("Couldn't find a migration"))
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+fn name(dir: &Path) -> String {
+    dir.file_name().unwrap().to_string_lossy().to_string()
+}
+
+// Diesel records a migration by its directory name up to the first `_`, without dashes
+fn version(dir: &Path) -> String {
+    name(dir).split('_').next().unwrap_or("").replace('-', "")
+}
+
+fn versions(dirs: &[PathBuf]) -> Vec<String> {
+    dirs.iter().map(|dir| version(dir)).collect()
+}
+
+fn embedded(dirs: &[PathBuf]) -> String {
+    dirs.iter()
+        .map(|dir| {
+            format!(
+                "    EmbeddedMigration {{ name: {:?}, version: {:?}, up: include_str!({:?}), down: include_str!({:?}) }},\n",
+                name(dir),
+                version(dir),
+                dir.join("up.sql"),
+                dir.join("down.sql"),
+            )
+        })
+        .collect()
+}
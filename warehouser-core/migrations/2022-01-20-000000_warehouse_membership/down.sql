@@ -0,0 +1,19 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE warehouses ADD COLUMN items INTEGER[] NOT NULL DEFAULT '{}';
+
+UPDATE warehouses
+SET items = owned.items
+FROM (
+    SELECT warehouse, array_agg(id ORDER BY id) AS items
+    FROM inventory
+    WHERE warehouse IS NOT NULL
+    GROUP BY warehouse
+) AS owned
+WHERE warehouses.id = owned.warehouse;
+
+ALTER TABLE warehouses ALTER COLUMN items DROP DEFAULT;
+
+DROP INDEX inventory_warehouse_idx;
+
+ALTER TABLE inventory DROP CONSTRAINT inventory_warehouse_fkey;
@@ -0,0 +1,22 @@
+-- Make `inventory.warehouse` the single source of truth for warehouse membership
+
+-- Carry over memberships that only the warehouse knew about
+UPDATE inventory
+SET warehouse = warehouses.id
+FROM warehouses
+WHERE inventory.warehouse IS NULL
+    AND inventory.id = ANY(warehouses.items);
+
+-- Items cannot belong to a warehouse that does not exist
+UPDATE inventory
+SET warehouse = NULL
+WHERE warehouse IS NOT NULL
+    AND warehouse NOT IN (SELECT id FROM warehouses);
+
+ALTER TABLE inventory
+    ADD CONSTRAINT inventory_warehouse_fkey
+    FOREIGN KEY (warehouse) REFERENCES warehouses (id);
+
+CREATE INDEX inventory_warehouse_idx ON inventory (warehouse);
+
+ALTER TABLE warehouses DROP COLUMN items;
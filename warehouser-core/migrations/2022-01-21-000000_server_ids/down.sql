@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE warehouses ALTER COLUMN id DROP IDENTITY;
+ALTER TABLE inventory ALTER COLUMN id DROP IDENTITY;
@@ -0,0 +1,8 @@
+-- Let the database pick ids for new items and warehouses
+
+ALTER TABLE inventory ALTER COLUMN id ADD GENERATED BY DEFAULT AS IDENTITY;
+ALTER TABLE warehouses ALTER COLUMN id ADD GENERATED BY DEFAULT AS IDENTITY;
+
+-- Start counting after any ids that clients have already picked
+SELECT setval(pg_get_serial_sequence('inventory', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM inventory;
+SELECT setval(pg_get_serial_sequence('warehouses', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM warehouses;
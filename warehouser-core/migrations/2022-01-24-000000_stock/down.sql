@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+-- Quantities cannot be represented without the stock table and are lost
+
+ALTER TABLE inventory ADD COLUMN warehouse INTEGER NULL REFERENCES warehouses (id);
+
+UPDATE inventory
+SET warehouse = stock.warehouse
+FROM stock
+WHERE inventory.id = stock.item;
+
+CREATE INDEX inventory_warehouse_idx ON inventory (warehouse);
+
+DROP TABLE stock;
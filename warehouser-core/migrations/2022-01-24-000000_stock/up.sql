@@ -0,0 +1,18 @@
+-- Track how many units of an item a warehouse has on hand
+-- An item is stocked by at most one warehouse, this replaces `inventory.warehouse`
+
+CREATE TABLE stock (
+    item INTEGER PRIMARY KEY REFERENCES inventory (id) ON DELETE CASCADE,
+    warehouse INTEGER NOT NULL REFERENCES warehouses (id),
+    quantity INTEGER NOT NULL CHECK (quantity > 0)
+);
+
+CREATE INDEX stock_warehouse_idx ON stock (warehouse);
+
+-- Every existing row was a single physical unit
+INSERT INTO stock (item, warehouse, quantity)
+SELECT id, warehouse, 1
+FROM inventory
+WHERE warehouse IS NOT NULL;
+
+ALTER TABLE inventory DROP COLUMN warehouse;
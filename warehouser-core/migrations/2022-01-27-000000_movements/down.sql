@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE movements;
+DROP FUNCTION movements_append_only();
+DROP TYPE movement_reason;
@@ -0,0 +1,29 @@
+-- An append-only record of every change to stock
+
+CREATE TYPE movement_reason AS ENUM ('Received', 'Removed', 'ItemDeleted', 'WarehouseDeleted');
+
+-- No foreign keys, history outlives the items and warehouses it mentions
+CREATE TABLE movements (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    item INTEGER NOT NULL,
+    from_warehouse INTEGER NULL,
+    to_warehouse INTEGER NULL,
+    quantity INTEGER NOT NULL CHECK (quantity >= 0),
+    reason movement_reason NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    actor TEXT NOT NULL
+);
+
+CREATE INDEX movements_item_idx ON movements (item);
+CREATE INDEX movements_from_warehouse_idx ON movements (from_warehouse);
+CREATE INDEX movements_to_warehouse_idx ON movements (to_warehouse);
+
+CREATE FUNCTION movements_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'movements are append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER movements_append_only
+    BEFORE UPDATE OR DELETE OR TRUNCATE ON movements
+    FOR EACH STATEMENT EXECUTE PROCEDURE movements_append_only();
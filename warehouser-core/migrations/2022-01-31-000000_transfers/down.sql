@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+-- Postgres can't drop a value from an enum, so the type is rebuilt without it.
+-- This fails once a transfer has been recorded, the ledger can't be rewritten
+
+ALTER TYPE movement_reason RENAME TO movement_reason_old;
+
+CREATE TYPE movement_reason AS ENUM ('Received', 'Removed', 'ItemDeleted', 'WarehouseDeleted');
+
+ALTER TABLE movements
+    ALTER COLUMN reason TYPE movement_reason USING reason::text::movement_reason;
+
+DROP TYPE movement_reason_old;
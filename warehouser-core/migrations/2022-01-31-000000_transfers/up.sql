@@ -0,0 +1,3 @@
+-- Items moved straight from one warehouse to another
+
+ALTER TYPE movement_reason ADD VALUE 'Transferred';
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE warehouses DROP CONSTRAINT warehouses_id_check;
+
+ALTER TABLE inventory
+    DROP CONSTRAINT inventory_id_check,
+    DROP CONSTRAINT inventory_dimensions_check,
+    DROP CONSTRAINT inventory_value_check,
+    DROP CONSTRAINT inventory_weight_check;
@@ -0,0 +1,14 @@
+-- The same rules the service layer checks, so the database enforces them too
+-- This fails if existing rows break the rules, fix them before running it
+
+ALTER TABLE inventory
+    ADD CONSTRAINT inventory_weight_check CHECK (weight >= 0),
+    ADD CONSTRAINT inventory_value_check CHECK (value >= 0),
+    ADD CONSTRAINT inventory_dimensions_check CHECK (
+        (dimensions).width >= 1
+        AND (dimensions).height >= 1
+        AND (dimensions).depth >= 1
+    );
+
+ALTER TABLE inventory ADD CONSTRAINT inventory_id_check CHECK (id >= 1);
+ALTER TABLE warehouses ADD CONSTRAINT warehouses_id_check CHECK (id >= 1);
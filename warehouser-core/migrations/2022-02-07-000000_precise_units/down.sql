@@ -0,0 +1,52 @@
+-- This file should undo anything in `up.sql`
+-- Precision is lost: weights and values are rounded, dimensions are rounded up to whole metres,
+-- and currencies are dropped. Rows that don't fit in the old SMALLINT columns make this fail
+
+CREATE TYPE dimensions AS (
+    width SMALLINT,
+    height SMALLINT,
+    depth SMALLINT
+);
+
+ALTER TABLE inventory
+    DROP CONSTRAINT inventory_dimensions_check,
+    DROP CONSTRAINT inventory_value_check,
+    DROP CONSTRAINT inventory_weight_check;
+
+ALTER TABLE inventory
+    ADD COLUMN weight SMALLINT,
+    ADD COLUMN value_int SMALLINT,
+    ADD COLUMN dimensions dimensions;
+
+UPDATE inventory SET
+    weight = ROUND(weight_g / 1000.0),
+    value_int = ROUND((value).amount),
+    dimensions = ROW(
+        CEIL((dimensions_mm).width / 1000.0),
+        CEIL((dimensions_mm).height / 1000.0),
+        CEIL((dimensions_mm).depth / 1000.0)
+    )::dimensions;
+
+ALTER TABLE inventory
+    DROP COLUMN weight_g,
+    DROP COLUMN value,
+    DROP COLUMN dimensions_mm;
+
+ALTER TABLE inventory RENAME COLUMN value_int TO value;
+
+DROP TYPE dimensions_mm;
+DROP TYPE money_value;
+
+ALTER TABLE inventory
+    ALTER COLUMN weight SET NOT NULL,
+    ALTER COLUMN value SET NOT NULL,
+    ALTER COLUMN dimensions SET NOT NULL;
+
+ALTER TABLE inventory
+    ADD CONSTRAINT inventory_weight_check CHECK (weight >= 0),
+    ADD CONSTRAINT inventory_value_check CHECK (value >= 0),
+    ADD CONSTRAINT inventory_dimensions_check CHECK (
+        (dimensions).width >= 1
+        AND (dimensions).height >= 1
+        AND (dimensions).depth >= 1
+    );
@@ -0,0 +1,59 @@
+-- Weight moves to grams, value to an exact amount with a currency,
+-- and dimensions to millimetres. Existing values are converted exactly,
+-- values recorded before this migration are assumed to be in USD
+
+CREATE TYPE money_value AS (
+    amount NUMERIC(19, 4),
+    currency TEXT
+);
+
+CREATE TYPE dimensions_mm AS (
+    width INTEGER,
+    height INTEGER,
+    depth INTEGER
+);
+
+ALTER TABLE inventory
+    DROP CONSTRAINT inventory_dimensions_check,
+    DROP CONSTRAINT inventory_value_check,
+    DROP CONSTRAINT inventory_weight_check;
+
+ALTER TABLE inventory
+    ADD COLUMN weight_g BIGINT,
+    ADD COLUMN value_money money_value,
+    ADD COLUMN dimensions_mm dimensions_mm;
+
+UPDATE inventory SET
+    weight_g = weight::BIGINT * 1000,
+    value_money = ROW(value, 'USD')::money_value,
+    dimensions_mm = ROW(
+        (dimensions).width * 1000,
+        (dimensions).height * 1000,
+        (dimensions).depth * 1000
+    )::dimensions_mm;
+
+ALTER TABLE inventory
+    DROP COLUMN weight,
+    DROP COLUMN value,
+    DROP COLUMN dimensions;
+
+ALTER TABLE inventory RENAME COLUMN value_money TO value;
+
+DROP TYPE dimensions;
+
+ALTER TABLE inventory
+    ALTER COLUMN weight_g SET NOT NULL,
+    ALTER COLUMN value SET NOT NULL,
+    ALTER COLUMN dimensions_mm SET NOT NULL;
+
+ALTER TABLE inventory
+    ADD CONSTRAINT inventory_weight_check CHECK (weight_g >= 0),
+    ADD CONSTRAINT inventory_value_check CHECK (
+        (value).amount >= 0
+        AND (value).currency ~ '^[A-Z]{3}$'
+    ),
+    ADD CONSTRAINT inventory_dimensions_check CHECK (
+        (dimensions_mm).width >= 1
+        AND (dimensions_mm).height >= 1
+        AND (dimensions_mm).depth >= 1
+    );
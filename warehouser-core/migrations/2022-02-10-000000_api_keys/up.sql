@@ -0,0 +1,13 @@
+-- Clients authenticate with an API key, only a hash of each key is kept
+
+CREATE TYPE api_role AS ENUM ('ReadOnly', 'Operator', 'Admin');
+
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY GENERATED BY DEFAULT AS IDENTITY,
+    name TEXT NOT NULL,
+    role api_role NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE, -- SHA-256 of the key, hex encoded
+    prefix TEXT NOT NULL,          -- The start of the key, so people can tell keys apart
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ NULL
+);
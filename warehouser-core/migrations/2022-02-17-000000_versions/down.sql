@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER stock_versions ON stock;
+DROP FUNCTION stock_versions();
+DROP TRIGGER inventory_version ON inventory;
+DROP FUNCTION inventory_version();
+
+ALTER TABLE warehouses DROP COLUMN version;
+ALTER TABLE inventory DROP COLUMN version;
@@ -0,0 +1,40 @@
+-- Items and warehouses count their changes, the count is sent to clients as an ETag.
+-- An item changes with its own columns and with its stock,
+-- a warehouse changes when an item arrives or leaves
+
+ALTER TABLE inventory ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE warehouses ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+CREATE FUNCTION inventory_version() RETURNS trigger AS $$
+BEGIN
+    NEW.version := OLD.version + 1;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Not `version` itself, so the stock trigger's bump isn't counted twice
+CREATE TRIGGER inventory_version
+    BEFORE UPDATE OF transport, weight_g, value, dimensions_mm ON inventory
+    FOR EACH ROW EXECUTE PROCEDURE inventory_version();
+
+CREATE FUNCTION stock_versions() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        UPDATE inventory SET version = version + 1 WHERE id = OLD.item;
+        UPDATE warehouses SET version = version + 1 WHERE id = OLD.warehouse;
+        RETURN NULL;
+    END IF;
+
+    UPDATE inventory SET version = version + 1 WHERE id = NEW.item;
+    IF TG_OP = 'INSERT' THEN
+        UPDATE warehouses SET version = version + 1 WHERE id = NEW.warehouse;
+    ELSIF NEW.warehouse <> OLD.warehouse THEN
+        UPDATE warehouses SET version = version + 1 WHERE id IN (OLD.warehouse, NEW.warehouse);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER stock_versions
+    AFTER INSERT OR UPDATE OR DELETE ON stock
+    FOR EACH ROW EXECUTE PROCEDURE stock_versions();
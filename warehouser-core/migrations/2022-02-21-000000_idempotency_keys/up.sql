@@ -0,0 +1,15 @@
+-- Changes made with an `Idempotency-Key`, and the response each got, so a retry is sent it again
+
+CREATE TABLE idempotency_keys (
+    key_id INTEGER NOT NULL REFERENCES api_keys (id) ON DELETE CASCADE, -- Keys are only unique per API key
+    idempotency_key TEXT NOT NULL,
+    fingerprint TEXT NOT NULL, -- SHA-256 of the request's method, path and body, hex encoded
+    status INTEGER NULL,       -- The response, missing while the request is being handled
+    headers TEXT NULL,         -- A JSON list of [name, value] pairs
+    body BYTEA NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (key_id, idempotency_key)
+);
+
+-- Expired keys are deleted by age
+CREATE INDEX idempotency_keys_created_at_idx ON idempotency_keys (created_at);
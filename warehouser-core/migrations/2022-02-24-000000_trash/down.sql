@@ -0,0 +1,23 @@
+-- This file should undo anything in `up.sql`
+-- Whatever is in the trash is deleted for good, the way it would have been without one.
+-- Like `transfers`, this fails once a restore has been recorded, the ledger can't be rewritten
+
+ALTER TYPE movement_reason RENAME TO movement_reason_old;
+
+CREATE TYPE movement_reason AS ENUM (
+    'Received', 'Removed', 'ItemDeleted', 'WarehouseDeleted', 'Transferred'
+);
+
+ALTER TABLE movements
+    ALTER COLUMN reason TYPE movement_reason USING reason::text::movement_reason;
+
+DROP TYPE movement_reason_old;
+
+DELETE FROM inventory WHERE deleted_at IS NOT NULL;
+DELETE FROM warehouses WHERE deleted_at IS NOT NULL;
+
+DROP INDEX warehouses_deleted_at_idx;
+DROP INDEX inventory_deleted_at_idx;
+
+ALTER TABLE warehouses DROP COLUMN deleted_at;
+ALTER TABLE inventory DROP COLUMN deleted_at;
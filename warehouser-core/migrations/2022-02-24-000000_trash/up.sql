@@ -0,0 +1,10 @@
+-- Deleted items and warehouses go to the trash, where they can be restored until they're purged
+
+ALTER TABLE inventory ADD COLUMN deleted_at TIMESTAMPTZ NULL;
+ALTER TABLE warehouses ADD COLUMN deleted_at TIMESTAMPTZ NULL;
+
+-- The trash is listed and purged by when things were deleted
+CREATE INDEX inventory_deleted_at_idx ON inventory (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX warehouses_deleted_at_idx ON warehouses (deleted_at) WHERE deleted_at IS NOT NULL;
+
+ALTER TYPE movement_reason ADD VALUE 'Restored';
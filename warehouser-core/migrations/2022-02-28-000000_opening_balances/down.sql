@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+-- The ledger is append-only, the opening balances stay in it like any other movement
+
+SELECT 1;
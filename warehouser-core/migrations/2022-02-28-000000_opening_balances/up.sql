@@ -0,0 +1,8 @@
+-- Stock from before the ledger was never recorded in it, so an item held by a warehouse
+-- with no movements at all is received there once, as it stands
+
+INSERT INTO movements (item, from_warehouse, to_warehouse, quantity, reason, actor)
+SELECT stock.item, NULL, stock.warehouse, stock.quantity, 'Received', 'opening balance'
+FROM stock
+WHERE NOT EXISTS (SELECT 1 FROM movements WHERE movements.item = stock.item)
+ORDER BY stock.item;
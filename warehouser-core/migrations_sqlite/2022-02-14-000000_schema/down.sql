@@ -0,0 +1,5 @@
+DROP TABLE api_keys;
+DROP TABLE movements;
+DROP TABLE stock;
+DROP TABLE inventory;
+DROP TABLE warehouses;
@@ -0,0 +1,65 @@
+-- The same schema as `migrations/`, for SQLite
+-- SQLite has no enum or composite types, so enums are checked text
+-- and the parts of a composite get a column each
+
+CREATE TABLE warehouses (
+    id INTEGER PRIMARY KEY AUTOINCREMENT CHECK (id >= 1)
+);
+
+CREATE TABLE inventory (
+    id INTEGER PRIMARY KEY AUTOINCREMENT CHECK (id >= 1),
+    transport TEXT NOT NULL CHECK (transport IN ('Air', 'Sea', 'Land')),
+    weight_g INTEGER NOT NULL CHECK (weight_g >= 0),
+    value_amount INTEGER NOT NULL CHECK (value_amount >= 0), -- Ten-thousandths, like NUMERIC(19, 4)
+    value_currency TEXT NOT NULL CHECK (
+        length(value_currency) = 3 AND value_currency NOT GLOB '*[^A-Z]*'
+    ),
+    width_mm INTEGER NOT NULL CHECK (width_mm >= 1),
+    height_mm INTEGER NOT NULL CHECK (height_mm >= 1),
+    depth_mm INTEGER NOT NULL CHECK (depth_mm >= 1)
+);
+
+CREATE TABLE stock (
+    item INTEGER PRIMARY KEY REFERENCES inventory (id) ON DELETE CASCADE,
+    warehouse INTEGER NOT NULL REFERENCES warehouses (id),
+    quantity INTEGER NOT NULL CHECK (quantity > 0)
+);
+
+CREATE INDEX stock_warehouse_idx ON stock (warehouse);
+
+CREATE TABLE movements (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    item INTEGER NOT NULL,
+    from_warehouse INTEGER NULL,
+    to_warehouse INTEGER NULL,
+    quantity INTEGER NOT NULL CHECK (quantity >= 0),
+    reason TEXT NOT NULL CHECK (
+        reason IN ('Received', 'Removed', 'ItemDeleted', 'WarehouseDeleted', 'Transferred')
+    ),
+    created_at TIMESTAMP NOT NULL,
+    actor TEXT NOT NULL
+);
+
+CREATE INDEX movements_item_idx ON movements (item);
+CREATE INDEX movements_from_warehouse_idx ON movements (from_warehouse);
+CREATE INDEX movements_to_warehouse_idx ON movements (to_warehouse);
+
+CREATE TRIGGER movements_no_update BEFORE UPDATE ON movements
+BEGIN
+    SELECT RAISE(ABORT, 'movements are append-only');
+END;
+
+CREATE TRIGGER movements_no_delete BEFORE DELETE ON movements
+BEGIN
+    SELECT RAISE(ABORT, 'movements are append-only');
+END;
+
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('ReadOnly', 'Operator', 'Admin')),
+    key_hash TEXT NOT NULL UNIQUE, -- SHA-256 of the key, hex encoded
+    prefix TEXT NOT NULL,          -- The start of the key, so people can tell keys apart
+    created_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP NULL
+);
@@ -0,0 +1,7 @@
+DROP TRIGGER stock_delete_versions;
+DROP TRIGGER stock_update_versions;
+DROP TRIGGER stock_insert_versions;
+DROP TRIGGER inventory_version;
+
+ALTER TABLE warehouses DROP COLUMN version;
+ALTER TABLE inventory DROP COLUMN version;
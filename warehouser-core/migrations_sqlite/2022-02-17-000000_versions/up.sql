@@ -0,0 +1,31 @@
+-- The same versions as `migrations/`, for SQLite
+-- Triggers don't fire themselves again, so an item's own trigger can bump its row
+
+ALTER TABLE inventory ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE warehouses ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+CREATE TRIGGER inventory_version
+    AFTER UPDATE OF transport, weight_g, value_amount, value_currency, width_mm, height_mm, depth_mm
+    ON inventory
+BEGIN
+    UPDATE inventory SET version = OLD.version + 1 WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER stock_insert_versions AFTER INSERT ON stock
+BEGIN
+    UPDATE inventory SET version = version + 1 WHERE id = NEW.item;
+    UPDATE warehouses SET version = version + 1 WHERE id = NEW.warehouse;
+END;
+
+CREATE TRIGGER stock_update_versions AFTER UPDATE ON stock
+BEGIN
+    UPDATE inventory SET version = version + 1 WHERE id = NEW.item;
+    UPDATE warehouses SET version = version + 1
+    WHERE NEW.warehouse <> OLD.warehouse AND id IN (OLD.warehouse, NEW.warehouse);
+END;
+
+CREATE TRIGGER stock_delete_versions AFTER DELETE ON stock
+BEGIN
+    UPDATE inventory SET version = version + 1 WHERE id = OLD.item;
+    UPDATE warehouses SET version = version + 1 WHERE id = OLD.warehouse;
+END;
@@ -0,0 +1,42 @@
+-- Whatever is in the trash is deleted for good, and the ledger is copied back
+-- to a table without 'Restored', which fails once a restore has been recorded
+
+CREATE TABLE movements_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    item INTEGER NOT NULL,
+    from_warehouse INTEGER NULL,
+    to_warehouse INTEGER NULL,
+    quantity INTEGER NOT NULL CHECK (quantity >= 0),
+    reason TEXT NOT NULL CHECK (
+        reason IN ('Received', 'Removed', 'ItemDeleted', 'WarehouseDeleted', 'Transferred')
+    ),
+    created_at TIMESTAMP NOT NULL,
+    actor TEXT NOT NULL
+);
+
+INSERT INTO movements_old SELECT * FROM movements;
+DROP TABLE movements;
+ALTER TABLE movements_old RENAME TO movements;
+
+CREATE INDEX movements_item_idx ON movements (item);
+CREATE INDEX movements_from_warehouse_idx ON movements (from_warehouse);
+CREATE INDEX movements_to_warehouse_idx ON movements (to_warehouse);
+
+CREATE TRIGGER movements_no_update BEFORE UPDATE ON movements
+BEGIN
+    SELECT RAISE(ABORT, 'movements are append-only');
+END;
+
+CREATE TRIGGER movements_no_delete BEFORE DELETE ON movements
+BEGIN
+    SELECT RAISE(ABORT, 'movements are append-only');
+END;
+
+DELETE FROM inventory WHERE deleted_at IS NOT NULL;
+DELETE FROM warehouses WHERE deleted_at IS NOT NULL;
+
+DROP INDEX warehouses_deleted_at_idx;
+DROP INDEX inventory_deleted_at_idx;
+
+ALTER TABLE warehouses DROP COLUMN deleted_at;
+ALTER TABLE inventory DROP COLUMN deleted_at;
@@ -0,0 +1,41 @@
+-- The same trash as `migrations/`, for SQLite
+
+ALTER TABLE inventory ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE warehouses ADD COLUMN deleted_at TIMESTAMP NULL;
+
+CREATE INDEX inventory_deleted_at_idx ON inventory (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX warehouses_deleted_at_idx ON warehouses (deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- A CHECK can't be changed, so the ledger is copied to a table that allows 'Restored'.
+-- Dropping a table doesn't fire its triggers, they're dropped along with it
+
+CREATE TABLE movements_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    item INTEGER NOT NULL,
+    from_warehouse INTEGER NULL,
+    to_warehouse INTEGER NULL,
+    quantity INTEGER NOT NULL CHECK (quantity >= 0),
+    reason TEXT NOT NULL CHECK (
+        reason IN ('Received', 'Removed', 'ItemDeleted', 'WarehouseDeleted', 'Transferred', 'Restored')
+    ),
+    created_at TIMESTAMP NOT NULL,
+    actor TEXT NOT NULL
+);
+
+INSERT INTO movements_new SELECT * FROM movements;
+DROP TABLE movements;
+ALTER TABLE movements_new RENAME TO movements;
+
+CREATE INDEX movements_item_idx ON movements (item);
+CREATE INDEX movements_from_warehouse_idx ON movements (from_warehouse);
+CREATE INDEX movements_to_warehouse_idx ON movements (to_warehouse);
+
+CREATE TRIGGER movements_no_update BEFORE UPDATE ON movements
+BEGIN
+    SELECT RAISE(ABORT, 'movements are append-only');
+END;
+
+CREATE TRIGGER movements_no_delete BEFORE DELETE ON movements
+BEGIN
+    SELECT RAISE(ABORT, 'movements are append-only');
+END;
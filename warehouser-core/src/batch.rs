@@ -0,0 +1,182 @@
+/// Batches of changes
+/// `POST /api/batch` takes a list of operations, each standing in for a request to one of the
+/// endpoints that change things, and runs them in order in a single transaction.
+/// Items in a batch are read and written in the version named by `Content-Type` and `Accept`
+// The operations are run by `service::run_batch`, with the same service functions as the endpoints
+use http::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::conditional::{IfMatch, Tags};
+use crate::models::{
+    Error, InventoryItem, NewInventoryItem, NewWarehouse, Problem, Transfer, Warehouse,
+};
+use crate::versions::{ApiVersion, ItemUpdate, ItemV1, NewItemV1, Upgrade};
+
+/// One step of a batch, named by its `op`
+///
+/// A `version` is checked like `If-Match`, leave it out to change whatever version is there
+// Documented with its version 1 items, the OpenAPI document works out the version 2 schema
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation<New = NewInventoryItem, Item = ItemUpdate> {
+    CreateItem {
+        #[schema(value_type = NewItemV1)]
+        item: New,
+    },
+    UpdateItem {
+        #[schema(value_type = ItemV1)]
+        item: Item,
+        version: Option<i32>,
+    },
+    DeleteItem {
+        id: i32,
+        version: Option<i32>,
+    },
+    CreateWarehouse {
+        warehouse: NewWarehouse,
+    },
+    DeleteWarehouse {
+        id: i32,
+        version: Option<i32>,
+    },
+    /// Add `quantity` units of `item` to `warehouse`, one unit when it's missing
+    AddItem {
+        warehouse: i32,
+        item: i32,
+        quantity: Option<i32>,
+    },
+    /// Remove `quantity` units of `item` from `warehouse`, one unit when it's missing
+    RemoveItem {
+        warehouse: i32,
+        item: i32,
+        quantity: Option<i32>,
+    },
+    Transfer {
+        from: i32,
+        to: i32,
+        items: Vec<i32>,
+    },
+}
+
+impl Operation {
+    /// The request the operation stands in for, so it's allowed the same roles
+    pub fn endpoint(&self) -> (Method, String) {
+        match self {
+            Operation::CreateItem { .. } => (Method::POST, "/api/item".to_string()),
+            Operation::UpdateItem { .. } => (Method::PUT, "/api/item".to_string()),
+            Operation::DeleteItem { id, .. } => (Method::DELETE, format!("/api/item/{id}")),
+            Operation::CreateWarehouse { .. } => (Method::POST, "/api/warehouse".to_string()),
+            Operation::DeleteWarehouse { id, .. } => {
+                (Method::DELETE, format!("/api/warehouse/{id}"))
+            }
+            Operation::AddItem { warehouse, .. } => {
+                (Method::POST, format!("/api/warehouse/{warehouse}/add"))
+            }
+            Operation::RemoveItem { warehouse, .. } => {
+                (Method::POST, format!("/api/warehouse/{warehouse}/remove"))
+            }
+            Operation::Transfer { from, .. } => {
+                (Method::POST, format!("/api/warehouse/{from}/transfer"))
+            }
+        }
+    }
+}
+
+/// The `If-Match` an operation's `version` stands for
+pub fn if_match(version: Option<i32>) -> IfMatch {
+    IfMatch(version.map_or(Tags::Any, |version| Tags::Versions(vec![version])))
+}
+
+impl From<Operation<NewItemV1, ItemV1>> for Operation {
+    fn from(op: Operation<NewItemV1, ItemV1>) -> Self {
+        match op {
+            Operation::CreateItem { item } => Operation::CreateItem { item: item.into() },
+            Operation::UpdateItem { item, version } => Operation::UpdateItem {
+                item: item.into(),
+                version,
+            },
+            Operation::DeleteItem { id, version } => Operation::DeleteItem { id, version },
+            Operation::CreateWarehouse { warehouse } => Operation::CreateWarehouse { warehouse },
+            Operation::DeleteWarehouse { id, version } => {
+                Operation::DeleteWarehouse { id, version }
+            }
+            Operation::AddItem {
+                warehouse,
+                item,
+                quantity,
+            } => Operation::AddItem {
+                warehouse,
+                item,
+                quantity,
+            },
+            Operation::RemoveItem {
+                warehouse,
+                item,
+                quantity,
+            } => Operation::RemoveItem {
+                warehouse,
+                item,
+                quantity,
+            },
+            Operation::Transfer { from, to, items } => Operation::Transfer { from, to, items },
+        }
+    }
+}
+
+/// The operations of a batch, in the order they're run
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct Batch<New = NewInventoryItem, Item = ItemUpdate>(pub Vec<Operation<New, Item>>);
+
+impl From<Batch<NewItemV1, ItemV1>> for Batch {
+    fn from(batch: Batch<NewItemV1, ItemV1>) -> Self {
+        Batch(batch.0.into_iter().map(Into::into).collect())
+    }
+}
+
+impl Upgrade for Batch {
+    type V1 = Batch<NewItemV1, ItemV1>;
+}
+
+/// What an operation made or changed
+#[derive(Debug)]
+pub enum Output {
+    Created(InventoryItem),
+    Item(InventoryItem),
+    CreatedWarehouse(Warehouse),
+    Warehouse(Warehouse),
+    Transfer(Transfer),
+}
+
+/// How an operation went, as the request it stands in for would have been answered
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OperationResult {
+    pub status: u16,
+    /// The response's body, a problem document when the operation failed
+    #[schema(value_type = Object)]
+    pub body: serde_json::Value,
+}
+
+impl OperationResult {
+    pub fn new(version: ApiVersion, result: Result<Output, Error>) -> Self {
+        let (status, body) = match result {
+            Ok(Output::Created(item)) => (StatusCode::CREATED, to_value(version.item(item))),
+            Ok(Output::Item(item)) => (StatusCode::OK, to_value(version.item(item))),
+            Ok(Output::CreatedWarehouse(whouse)) => (StatusCode::CREATED, to_value(whouse)),
+            Ok(Output::Warehouse(whouse)) => (StatusCode::OK, to_value(whouse)),
+            Ok(Output::Transfer(transfer)) => (StatusCode::OK, to_value(transfer)),
+            Err(e) => (e.status(), to_value(Problem::from(&e))),
+        };
+
+        OperationResult {
+            status: status.as_u16(),
+            body,
+        }
+    }
+}
+
+// The models always serialize
+fn to_value(body: impl Serialize) -> serde_json::Value {
+    serde_json::to_value(body).unwrap_or_default()
+}
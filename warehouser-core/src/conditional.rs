@@ -0,0 +1,186 @@
+/// Conditional requests
+/// Items and warehouses have a `version` that goes up every time they change, sent as their `ETag`.
+/// Changing or deleting one needs the version it was read at in `If-Match`,
+/// so two clients editing the same item can't silently overwrite each other
+// Versions are counted by triggers, see the `versions` migrations (and `memory`, by hand)
+use crate::models::{Error, Result};
+
+/// The `ETag` of a row at `version`
+pub fn etag(version: i32) -> String {
+    format!("\"{version}\"")
+}
+
+// Marks the `ETag` of an item sent in version 2's shape
+const V2_SUFFIX: &str = "-v2";
+
+/// The `ETag` of an item at `version`, sent in version 2's shape
+///
+/// Each shape gets its own, so a client or cache holding one is never told it has the other.
+/// `If-Match` takes either, they name the same version of the row
+pub fn v2_etag(version: i32) -> String {
+    format!("\"{version}{V2_SUFFIX}\"")
+}
+
+/// The entity tags of an `If-Match` or `If-None-Match` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tags {
+    Any, // `*`
+    Versions(Vec<i32>),
+}
+
+impl Tags {
+    /// The versions in an `If-Match` header's values, `None` when it had none.
+    /// Weak tags are dropped, `If-Match` never matches them
+    pub fn parse<'a>(values: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let tags = entity_tags(values)?;
+        if tags.contains(&"*") {
+            return Some(Tags::Any);
+        }
+
+        // Tags we never handed out can't match, so they're dropped rather than rejected
+        let versions = tags
+            .into_iter()
+            .filter(|tag| !tag.starts_with("W/"))
+            .filter_map(|tag| {
+                let tag = tag.strip_prefix('"')?.strip_suffix('"')?;
+                tag.strip_suffix(V2_SUFFIX).unwrap_or(tag).parse().ok()
+            })
+            .collect();
+        Some(Tags::Versions(versions))
+    }
+
+    pub fn matches(&self, version: i32) -> bool {
+        match self {
+            Tags::Any => true,
+            Tags::Versions(versions) => versions.contains(&version),
+        }
+    }
+}
+
+/// `If-Match`, the versions a change may be made to
+#[derive(Debug, Clone)]
+pub struct IfMatch(pub Tags);
+
+impl IfMatch {
+    /// Fails unless the row, now at `version`, is one the client named
+    pub fn check(&self, version: i32) -> Result<()> {
+        if self.0.matches(version) {
+            Ok(())
+        } else {
+            Err(Error::PreconditionFailed { version })
+        }
+    }
+}
+
+/// `If-None-Match`, the `ETag`s of the copies the client already has, or `*`
+#[derive(Debug, Clone, Default)]
+pub struct IfNoneMatch(pub Option<Vec<String>>);
+
+impl IfNoneMatch {
+    /// Whether the client's copy is still current, so it can be sent `304 Not Modified`
+    ///
+    /// Compared weakly, as `If-None-Match` is, but whole,
+    /// so a copy in one version's shape doesn't match the other's `ETag`
+    pub fn matches(&self, etag: &str) -> bool {
+        self.0.as_ref().is_some_and(|tags| {
+            tags.iter()
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+        })
+    }
+}
+
+// The entity tags in a header's values, `None` when it had none
+fn entity_tags<'a>(values: impl IntoIterator<Item = &'a str>) -> Option<Vec<&'a str>> {
+    let tags: Vec<&str> = values
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    Some(tags).filter(|tags| !tags.is_empty())
+}
+
+// How handlers get their `IfMatch` and `IfNoneMatch`
+#[cfg(feature = "actix")]
+mod extract {
+    use std::future::{ready, Ready};
+
+    use actix_web::{
+        dev::Payload,
+        http::{header, HeaderName},
+        FromRequest, HttpRequest,
+    };
+
+    use super::{entity_tags, IfMatch, IfNoneMatch, Tags};
+    use crate::models::Error;
+
+    fn values(req: &HttpRequest, name: HeaderName) -> impl Iterator<Item = &str> {
+        req.headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+    }
+
+    /// Required, a change without it is turned down with `428 Precondition Required`
+    impl FromRequest for IfMatch {
+        type Error = actix_web::Error;
+        type Future = Ready<Result<Self, Self::Error>>;
+        type Config = ();
+
+        fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+            let tags = Tags::parse(values(req, header::IF_MATCH))
+                .map(IfMatch)
+                .ok_or_else(|| Error::PreconditionRequired.into());
+            ready(tags)
+        }
+    }
+
+    impl FromRequest for IfNoneMatch {
+        type Error = actix_web::Error;
+        type Future = Ready<Result<Self, Self::Error>>;
+        type Config = ();
+
+        fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+            let tags = entity_tags(values(req, header::IF_NONE_MATCH))
+                .map(|tags| tags.into_iter().map(str::to_string).collect());
+            ready(Ok(IfNoneMatch(tags)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_none_match(tags: &[&str]) -> IfNoneMatch {
+        IfNoneMatch(
+            entity_tags(tags.iter().copied())
+                .map(|tags| tags.into_iter().map(str::to_string).collect()),
+        )
+    }
+
+    #[test]
+    fn a_copy_in_one_shape_is_not_current_in_the_other() {
+        let v1 = if_none_match(&[&etag(3)]);
+        assert!(v1.matches(&etag(3)));
+        assert!(!v1.matches(&v2_etag(3)));
+
+        let v2 = if_none_match(&[&format!("W/{}", v2_etag(3))]);
+        assert!(v2.matches(&v2_etag(3)));
+        assert!(!v2.matches(&etag(3)));
+        assert!(!v2.matches(&v2_etag(4)));
+
+        assert!(if_none_match(&["*"]).matches(&v2_etag(3)));
+        assert!(!IfNoneMatch::default().matches(&etag(3)));
+    }
+
+    #[test]
+    fn if_match_takes_the_tag_of_either_shape_but_no_weak_ones() {
+        let tags = Tags::parse([format!("{}, {}", etag(3), v2_etag(4)).as_str()]).unwrap();
+        assert_eq!(tags, Tags::Versions(vec![3, 4]));
+
+        let weak = Tags::parse([format!("W/{}", etag(3)).as_str()]).unwrap();
+        assert_eq!(weak, Tags::Versions(vec![]));
+        assert_eq!(Tags::parse(["*"]), Some(Tags::Any));
+        assert_eq!(Tags::parse([" "]), None);
+    }
+}
@@ -0,0 +1,1102 @@
+use crate::diesel::ExpressionMethods;
+/// Wraps common database operations
+/// The Postgres `Repository`, these functions should not be used directly
+/// Instead, use the functions `crate::service`
+// Implementation note:
+// This code is fairly repetitive
+// it could fairly easily be reduced by creating a macro
+// or something else?
+use std::collections::HashMap;
+
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use diesel::connection::{Connection, TransactionManager};
+use diesel::dsl::{any, now, sql};
+use diesel::expression::{AsExpression, BoxableExpression};
+use diesel::pg::Pg;
+use diesel::query_builder::{BoxedSelectStatement, QueryFragment};
+use diesel::query_source::joins::{Join, JoinOn, JoinTo, LeftOuter};
+use diesel::sql_types::{Bool, Int4, Int8, Nullable, Numeric, Text, Timestamptz};
+use diesel::{
+    BoolExpressionMethods, NullableExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
+
+use crate::migrations::MIGRATIONS;
+use crate::models::{
+    ApiKey, ApiKeyRow, Error, IdempotentRequest, InventoryItem, Movement, NewInventoryItem,
+    NewMovement, PgDimensions, PgMoney, PgTransport, Result, Stock, StoredResponse, Warehouse,
+};
+use crate::pagination::{Cursor, ItemKey, ItemParams, KeyType, Sort, WarehouseFilter};
+use crate::repository::Repository;
+use crate::schema::{inventory, stock};
+
+// The sql types of the columns `InventoryItem` is loaded from
+type ItemSqlType = (
+    Int4,
+    Nullable<Int4>,
+    Int4,
+    Int8,
+    PgMoney,
+    PgTransport,
+    PgDimensions,
+    Int4,
+    Nullable<Timestamptz>,
+);
+
+type ItemSource = JoinOn<
+    Join<inventory::table, stock::table, LeftOuter>,
+    <inventory::table as JoinTo<stock::table>>::OnClause,
+>;
+
+type ItemQuery<'a> = BoxedSelectStatement<'a, ItemSqlType, ItemSource, Pg>;
+
+sql_function!(fn coalesce(x: Nullable<Int4>, y: Int4) -> Int4);
+
+// An item's warehouse and quantity live in the stock table
+// so every item we hand out is read through this join
+fn items<'a>() -> ItemQuery<'a> {
+    inventory::table
+        .left_join(stock::table)
+        .select((
+            inventory::id,
+            stock::warehouse.nullable(),
+            coalesce(stock::quantity.nullable(), 0),
+            inventory::weight_g,
+            inventory::value,
+            inventory::transport,
+            inventory::dimensions_mm,
+            inventory::version,
+            inventory::deleted_at,
+        ))
+        .into_boxed()
+}
+
+// The items that aren't in the trash
+fn live_items<'a>() -> ItemQuery<'a> {
+    items().filter(inventory::deleted_at.is_null())
+}
+
+pub fn get_items_by_id(conn: &PgConnection, limit: i64, ids: &[i32]) -> Result<Vec<InventoryItem>> {
+    live_items()
+        .limit(limit)
+        .filter(inventory::id.eq(any(ids)))
+        .get_results(conn)
+        .map_err(Into::into)
+}
+
+/// Items matching `params`, optionally only those in warehouse `w_id`,
+/// in `sort` order and starting after `after`.
+/// Returns up to one more item than the limit, so the caller can tell if there's another page
+pub fn list_items(
+    conn: &PgConnection,
+    params: &ItemParams,
+    w_id: Option<i32>,
+    sort: Sort<ItemKey>,
+    after: Option<&Cursor>,
+) -> Result<Vec<InventoryItem>> {
+    let mut query = if params.include_deleted() {
+        items()
+    } else {
+        live_items()
+    };
+
+    if let Some(w_id) = w_id {
+        query = query.filter(stock::warehouse.eq(w_id));
+    }
+
+    match params.warehouse {
+        Some(WarehouseFilter::None) => query = query.filter(stock::warehouse.nullable().is_null()),
+        Some(WarehouseFilter::Id(w_id)) => query = query.filter(stock::warehouse.eq(w_id)),
+        None => {}
+    }
+
+    if let Some(transport) = &params.transport {
+        query = query.filter(inventory::transport.eq(transport.clone()));
+    }
+
+    if let Some(currency) = &params.currency {
+        query = query.filter(compare::<Text, _>(
+            "(inventory.value).currency",
+            "=",
+            currency.clone(),
+        ));
+    }
+
+    // Weights are filtered in kg, but stored in grams
+    let grams = |kg: &Option<BigDecimal>| kg.as_ref().map(|kg| kg * BigDecimal::from(1000));
+    let ranges = [
+        (
+            ">",
+            grams(&params.weight_gt),
+            &params.value_gt,
+            params.quantity_gt,
+        ),
+        (
+            ">=",
+            grams(&params.weight_gte),
+            &params.value_gte,
+            params.quantity_gte,
+        ),
+        (
+            "<",
+            grams(&params.weight_lt),
+            &params.value_lt,
+            params.quantity_lt,
+        ),
+        (
+            "<=",
+            grams(&params.weight_lte),
+            &params.value_lte,
+            params.quantity_lte,
+        ),
+    ];
+
+    for (op, weight, value, quantity) in ranges {
+        if let Some(weight) = weight {
+            query = query.filter(compare::<Numeric, _>(ItemKey::Weight.sql(), op, weight));
+        }
+        if let Some(value) = value {
+            query = query.filter(compare::<Numeric, _>(
+                ItemKey::Value.sql(),
+                op,
+                value.clone(),
+            ));
+        }
+        if let Some(quantity) = quantity {
+            query = query.filter(compare::<Int4, _>(ItemKey::Quantity.sql(), op, quantity));
+        }
+    }
+
+    let dir = if sort.descending { "DESC" } else { "ASC" };
+
+    if let Some(after) = after {
+        query = query.filter(after_item(sort, after)?);
+    }
+
+    query
+        .order(sql::<Bool>(&format!(
+            "{} {dir}, inventory.id {dir}",
+            sort.key.sql()
+        )))
+        .limit(params.limit() + 1)
+        .get_results(conn)
+        .map_err(Into::into)
+}
+
+type ItemPredicate<'a> = Box<dyn BoxableExpression<ItemSource, Pg, SqlType = Bool> + 'a>;
+
+// `expr op value`, with `value` sent as a bind parameter
+// `expr` and `op` are always our own strings, never user input
+fn compare<'a, ST, T>(expr: &str, op: &str, value: T) -> ItemPredicate<'a>
+where
+    ST: 'a,
+    T: AsExpression<ST>,
+    T::Expression: QueryFragment<Pg> + 'a,
+{
+    Box::new(sql::<Bool>(&format!("{expr} {op} ")).bind::<ST, _>(value))
+}
+
+// Items that come after the cursor in `sort` order
+fn after_item<'a>(sort: Sort<ItemKey>, after: &Cursor) -> Result<ItemPredicate<'a>> {
+    let op = if sort.descending { "<" } else { ">" };
+    let start = sql::<Bool>(&format!("({}, inventory.id) {op} (", sort.key.sql()));
+    let invalid = || Error::InvalidRequest {
+        detail: "cursor is not valid".to_string(),
+    };
+
+    let predicate: ItemPredicate = match sort.key.key_type() {
+        KeyType::Int => {
+            let key: i64 = after.key.parse().map_err(|_| invalid())?;
+            Box::new(
+                start
+                    .bind::<Int8, _>(key)
+                    .sql(", ")
+                    .bind::<Int4, _>(after.id)
+                    .sql(")"),
+            )
+        }
+        KeyType::Decimal => {
+            let key = BigDecimal::from_str(&after.key).map_err(|_| invalid())?;
+            Box::new(
+                start
+                    .bind::<Numeric, _>(key)
+                    .sql(", ")
+                    .bind::<Int4, _>(after.id)
+                    .sql(")"),
+            )
+        }
+        KeyType::Text => Box::new(
+            start
+                .bind::<Text, _>(after.key.clone())
+                .sql(", ")
+                .bind::<Int4, _>(after.id)
+                .sql(")"),
+        ),
+    };
+
+    Ok(predicate)
+}
+
+pub fn get_item(conn: &PgConnection, id_: i32) -> Result<InventoryItem> {
+    live_items()
+        .filter(inventory::id.eq(id_))
+        .first(conn)
+        .map_err(Into::into)
+}
+
+// An item whether it's in the trash or not
+fn get_any_item(conn: &PgConnection, id_: i32) -> Result<InventoryItem> {
+    items()
+        .filter(inventory::id.eq(id_))
+        .first(conn)
+        .map_err(Into::into)
+}
+
+/// Insert an item's row, the item is not stocked by any warehouse
+pub fn insert_item(conn: &PgConnection, item: &NewInventoryItem) -> Result<InventoryItem> {
+    use crate::schema::inventory::dsl::*;
+
+    let inserted = diesel::insert_into(inventory)
+        .values(item.row())
+        .returning(id)
+        .get_result(conn)?;
+
+    get_item(conn, inserted)
+}
+
+/// Insert an item's row with a client-chosen id, the item is not stocked by any warehouse
+pub fn import_item(conn: &PgConnection, item: &InventoryItem) -> Result<InventoryItem> {
+    use crate::schema::inventory::dsl::*;
+
+    diesel::insert_into(inventory)
+        .values((id.eq(item.id), item.row()))
+        .execute(conn)?;
+
+    get_item(conn, item.id)
+}
+
+/// Move the id sequences past the ids clients picked,
+/// otherwise the database would eventually hand out the same id
+///
+/// `setval` isn't undone by a rollback, so this is left out of dry runs
+pub fn sync_id_sequences(conn: &PgConnection) -> Result<()> {
+    sync_id_sequence(conn, "inventory")?;
+    sync_id_sequence(conn, "warehouses")
+}
+
+// Only ever forwards, an id that was handed out, even to a row purged since, stays used
+fn sync_id_sequence(conn: &PgConnection, table: &'static str) -> Result<()> {
+    // `table` is always one of our own table names, never user input
+    let query = format!(
+        "SELECT setval(seq, GREATEST(MAX(id), pg_sequence_last_value(seq), 1)) \
+         FROM {table}, CAST(pg_get_serial_sequence('{table}', 'id') AS regclass) AS seq \
+         GROUP BY seq"
+    );
+
+    diesel::sql_query(query).execute(conn)?;
+    Ok(())
+}
+
+/// Update the columns stored on the item's row, its stock is left alone
+pub fn update_item(conn: &PgConnection, item: &InventoryItem) -> Result<InventoryItem> {
+    use crate::schema::inventory::dsl::*;
+
+    diesel::update(inventory)
+        .filter(id.eq(item.id))
+        .filter(deleted_at.is_null())
+        .set(item.row())
+        .execute(conn)?;
+
+    get_item(conn, item.id)
+}
+
+/// Move an item that's in no warehouse to the trash
+pub fn trash_item(conn: &PgConnection, id_: i32) -> Result<InventoryItem> {
+    use crate::schema::inventory::dsl::*;
+
+    let trashed = diesel::update(inventory)
+        .filter(id.eq(id_))
+        .filter(deleted_at.is_null())
+        .set(deleted_at.eq(now))
+        .execute(conn)?;
+
+    match trashed {
+        0 => Err(Error::NotFound),
+        _ => get_any_item(conn, id_),
+    }
+}
+
+/// Take an item back out of the trash, in no warehouse
+pub fn restore_item(conn: &PgConnection, id_: i32) -> Result<InventoryItem> {
+    use crate::schema::inventory::dsl::*;
+
+    let restored = diesel::update(inventory)
+        .filter(id.eq(id_))
+        .filter(deleted_at.is_not_null())
+        .set(deleted_at.eq(None::<DateTime<Utc>>))
+        .execute(conn)?;
+
+    match restored {
+        0 => Err(Error::NotFound),
+        _ => get_item(conn, id_),
+    }
+}
+
+/// Items in the trash, most recently deleted first
+pub fn get_trashed_items(conn: &PgConnection, limit: i64) -> Result<Vec<InventoryItem>> {
+    items()
+        .filter(inventory::deleted_at.is_not_null())
+        .order((inventory::deleted_at.desc(), inventory::id.desc()))
+        .limit(limit)
+        .get_results(conn)
+        .map_err(Into::into)
+}
+
+/// Delete the items moved to the trash before `before` for good, returns how many there were
+pub fn purge_items(conn: &PgConnection, before: DateTime<Utc>) -> Result<usize> {
+    use crate::schema::inventory::dsl::*;
+
+    diesel::delete(inventory.filter(deleted_at.lt(before)))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Lock an item's row for the rest of the current transaction
+pub fn get_item_for_update(conn: &PgConnection, id_: i32) -> Result<InventoryItem> {
+    use crate::schema::inventory::dsl::*;
+
+    // Postgres won't lock the nullable side of an outer join
+    // so the item's row is locked on its own before it's loaded.
+    // Stock is keyed by item, so this also guards the item's stock
+    inventory
+        .find(id_)
+        .filter(deleted_at.is_null())
+        .select(id)
+        .for_update()
+        .first::<i32>(conn)?;
+
+    get_item(conn, id_)
+}
+
+/// Every item in a warehouse
+pub fn get_warehouse_items(
+    conn: &PgConnection,
+    w_id: i32,
+    limit: i64,
+) -> Result<Vec<InventoryItem>> {
+    items()
+        .filter(stock::warehouse.eq(w_id))
+        .order(inventory::id)
+        .limit(limit)
+        .get_results(conn)
+        .map_err(Into::into)
+}
+
+/// Stock an item that is in no warehouse
+pub fn insert_stock(conn: &PgConnection, item_id: i32, w_id: i32, qty: i32) -> Result<Stock> {
+    use crate::schema::stock::dsl::*;
+
+    diesel::insert_into(stock)
+        .values(&Stock {
+            item: item_id,
+            warehouse: w_id,
+            quantity: qty,
+        })
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+/// Change how many units of an item are on hand
+pub fn set_stock_quantity(conn: &PgConnection, item_id: i32, qty: i32) -> Result<Stock> {
+    use crate::schema::stock::dsl::*;
+
+    diesel::update(stock)
+        .filter(item.eq(item_id))
+        .set(quantity.eq(qty))
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+/// Move all of an item's stock to another warehouse
+pub fn move_stock(conn: &PgConnection, item_id: i32, w_id: i32) -> Result<Stock> {
+    use crate::schema::stock::dsl::*;
+
+    diesel::update(stock)
+        .filter(item.eq(item_id))
+        .set(warehouse.eq(w_id))
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+/// Take an item out of its warehouse entirely
+pub fn delete_stock(conn: &PgConnection, item_id: i32) -> Result<Stock> {
+    use crate::schema::stock::dsl::*;
+
+    diesel::delete(stock)
+        .filter(item.eq(item_id))
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+pub fn insert_movement(conn: &PgConnection, movement: &NewMovement) -> Result<Movement> {
+    use crate::schema::movements::dsl::*;
+
+    diesel::insert_into(movements)
+        .values(movement)
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+/// Every movement of an item, oldest first
+pub fn get_item_movements(conn: &PgConnection, item_id: i32, limit: i64) -> Result<Vec<Movement>> {
+    use crate::schema::movements::dsl::*;
+
+    movements
+        .filter(item.eq(item_id))
+        .order(id)
+        .limit(limit)
+        .get_results(conn)
+        .map_err(Into::into)
+}
+
+/// The most recent movement of an item
+pub fn get_last_item_movement(conn: &PgConnection, item_id: i32) -> Result<Movement> {
+    use crate::schema::movements::dsl::*;
+
+    movements
+        .filter(item.eq(item_id))
+        .order(id.desc())
+        .first(conn)
+        .map_err(Into::into)
+}
+
+/// Every movement into or out of a warehouse, oldest first
+pub fn get_warehouse_movements(
+    conn: &PgConnection,
+    w_id: i32,
+    limit: i64,
+) -> Result<Vec<Movement>> {
+    use crate::schema::movements::dsl::*;
+
+    movements
+        .filter(from_warehouse.eq(w_id).or(to_warehouse.eq(w_id)))
+        .order(id)
+        .limit(limit)
+        .get_results(conn)
+        .map_err(Into::into)
+}
+
+/// Up to `limit` movements with an id above `after`, oldest first
+pub fn get_movements_after(conn: &PgConnection, after: i64, limit: i64) -> Result<Vec<Movement>> {
+    use crate::schema::movements::dsl::*;
+
+    movements
+        .filter(id.gt(after))
+        .order(id)
+        .limit(limit)
+        .get_results(conn)
+        .map_err(Into::into)
+}
+
+/// Every stock row, by item
+pub fn get_all_stock(conn: &PgConnection) -> Result<Vec<Stock>> {
+    use crate::schema::stock::dsl::*;
+
+    stock.order(item).get_results(conn).map_err(Into::into)
+}
+
+/// Remove every item from a warehouse, returns the number of items released
+pub fn clear_warehouse_items(conn: &PgConnection, w_id: i32) -> Result<usize> {
+    use crate::schema::stock::dsl::*;
+
+    diesel::delete(stock)
+        .filter(warehouse.eq(w_id))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+// The columns of a warehouse's row, its id, version, and when it was moved to the trash
+type WarehouseRow = (i32, i32, Option<DateTime<Utc>>);
+
+// Warehouses don't store their items,
+// so every warehouse we hand out is assembled from its row
+// and the items that point at it
+fn with_items(conn: &PgConnection, rows: Vec<WarehouseRow>) -> Result<Vec<Warehouse>> {
+    use crate::schema::stock::dsl::*;
+
+    let ids: Vec<i32> = rows.iter().map(|(id_, _, _)| *id_).collect();
+    let owned: Vec<(i32, i32)> = stock
+        .select((warehouse, item))
+        .filter(warehouse.eq(any(&ids)))
+        .order(item)
+        .load(conn)?;
+
+    let mut whouses: Vec<Warehouse> = rows
+        .into_iter()
+        .map(|(id_, version_, deleted_at_)| Warehouse {
+            id: id_,
+            items: Vec::new(),
+            version: version_,
+            deleted_at: deleted_at_,
+        })
+        .collect();
+
+    let index: HashMap<i32, usize> = whouses
+        .iter()
+        .enumerate()
+        .map(|(i, whouse)| (whouse.id, i))
+        .collect();
+
+    for (w_id, item_id) in owned {
+        if let Some(&i) = index.get(&w_id) {
+            whouses[i].items.push(item_id);
+        }
+    }
+
+    Ok(whouses)
+}
+
+pub fn get_warehouses_by_id(
+    conn: &PgConnection,
+    limit: i64,
+    ids: &[i32],
+) -> Result<Vec<Warehouse>> {
+    use crate::schema::warehouses::dsl::*;
+
+    let found = warehouses
+        .select((id, version, deleted_at))
+        .limit(limit)
+        .filter(id.eq(any(ids)))
+        .filter(deleted_at.is_null())
+        .get_results(conn)?;
+
+    with_items(conn, found)
+}
+
+/// Warehouses in `sort` order, starting after `after`, along with the ones in the trash
+/// when `trashed` is set.
+/// Returns up to one more warehouse than the limit, so the caller can tell if there's another page
+pub fn list_warehouses(
+    conn: &PgConnection,
+    limit: i64,
+    trashed: bool,
+    sort: Sort<()>,
+    after: Option<&Cursor>,
+) -> Result<Vec<Warehouse>> {
+    use crate::schema::warehouses::dsl::*;
+
+    let mut query = warehouses.select((id, version, deleted_at)).into_boxed();
+
+    if !trashed {
+        query = query.filter(deleted_at.is_null());
+    }
+
+    query = match (sort.descending, after) {
+        (false, Some(after)) => query.filter(id.gt(after.id)),
+        (true, Some(after)) => query.filter(id.lt(after.id)),
+        (_, None) => query,
+    };
+
+    query = if sort.descending {
+        query.order(id.desc())
+    } else {
+        query.order(id.asc())
+    };
+
+    let found = query.limit(limit + 1).get_results(conn)?;
+
+    with_items(conn, found)
+}
+
+pub fn get_warehouse(conn: &PgConnection, id_: i32) -> Result<Warehouse> {
+    use crate::schema::warehouses::dsl::*;
+
+    let found = warehouses
+        .select((id, version, deleted_at))
+        .find(id_)
+        .filter(deleted_at.is_null())
+        .first(conn)?;
+
+    let mut whouses = with_items(conn, vec![found])?;
+    Ok(whouses.remove(0))
+}
+
+/// Lock a warehouse's row for the rest of the current transaction
+pub fn get_warehouse_for_update(conn: &PgConnection, id_: i32) -> Result<Warehouse> {
+    use crate::schema::warehouses::dsl::*;
+
+    // Stock changes bump the warehouse's version, so they wait for the lock too
+    warehouses
+        .find(id_)
+        .filter(deleted_at.is_null())
+        .select(id)
+        .for_update()
+        .first::<i32>(conn)?;
+
+    get_warehouse(conn, id_)
+}
+
+/// Insert an empty warehouse
+pub fn insert_warehouse(conn: &PgConnection) -> Result<Warehouse> {
+    use crate::schema::warehouses::dsl::*;
+
+    let (inserted, version_) = diesel::insert_into(warehouses)
+        .default_values()
+        .returning((id, version))
+        .get_result(conn)?;
+
+    Ok(Warehouse {
+        id: inserted,
+        items: Vec::new(),
+        version: version_,
+        deleted_at: None,
+    })
+}
+
+/// Insert an empty warehouse with a client-chosen id
+pub fn import_warehouse(conn: &PgConnection, id_: i32) -> Result<Warehouse> {
+    use crate::schema::warehouses::dsl::*;
+
+    let (inserted, version_) = diesel::insert_into(warehouses)
+        .values(id.eq(id_))
+        .returning((id, version))
+        .get_result(conn)?;
+
+    Ok(Warehouse {
+        id: inserted,
+        items: Vec::new(),
+        version: version_,
+        deleted_at: None,
+    })
+}
+
+/// Move an empty warehouse to the trash
+pub fn trash_warehouse(conn: &PgConnection, id_: i32) -> Result<Warehouse> {
+    use crate::schema::warehouses::dsl::*;
+
+    let trashed = diesel::update(warehouses)
+        .filter(id.eq(id_))
+        .filter(deleted_at.is_null())
+        .set(deleted_at.eq(now))
+        .returning((id, version, deleted_at))
+        .get_result(conn)?;
+
+    let mut whouses = with_items(conn, vec![trashed])?;
+    Ok(whouses.remove(0))
+}
+
+/// Take a warehouse back out of the trash, still empty
+pub fn restore_warehouse(conn: &PgConnection, id_: i32) -> Result<Warehouse> {
+    use crate::schema::warehouses::dsl::*;
+
+    let restored = diesel::update(warehouses)
+        .filter(id.eq(id_))
+        .filter(deleted_at.is_not_null())
+        .set(deleted_at.eq(None::<DateTime<Utc>>))
+        .returning((id, version, deleted_at))
+        .get_result(conn)?;
+
+    let mut whouses = with_items(conn, vec![restored])?;
+    Ok(whouses.remove(0))
+}
+
+/// Warehouses in the trash, most recently deleted first
+pub fn get_trashed_warehouses(conn: &PgConnection, limit: i64) -> Result<Vec<Warehouse>> {
+    use crate::schema::warehouses::dsl::*;
+
+    let found = warehouses
+        .select((id, version, deleted_at))
+        .filter(deleted_at.is_not_null())
+        .order((deleted_at.desc(), id.desc()))
+        .limit(limit)
+        .get_results(conn)?;
+
+    with_items(conn, found)
+}
+
+/// Delete the warehouses moved to the trash before `before` for good,
+/// returns how many there were
+pub fn purge_warehouses(conn: &PgConnection, before: DateTime<Utc>) -> Result<usize> {
+    use crate::schema::warehouses::dsl::*;
+
+    diesel::delete(warehouses.filter(deleted_at.lt(before)))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+pub fn insert_api_key(conn: &PgConnection, key: &ApiKeyRow) -> Result<ApiKey> {
+    use crate::schema::api_keys::dsl::*;
+
+    diesel::insert_into(api_keys)
+        .values(key)
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+/// Every API key, revoked ones included, oldest first
+pub fn get_api_keys(conn: &PgConnection) -> Result<Vec<ApiKey>> {
+    use crate::schema::api_keys::dsl::*;
+
+    api_keys.order(id).get_results(conn).map_err(Into::into)
+}
+
+/// The key with this hash, as long as it hasn't been revoked
+pub fn get_active_api_key(conn: &PgConnection, hash: &str) -> Result<ApiKey> {
+    use crate::schema::api_keys::dsl::*;
+
+    api_keys
+        .filter(key_hash.eq(hash))
+        .filter(revoked_at.is_null())
+        .first(conn)
+        .map_err(Into::into)
+}
+
+/// Revoke a key, revoking a key twice keeps the time it was first revoked
+pub fn revoke_api_key(conn: &PgConnection, key_id: i32) -> Result<ApiKey> {
+    use crate::schema::api_keys::dsl::*;
+
+    diesel::update(api_keys.find(key_id))
+        .set(revoked_at.eq(sql("COALESCE(revoked_at, now())")))
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+pub fn count_api_keys(conn: &PgConnection) -> Result<i64> {
+    use crate::schema::api_keys::dsl::*;
+
+    api_keys.count().get_result(conn).map_err(Into::into)
+}
+
+// A row of `idempotency_keys`, its response is kept in three columns
+type IdempotentRow = (
+    i32,
+    String,
+    String,
+    Option<i32>,
+    Option<String>,
+    Option<Vec<u8>>,
+    DateTime<Utc>,
+);
+
+fn idempotent_request(row: IdempotentRow) -> Result<IdempotentRequest> {
+    let (key_id, idempotency_key, fingerprint, status, headers, body, created_at) = row;
+    Ok(IdempotentRequest {
+        key_id,
+        idempotency_key,
+        fingerprint,
+        response: StoredResponse::from_columns(status, headers, body)?,
+        created_at,
+    })
+}
+
+pub fn get_idempotent_request(
+    conn: &PgConnection,
+    key_id_: i32,
+    key: &str,
+) -> Result<IdempotentRequest> {
+    use crate::schema::idempotency_keys::dsl::*;
+
+    let row = idempotency_keys.find((key_id_, key)).first(conn)?;
+    idempotent_request(row)
+}
+
+pub fn insert_idempotent_request(
+    conn: &PgConnection,
+    key_id_: i32,
+    key: &str,
+    fingerprint_: &str,
+) -> Result<IdempotentRequest> {
+    use crate::schema::idempotency_keys::dsl::*;
+
+    let row = diesel::insert_into(idempotency_keys)
+        .values((
+            key_id.eq(key_id_),
+            idempotency_key.eq(key),
+            fingerprint.eq(fingerprint_),
+        ))
+        .get_result(conn)?;
+    idempotent_request(row)
+}
+
+pub fn save_idempotent_response(
+    conn: &PgConnection,
+    key_id_: i32,
+    key: &str,
+    response: &StoredResponse,
+) -> Result<()> {
+    use crate::schema::idempotency_keys::dsl::*;
+
+    let updated = diesel::update(idempotency_keys.find((key_id_, key)))
+        .set((
+            status.eq(response.status as i32),
+            headers.eq(response.headers_column()),
+            body.eq(&response.body),
+        ))
+        .execute(conn)?;
+
+    match updated {
+        0 => Err(Error::NotFound),
+        _ => Ok(()),
+    }
+}
+
+pub fn delete_idempotent_request(conn: &PgConnection, key_id_: i32, key: &str) -> Result<()> {
+    use crate::schema::idempotency_keys::dsl::*;
+
+    diesel::delete(idempotency_keys.find((key_id_, key))).execute(conn)?;
+    Ok(())
+}
+
+pub fn delete_idempotent_requests_before(
+    conn: &PgConnection,
+    before: DateTime<Utc>,
+) -> Result<usize> {
+    use crate::schema::idempotency_keys::dsl::*;
+
+    diesel::delete(idempotency_keys.filter(created_at.lt(before)))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+#[derive(QueryableByName)]
+struct AppliedMigration {
+    #[sql_type = "Text"]
+    version: String,
+}
+
+/// The version of every migration that's been run on the database, as recorded by Diesel
+pub fn get_applied_migrations(conn: &PgConnection) -> Result<Vec<String>> {
+    let applied: Vec<AppliedMigration> =
+        diesel::sql_query("SELECT version FROM __diesel_schema_migrations").load(conn)?;
+
+    Ok(applied.into_iter().map(|m| m.version).collect())
+}
+
+// Diesel nests transactions as savepoints, the same as `conn.transaction` does
+impl Repository for PgConnection {
+    fn begin(&self) -> Result<()> {
+        self.transaction_manager()
+            .begin_transaction(self)
+            .map_err(Into::into)
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.transaction_manager()
+            .commit_transaction(self)
+            .map_err(Into::into)
+    }
+
+    fn rollback(&self) -> Result<()> {
+        self.transaction_manager()
+            .rollback_transaction(self)
+            .map_err(Into::into)
+    }
+
+    fn get_items_by_id(&self, limit: i64, ids: &[i32]) -> Result<Vec<InventoryItem>> {
+        get_items_by_id(self, limit, ids)
+    }
+
+    fn list_items(
+        &self,
+        params: &ItemParams,
+        w_id: Option<i32>,
+        sort: Sort<ItemKey>,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<InventoryItem>> {
+        list_items(self, params, w_id, sort, after)
+    }
+
+    fn get_item(&self, id: i32) -> Result<InventoryItem> {
+        get_item(self, id)
+    }
+
+    fn insert_item(&self, item: &NewInventoryItem) -> Result<InventoryItem> {
+        insert_item(self, item)
+    }
+
+    fn import_item(&self, item: &InventoryItem) -> Result<InventoryItem> {
+        import_item(self, item)
+    }
+
+    fn sync_id_sequences(&self) -> Result<()> {
+        sync_id_sequences(self)
+    }
+
+    fn update_item(&self, item: &InventoryItem) -> Result<InventoryItem> {
+        update_item(self, item)
+    }
+
+    fn trash_item(&self, id: i32) -> Result<InventoryItem> {
+        trash_item(self, id)
+    }
+
+    fn restore_item(&self, id: i32) -> Result<InventoryItem> {
+        restore_item(self, id)
+    }
+
+    fn get_trashed_items(&self, limit: i64) -> Result<Vec<InventoryItem>> {
+        get_trashed_items(self, limit)
+    }
+
+    fn purge_items(&self, before: DateTime<Utc>) -> Result<usize> {
+        purge_items(self, before)
+    }
+
+    fn get_item_for_update(&self, id: i32) -> Result<InventoryItem> {
+        get_item_for_update(self, id)
+    }
+
+    fn get_warehouse_items(&self, w_id: i32, limit: i64) -> Result<Vec<InventoryItem>> {
+        get_warehouse_items(self, w_id, limit)
+    }
+
+    fn insert_stock(&self, item_id: i32, w_id: i32, qty: i32) -> Result<Stock> {
+        insert_stock(self, item_id, w_id, qty)
+    }
+
+    fn set_stock_quantity(&self, item_id: i32, qty: i32) -> Result<Stock> {
+        set_stock_quantity(self, item_id, qty)
+    }
+
+    fn move_stock(&self, item_id: i32, w_id: i32) -> Result<Stock> {
+        move_stock(self, item_id, w_id)
+    }
+
+    fn delete_stock(&self, item_id: i32) -> Result<Stock> {
+        delete_stock(self, item_id)
+    }
+
+    fn clear_warehouse_items(&self, w_id: i32) -> Result<usize> {
+        clear_warehouse_items(self, w_id)
+    }
+
+    fn insert_movement(&self, movement: &NewMovement) -> Result<Movement> {
+        insert_movement(self, movement)
+    }
+
+    fn get_item_movements(&self, item_id: i32, limit: i64) -> Result<Vec<Movement>> {
+        get_item_movements(self, item_id, limit)
+    }
+
+    fn get_last_item_movement(&self, item_id: i32) -> Result<Movement> {
+        get_last_item_movement(self, item_id)
+    }
+
+    fn get_warehouse_movements(&self, w_id: i32, limit: i64) -> Result<Vec<Movement>> {
+        get_warehouse_movements(self, w_id, limit)
+    }
+
+    fn get_movements_after(&self, after: i64, limit: i64) -> Result<Vec<Movement>> {
+        get_movements_after(self, after, limit)
+    }
+
+    fn get_all_stock(&self) -> Result<Vec<Stock>> {
+        get_all_stock(self)
+    }
+
+    fn get_warehouses_by_id(&self, limit: i64, ids: &[i32]) -> Result<Vec<Warehouse>> {
+        get_warehouses_by_id(self, limit, ids)
+    }
+
+    fn list_warehouses(
+        &self,
+        limit: i64,
+        trashed: bool,
+        sort: Sort<()>,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<Warehouse>> {
+        list_warehouses(self, limit, trashed, sort, after)
+    }
+
+    fn get_warehouse(&self, id: i32) -> Result<Warehouse> {
+        get_warehouse(self, id)
+    }
+
+    fn get_warehouse_for_update(&self, id: i32) -> Result<Warehouse> {
+        get_warehouse_for_update(self, id)
+    }
+
+    fn insert_warehouse(&self) -> Result<Warehouse> {
+        insert_warehouse(self)
+    }
+
+    fn import_warehouse(&self, id: i32) -> Result<Warehouse> {
+        import_warehouse(self, id)
+    }
+
+    fn trash_warehouse(&self, id: i32) -> Result<Warehouse> {
+        trash_warehouse(self, id)
+    }
+
+    fn restore_warehouse(&self, id: i32) -> Result<Warehouse> {
+        restore_warehouse(self, id)
+    }
+
+    fn get_trashed_warehouses(&self, limit: i64) -> Result<Vec<Warehouse>> {
+        get_trashed_warehouses(self, limit)
+    }
+
+    fn purge_warehouses(&self, before: DateTime<Utc>) -> Result<usize> {
+        purge_warehouses(self, before)
+    }
+
+    fn insert_api_key(&self, key: &ApiKeyRow) -> Result<ApiKey> {
+        insert_api_key(self, key)
+    }
+
+    fn get_api_keys(&self) -> Result<Vec<ApiKey>> {
+        get_api_keys(self)
+    }
+
+    fn get_active_api_key(&self, hash: &str) -> Result<ApiKey> {
+        get_active_api_key(self, hash)
+    }
+
+    fn revoke_api_key(&self, key_id: i32) -> Result<ApiKey> {
+        revoke_api_key(self, key_id)
+    }
+
+    fn count_api_keys(&self) -> Result<i64> {
+        count_api_keys(self)
+    }
+
+    fn get_idempotent_request(&self, key_id: i32, key: &str) -> Result<IdempotentRequest> {
+        get_idempotent_request(self, key_id, key)
+    }
+
+    fn insert_idempotent_request(
+        &self,
+        key_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<IdempotentRequest> {
+        insert_idempotent_request(self, key_id, key, fingerprint)
+    }
+
+    fn save_idempotent_response(
+        &self,
+        key_id: i32,
+        key: &str,
+        response: &StoredResponse,
+    ) -> Result<()> {
+        save_idempotent_response(self, key_id, key, response)
+    }
+
+    fn delete_idempotent_request(&self, key_id: i32, key: &str) -> Result<()> {
+        delete_idempotent_request(self, key_id, key)
+    }
+
+    fn delete_idempotent_requests_before(&self, before: DateTime<Utc>) -> Result<usize> {
+        delete_idempotent_requests_before(self, before)
+    }
+
+    fn get_applied_migrations(&self) -> Result<Vec<String>> {
+        get_applied_migrations(self)
+    }
+
+    fn migrations(&self) -> &'static [&'static str] {
+        MIGRATIONS
+    }
+}
@@ -0,0 +1,26 @@
+/// API keys, how they're made and how they're stored
+// Keys are random, so a plain SHA-256 is enough to keep them safe at rest,
+// and lets us find a key by its hash instead of checking it against every row
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Every key starts with this, so a leaked key is easy to recognise
+const KEY_PREFIX: &str = "wh_";
+
+/// How much of a key is kept in the clear, the prefix and 8 hex digits
+const SHOWN_LEN: usize = KEY_PREFIX.len() + 8;
+
+/// A new random key, and the part of it that's kept in the clear
+pub fn generate_key() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    let key = format!("{KEY_PREFIX}{}", hex::encode(bytes));
+    let shown = key[..SHOWN_LEN].to_string();
+    (key, shown)
+}
+
+/// What's stored in place of a key
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
@@ -0,0 +1,45 @@
+//! Warehouser core
+//! The models, storage backends, and business logic of Warehouser,
+//! shared by the server and by anything else that wants the same types
+
+#![allow(non_local_definitions)] // Triggered by the code generated by Diesel 1.x
+
+#[cfg(feature = "storage")]
+#[macro_use]
+extern crate diesel;
+extern crate serde;
+
+pub mod batch;
+pub mod conditional;
+#[cfg(feature = "storage")]
+pub mod db;
+pub mod keys;
+#[cfg(feature = "storage")]
+pub mod memory;
+#[cfg(feature = "storage")]
+pub mod migrations;
+pub mod models;
+pub mod pagination;
+#[cfg(feature = "storage")]
+pub mod repository;
+#[cfg(feature = "storage")]
+pub mod schema;
+#[cfg(feature = "storage")]
+pub mod service;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_schema;
+pub mod util;
+pub mod versions;
+
+#[cfg(feature = "storage")]
+use diesel::{pg::PgConnection, r2d2::ConnectionManager};
+#[cfg(feature = "storage")]
+use r2d2::Pool;
+
+#[cfg(feature = "storage")]
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+
+#[cfg(feature = "sqlite")]
+pub type SqlitePool = Pool<ConnectionManager<diesel::sqlite::SqliteConnection>>;
@@ -0,0 +1,970 @@
+/// In-memory `Repository`
+/// Keeps everything in process, for tests and for trying out the API without a database.
+/// Nothing survives a restart
+// The rules Postgres enforces for us, foreign keys, unique ids, cascades,
+// and the triggers that count versions, are followed here by hand and reported with the same errors, so the service
+// layer can't tell the difference
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+
+use crate::migrations::MIGRATIONS;
+use crate::models::{
+    ApiKey, ApiKeyRow, Error, IdempotentRequest, InventoryItem, Money, Movement, NewInventoryItem,
+    NewMovement, Result, Stock, StoredResponse, Warehouse,
+};
+use crate::pagination::{Cursor, ItemKey, ItemParams, KeyType, Sort, WarehouseFilter};
+use crate::repository::Repository;
+
+/// The data, shared by every connection, cheap to clone
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    /// A connection to the store, requests take turns with it, one at a time
+    pub fn connect(&self) -> MemoryConnection<'_> {
+        // A panic can't leave the state half changed, it's only written between snapshots
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        MemoryConnection {
+            state: RefCell::new(state),
+            snapshots: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+/// Holds the store until it's dropped, along with the transactions in progress
+pub struct MemoryConnection<'a> {
+    state: RefCell<MutexGuard<'a, State>>,
+    snapshots: RefCell<Vec<State>>, // The state as it was at each `begin`, innermost last
+}
+
+#[derive(Clone)]
+struct State {
+    items: BTreeMap<i32, InventoryItem>, // Rows, without their stock
+    stock: BTreeMap<i32, Stock>,         // By item
+    warehouses: BTreeMap<i32, WarehouseRow>,
+    movements: Vec<Movement>,
+    api_keys: Vec<ApiKey>,
+    idempotency_keys: BTreeMap<(i32, String), IdempotentRequest>, // By API key and idempotency key
+    next_item: i32,
+    next_warehouse: i32,
+    next_movement: i64,
+    next_api_key: i32,
+}
+
+#[derive(Clone, Copy)]
+struct WarehouseRow {
+    version: i32,
+    deleted_at: Option<DateTime<Utc>>, // When it was moved to the trash
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            items: BTreeMap::new(),
+            stock: BTreeMap::new(),
+            warehouses: BTreeMap::new(),
+            movements: Vec::new(),
+            api_keys: Vec::new(),
+            idempotency_keys: BTreeMap::new(),
+            next_item: 1,
+            next_warehouse: 1,
+            next_movement: 1,
+            next_api_key: 1,
+        }
+    }
+}
+
+impl State {
+    // An item as it's read through the join with its stock
+    fn item(&self, id: i32) -> Result<InventoryItem> {
+        let mut item = self.items.get(&id).cloned().ok_or(Error::NotFound)?;
+        if let Some(stock) = self.stock.get(&id) {
+            item.warehouse = Some(stock.warehouse);
+            item.quantity = stock.quantity;
+        }
+        Ok(item)
+    }
+
+    // The same, leaving out the items in the trash
+    fn live_item(&self, id: i32) -> Result<InventoryItem> {
+        self.item(id)
+            .and_then(|item| item.deleted_at.map_or(Ok(item), |_| Err(Error::NotFound)))
+    }
+
+    fn warehouse(&self, id: i32) -> Result<Warehouse> {
+        let row = *self.warehouses.get(&id).ok_or(Error::NotFound)?;
+        let items = self
+            .stock
+            .values()
+            .filter(|stock| stock.warehouse == id)
+            .map(|stock| stock.item)
+            .collect();
+        Ok(Warehouse {
+            id,
+            items,
+            version: row.version,
+            deleted_at: row.deleted_at,
+        })
+    }
+
+    fn live_warehouse(&self, id: i32) -> Result<Warehouse> {
+        self.warehouse(id).and_then(|whouse| {
+            whouse
+                .deleted_at
+                .map_or(Ok(whouse), |_| Err(Error::NotFound))
+        })
+    }
+
+    fn is_live_warehouse(&self, id: i32) -> bool {
+        self.warehouses
+            .get(&id)
+            .is_some_and(|row| row.deleted_at.is_none())
+    }
+
+    // What the `stock` triggers do, an item's stock is part of it,
+    // and a warehouse changes when an item arrives or leaves
+    fn stock_changed(&mut self, item_id: i32, warehouses: &[i32]) {
+        if let Some(item) = self.items.get_mut(&item_id) {
+            item.version += 1;
+        }
+        for w_id in warehouses {
+            if let Some(row) = self.warehouses.get_mut(w_id) {
+                row.version += 1;
+            }
+        }
+    }
+
+    fn insert_item_row(&mut self, id: i32, item: InventoryItem) -> Result<()> {
+        if id < 1 {
+            return Err(check_violation("inventory", "inventory_id_check"));
+        }
+        if self.items.contains_key(&id) {
+            return Err(unique_violation("inventory_pkey"));
+        }
+        let row = InventoryItem {
+            version: 1,
+            ..item_row(id, item)
+        };
+        self.items.insert(id, row);
+        Ok(())
+    }
+
+    fn insert_warehouse_row(&mut self, id: i32) -> Result<Warehouse> {
+        if self.warehouses.contains_key(&id) {
+            return Err(unique_violation("warehouses_pkey"));
+        }
+        let row = WarehouseRow {
+            version: 1,
+            deleted_at: None,
+        };
+        self.warehouses.insert(id, row);
+        self.warehouse(id)
+    }
+}
+
+// Only the item's own columns, as they're stored in its row
+fn item_row(id: i32, item: InventoryItem) -> InventoryItem {
+    // Amounts are kept to 4 places, like the `NUMERIC(19, 4)` column
+    let amount = item.value.amount.with_scale(4);
+    InventoryItem {
+        id,
+        warehouse: None,
+        quantity: 0,
+        value: Money {
+            amount,
+            ..item.value
+        },
+        deleted_at: None,
+        ..item
+    }
+}
+
+fn check_violation(table: &str, constraint: &str) -> Error {
+    Error::Database {
+        detail: format!(
+            "new row for relation \"{table}\" violates check constraint \"{constraint}\""
+        ),
+    }
+}
+
+fn unique_violation(constraint: &str) -> Error {
+    Error::UniqueViolation {
+        column: None,
+        detail: format!("duplicate key value violates unique constraint \"{constraint}\""),
+    }
+}
+
+fn reference_violation(table: &str, constraint: &str) -> Error {
+    Error::ReferenceViolation {
+        detail: format!(
+            "insert or update on table \"{table}\" violates foreign key constraint \"{constraint}\""
+        ),
+    }
+}
+
+// A sort key as the database compares it, see `ItemKey::sql`
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum KeyValue {
+    Int(i64),
+    Decimal(BigDecimal),
+    Text(String),
+}
+
+impl KeyValue {
+    fn of(key: ItemKey, item: &InventoryItem) -> KeyValue {
+        match key {
+            ItemKey::Id => KeyValue::Int(item.id.into()),
+            ItemKey::Warehouse => KeyValue::Int(item.warehouse.unwrap_or(0).into()),
+            ItemKey::Quantity => KeyValue::Int(item.quantity.into()),
+            ItemKey::Weight => KeyValue::Int(item.weight_g),
+            ItemKey::Value => KeyValue::Decimal(item.value.amount.clone()),
+            ItemKey::Transport => KeyValue::Text(item.transport.to_string()),
+            ItemKey::Width => KeyValue::Int(item.dimensions_mm.width.into()),
+            ItemKey::Height => KeyValue::Int(item.dimensions_mm.height.into()),
+            ItemKey::Depth => KeyValue::Int(item.dimensions_mm.depth.into()),
+        }
+    }
+
+    fn parse(key: ItemKey, cursor: &Cursor) -> Result<KeyValue> {
+        let invalid = || Error::InvalidRequest {
+            detail: "cursor is not valid".to_string(),
+        };
+        Ok(match key.key_type() {
+            KeyType::Int => KeyValue::Int(cursor.key.parse().map_err(|_| invalid())?),
+            KeyType::Decimal => {
+                KeyValue::Decimal(BigDecimal::from_str(&cursor.key).map_err(|_| invalid())?)
+            }
+            KeyType::Text => KeyValue::Text(cursor.key.clone()),
+        })
+    }
+}
+
+// Whether `value op bound` holds, for the range filters of an item list
+fn compare<T: PartialOrd>(value: &T, op: &str, bound: &T) -> bool {
+    match op {
+        ">" => value > bound,
+        ">=" => value >= bound,
+        "<" => value < bound,
+        _ => value <= bound,
+    }
+}
+
+fn matches(params: &ItemParams, item: &InventoryItem) -> bool {
+    match params.warehouse {
+        Some(WarehouseFilter::None) if item.warehouse.is_some() => return false,
+        Some(WarehouseFilter::Id(w_id)) if item.warehouse != Some(w_id) => return false,
+        _ => {}
+    }
+
+    if params
+        .transport
+        .as_ref()
+        .is_some_and(|transport| *transport != item.transport)
+    {
+        return false;
+    }
+
+    if params
+        .currency
+        .as_ref()
+        .is_some_and(|currency| *currency != item.value.currency)
+    {
+        return false;
+    }
+
+    // Weights are filtered in kg, but stored in grams
+    let weight_g = BigDecimal::from(item.weight_g);
+    let grams = |kg: &BigDecimal| kg * BigDecimal::from(1000);
+    let ranges = [
+        (">", &params.weight_gt, &params.value_gt, params.quantity_gt),
+        (
+            ">=",
+            &params.weight_gte,
+            &params.value_gte,
+            params.quantity_gte,
+        ),
+        ("<", &params.weight_lt, &params.value_lt, params.quantity_lt),
+        (
+            "<=",
+            &params.weight_lte,
+            &params.value_lte,
+            params.quantity_lte,
+        ),
+    ];
+
+    ranges.into_iter().all(|(op, weight, value, quantity)| {
+        weight
+            .as_ref()
+            .is_none_or(|weight| compare(&weight_g, op, &grams(weight)))
+            && value
+                .as_ref()
+                .is_none_or(|value| compare(&item.value.amount, op, value))
+            && quantity.is_none_or(|quantity| compare(&item.quantity, op, &quantity))
+    })
+}
+
+impl MemoryConnection<'_> {
+    fn read<T>(&self, f: impl FnOnce(&State) -> Result<T>) -> Result<T> {
+        f(&self.state.borrow())
+    }
+
+    fn write<T>(&self, f: impl FnOnce(&mut State) -> Result<T>) -> Result<T> {
+        f(&mut self.state.borrow_mut())
+    }
+}
+
+impl Repository for MemoryConnection<'_> {
+    fn begin(&self) -> Result<()> {
+        let snapshot = self.state.borrow().clone();
+        self.snapshots.borrow_mut().push(snapshot);
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.snapshots
+            .borrow_mut()
+            .pop()
+            .map(drop)
+            .ok_or_else(no_transaction)
+    }
+
+    fn rollback(&self) -> Result<()> {
+        let snapshot = self
+            .snapshots
+            .borrow_mut()
+            .pop()
+            .ok_or_else(no_transaction)?;
+        **self.state.borrow_mut() = snapshot;
+        Ok(())
+    }
+
+    fn get_items_by_id(&self, limit: i64, ids: &[i32]) -> Result<Vec<InventoryItem>> {
+        self.read(|state| {
+            Ok(state
+                .items
+                .keys()
+                .filter(|id| ids.contains(id))
+                .filter_map(|&id| state.live_item(id).ok())
+                .take(limit as usize)
+                .collect())
+        })
+    }
+
+    fn list_items(
+        &self,
+        params: &ItemParams,
+        w_id: Option<i32>,
+        sort: Sort<ItemKey>,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<InventoryItem>> {
+        let after = after
+            .map(|after| Ok::<_, Error>((KeyValue::parse(sort.key, after)?, after.id)))
+            .transpose()?;
+
+        self.read(|state| {
+            let mut found: Vec<(KeyValue, InventoryItem)> = state
+                .items
+                .keys()
+                .filter_map(|&id| state.item(id).ok())
+                .filter(|item| params.include_deleted() || item.deleted_at.is_none())
+                .filter(|item| w_id.is_none() || item.warehouse == w_id)
+                .filter(|item| matches(params, item))
+                .map(|item| (KeyValue::of(sort.key, &item), item))
+                .filter(|(key, item)| match &after {
+                    Some(after) => {
+                        let order = (key, item.id).cmp(&(&after.0, after.1));
+                        order
+                            == if sort.descending {
+                                Ordering::Less
+                            } else {
+                                Ordering::Greater
+                            }
+                    }
+                    None => true,
+                })
+                .collect();
+
+            found.sort_by(|(a_key, a), (b_key, b)| {
+                let order = (a_key, a.id).cmp(&(b_key, b.id));
+                if sort.descending {
+                    order.reverse()
+                } else {
+                    order
+                }
+            });
+
+            Ok(found
+                .into_iter()
+                .map(|(_, item)| item)
+                .take(params.limit() as usize + 1)
+                .collect())
+        })
+    }
+
+    fn get_item(&self, id: i32) -> Result<InventoryItem> {
+        self.read(|state| state.live_item(id))
+    }
+
+    fn insert_item(&self, item: &NewInventoryItem) -> Result<InventoryItem> {
+        self.write(|state| {
+            let id = state.next_item;
+            state.next_item += 1;
+            state.insert_item_row(
+                id,
+                InventoryItem {
+                    id,
+                    warehouse: None,
+                    quantity: 0,
+                    weight_g: item.weight_g,
+                    value: item.value.clone(),
+                    transport: item.transport.clone(),
+                    dimensions_mm: item.dimensions_mm.clone(),
+                    version: 1,
+                    deleted_at: None,
+                },
+            )?;
+            state.item(id)
+        })
+    }
+
+    fn import_item(&self, item: &InventoryItem) -> Result<InventoryItem> {
+        self.write(|state| {
+            state.insert_item_row(item.id, item.clone())?;
+            // Past the client's id, so it's never handed out again
+            state.next_item = state.next_item.max(item.id + 1);
+            state.item(item.id)
+        })
+    }
+
+    // `import_item` and `import_warehouse` already move past the client's id
+    fn sync_id_sequences(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn update_item(&self, item: &InventoryItem) -> Result<InventoryItem> {
+        self.write(|state| {
+            let row = state
+                .items
+                .get_mut(&item.id)
+                .filter(|row| row.deleted_at.is_none())
+                .ok_or(Error::NotFound)?;
+            *row = InventoryItem {
+                version: row.version + 1,
+                ..item_row(item.id, item.clone())
+            };
+            state.item(item.id)
+        })
+    }
+
+    fn trash_item(&self, id: i32) -> Result<InventoryItem> {
+        self.write(|state| {
+            let row = state
+                .items
+                .get_mut(&id)
+                .filter(|row| row.deleted_at.is_none())
+                .ok_or(Error::NotFound)?;
+            row.deleted_at = Some(Utc::now());
+            state.item(id)
+        })
+    }
+
+    fn restore_item(&self, id: i32) -> Result<InventoryItem> {
+        self.write(|state| {
+            let row = state
+                .items
+                .get_mut(&id)
+                .filter(|row| row.deleted_at.is_some())
+                .ok_or(Error::NotFound)?;
+            row.deleted_at = None;
+            state.item(id)
+        })
+    }
+
+    fn get_trashed_items(&self, limit: i64) -> Result<Vec<InventoryItem>> {
+        self.read(|state| {
+            let mut trashed: Vec<InventoryItem> = state
+                .items
+                .values()
+                .filter(|item| item.deleted_at.is_some())
+                .cloned()
+                .collect();
+            trashed.sort_by_key(|row| Reverse((row.deleted_at, row.id)));
+            trashed.truncate(limit as usize);
+            Ok(trashed)
+        })
+    }
+
+    fn purge_items(&self, before: DateTime<Utc>) -> Result<usize> {
+        self.write(|state| {
+            let purged: Vec<i32> = state
+                .items
+                .values()
+                .filter(|item| item.deleted_at.is_some_and(|at| at < before))
+                .map(|item| item.id)
+                .collect();
+            for id in &purged {
+                state.items.remove(id);
+                state.stock.remove(id);
+            }
+            Ok(purged.len())
+        })
+    }
+
+    fn get_item_for_update(&self, id: i32) -> Result<InventoryItem> {
+        // The connection holds the whole store, so there's nothing else to lock
+        self.get_item(id)
+    }
+
+    fn get_warehouse_items(&self, w_id: i32, limit: i64) -> Result<Vec<InventoryItem>> {
+        self.read(|state| {
+            state
+                .stock
+                .values()
+                .filter(|stock| stock.warehouse == w_id)
+                .take(limit as usize)
+                .map(|stock| state.item(stock.item))
+                .collect()
+        })
+    }
+
+    fn insert_stock(&self, item_id: i32, w_id: i32, qty: i32) -> Result<Stock> {
+        self.write(|state| {
+            if qty <= 0 {
+                return Err(check_violation("stock", "stock_quantity_check"));
+            }
+            if state.stock.contains_key(&item_id) {
+                return Err(unique_violation("stock_pkey"));
+            }
+            if !state.items.contains_key(&item_id) {
+                return Err(reference_violation("stock", "stock_item_fkey"));
+            }
+            if !state.warehouses.contains_key(&w_id) {
+                return Err(reference_violation("stock", "stock_warehouse_fkey"));
+            }
+
+            let stock = Stock {
+                item: item_id,
+                warehouse: w_id,
+                quantity: qty,
+            };
+            state.stock.insert(item_id, stock.clone());
+            state.stock_changed(item_id, &[w_id]);
+            Ok(stock)
+        })
+    }
+
+    fn set_stock_quantity(&self, item_id: i32, qty: i32) -> Result<Stock> {
+        self.write(|state| {
+            if qty <= 0 {
+                return Err(check_violation("stock", "stock_quantity_check"));
+            }
+            let stock = state.stock.get_mut(&item_id).ok_or(Error::NotFound)?;
+            stock.quantity = qty;
+            let stock = stock.clone();
+            state.stock_changed(item_id, &[]);
+            Ok(stock)
+        })
+    }
+
+    fn move_stock(&self, item_id: i32, w_id: i32) -> Result<Stock> {
+        self.write(|state| {
+            if !state.stock.contains_key(&item_id) {
+                return Err(Error::NotFound);
+            }
+            if !state.warehouses.contains_key(&w_id) {
+                return Err(reference_violation("stock", "stock_warehouse_fkey"));
+            }
+            let stock = state.stock.get_mut(&item_id).ok_or(Error::NotFound)?;
+            let from = std::mem::replace(&mut stock.warehouse, w_id);
+            let stock = stock.clone();
+            let moved: &[i32] = if from == w_id { &[] } else { &[from, w_id] };
+            state.stock_changed(item_id, moved);
+            Ok(stock)
+        })
+    }
+
+    fn delete_stock(&self, item_id: i32) -> Result<Stock> {
+        self.write(|state| {
+            let stock = state.stock.remove(&item_id).ok_or(Error::NotFound)?;
+            state.stock_changed(item_id, &[stock.warehouse]);
+            Ok(stock)
+        })
+    }
+
+    fn clear_warehouse_items(&self, w_id: i32) -> Result<usize> {
+        self.write(|state| {
+            let released: Vec<i32> = state
+                .stock
+                .values()
+                .filter(|stock| stock.warehouse == w_id)
+                .map(|stock| stock.item)
+                .collect();
+            for item_id in &released {
+                state.stock.remove(item_id);
+                state.stock_changed(*item_id, &[w_id]);
+            }
+            Ok(released.len())
+        })
+    }
+
+    fn insert_movement(&self, movement: &NewMovement) -> Result<Movement> {
+        self.write(|state| {
+            if movement.quantity < 0 {
+                return Err(check_violation("movements", "movements_quantity_check"));
+            }
+
+            let movement = Movement {
+                id: state.next_movement,
+                item: movement.item,
+                from_warehouse: movement.from_warehouse,
+                to_warehouse: movement.to_warehouse,
+                quantity: movement.quantity,
+                reason: movement.reason,
+                created_at: Utc::now(),
+                actor: movement.actor.to_string(),
+            };
+            state.next_movement += 1;
+            state.movements.push(movement.clone());
+            Ok(movement)
+        })
+    }
+
+    fn get_item_movements(&self, item_id: i32, limit: i64) -> Result<Vec<Movement>> {
+        self.read(|state| {
+            Ok(state
+                .movements
+                .iter()
+                .filter(|movement| movement.item == item_id)
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn get_last_item_movement(&self, item_id: i32) -> Result<Movement> {
+        self.read(|state| {
+            state
+                .movements
+                .iter()
+                .rev()
+                .find(|movement| movement.item == item_id)
+                .cloned()
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn get_warehouse_movements(&self, w_id: i32, limit: i64) -> Result<Vec<Movement>> {
+        self.read(|state| {
+            Ok(state
+                .movements
+                .iter()
+                .filter(|movement| {
+                    movement.from_warehouse == Some(w_id) || movement.to_warehouse == Some(w_id)
+                })
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn get_movements_after(&self, after: i64, limit: i64) -> Result<Vec<Movement>> {
+        self.read(|state| {
+            Ok(state
+                .movements
+                .iter()
+                .filter(|movement| movement.id > after)
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn get_all_stock(&self) -> Result<Vec<Stock>> {
+        self.read(|state| Ok(state.stock.values().cloned().collect()))
+    }
+
+    fn get_warehouses_by_id(&self, limit: i64, ids: &[i32]) -> Result<Vec<Warehouse>> {
+        self.read(|state| {
+            state
+                .warehouses
+                .keys()
+                .filter(|id| ids.contains(id) && state.is_live_warehouse(**id))
+                .take(limit as usize)
+                .map(|&id| state.warehouse(id))
+                .collect()
+        })
+    }
+
+    fn list_warehouses(
+        &self,
+        limit: i64,
+        trashed: bool,
+        sort: Sort<()>,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<Warehouse>> {
+        self.read(|state| {
+            let after = after.map(|after| after.id);
+            let ids: Box<dyn Iterator<Item = &i32>> = if sort.descending {
+                Box::new(
+                    state
+                        .warehouses
+                        .keys()
+                        .rev()
+                        .filter(|&&id| after.is_none_or(|after| id < after)),
+                )
+            } else {
+                Box::new(
+                    state
+                        .warehouses
+                        .keys()
+                        .filter(|&&id| after.is_none_or(|after| id > after)),
+                )
+            };
+
+            ids.filter(|&&id| trashed || state.is_live_warehouse(id))
+                .take(limit as usize + 1)
+                .map(|&id| state.warehouse(id))
+                .collect()
+        })
+    }
+
+    fn get_warehouse(&self, id: i32) -> Result<Warehouse> {
+        self.read(|state| state.live_warehouse(id))
+    }
+
+    fn get_warehouse_for_update(&self, id: i32) -> Result<Warehouse> {
+        self.get_warehouse(id)
+    }
+
+    fn insert_warehouse(&self) -> Result<Warehouse> {
+        self.write(|state| {
+            let id = state.next_warehouse;
+            let whouse = state.insert_warehouse_row(id)?;
+            state.next_warehouse += 1;
+            Ok(whouse)
+        })
+    }
+
+    fn import_warehouse(&self, id: i32) -> Result<Warehouse> {
+        self.write(|state| {
+            if id < 1 {
+                return Err(check_violation("warehouses", "warehouses_id_check"));
+            }
+            let whouse = state.insert_warehouse_row(id)?;
+            state.next_warehouse = state.next_warehouse.max(id + 1);
+            Ok(whouse)
+        })
+    }
+
+    fn trash_warehouse(&self, id: i32) -> Result<Warehouse> {
+        self.write(|state| {
+            let row = state
+                .warehouses
+                .get_mut(&id)
+                .filter(|row| row.deleted_at.is_none())
+                .ok_or(Error::NotFound)?;
+            row.deleted_at = Some(Utc::now());
+            state.warehouse(id)
+        })
+    }
+
+    fn restore_warehouse(&self, id: i32) -> Result<Warehouse> {
+        self.write(|state| {
+            let row = state
+                .warehouses
+                .get_mut(&id)
+                .filter(|row| row.deleted_at.is_some())
+                .ok_or(Error::NotFound)?;
+            row.deleted_at = None;
+            state.warehouse(id)
+        })
+    }
+
+    fn get_trashed_warehouses(&self, limit: i64) -> Result<Vec<Warehouse>> {
+        self.read(|state| {
+            let mut trashed: Vec<Warehouse> = state
+                .warehouses
+                .iter()
+                .filter(|(_, row)| row.deleted_at.is_some())
+                .map(|(&id, _)| state.warehouse(id))
+                .collect::<Result<_>>()?;
+            trashed.sort_by_key(|row| Reverse((row.deleted_at, row.id)));
+            trashed.truncate(limit as usize);
+            Ok(trashed)
+        })
+    }
+
+    fn purge_warehouses(&self, before: DateTime<Utc>) -> Result<usize> {
+        self.write(|state| {
+            if state.stock.values().any(|stock| {
+                state.warehouses[&stock.warehouse]
+                    .deleted_at
+                    .is_some_and(|at| at < before)
+            }) {
+                return Err(Error::ReferenceViolation {
+                    detail: "update or delete on table \"warehouses\" violates foreign key constraint \"stock_warehouse_fkey\" on table \"stock\"".to_string(),
+                });
+            }
+            let before_count = state.warehouses.len();
+            state
+                .warehouses
+                .retain(|_, row| row.deleted_at.is_none_or(|at| at >= before));
+            Ok(before_count - state.warehouses.len())
+        })
+    }
+
+    fn insert_api_key(&self, key: &ApiKeyRow) -> Result<ApiKey> {
+        self.write(|state| {
+            if state.api_keys.iter().any(|k| k.key_hash == key.key_hash) {
+                return Err(unique_violation("api_keys_key_hash_key"));
+            }
+
+            let api_key = ApiKey {
+                id: state.next_api_key,
+                name: key.name.to_string(),
+                role: key.role,
+                key_hash: key.key_hash.to_string(),
+                prefix: key.prefix.to_string(),
+                created_at: Utc::now(),
+                revoked_at: None,
+            };
+            state.next_api_key += 1;
+            state.api_keys.push(api_key.clone());
+            Ok(api_key)
+        })
+    }
+
+    fn get_api_keys(&self) -> Result<Vec<ApiKey>> {
+        self.read(|state| Ok(state.api_keys.clone()))
+    }
+
+    fn get_active_api_key(&self, hash: &str) -> Result<ApiKey> {
+        self.read(|state| {
+            state
+                .api_keys
+                .iter()
+                .find(|key| key.key_hash == hash && key.revoked_at.is_none())
+                .cloned()
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn revoke_api_key(&self, key_id: i32) -> Result<ApiKey> {
+        self.write(|state| {
+            let key = state
+                .api_keys
+                .iter_mut()
+                .find(|key| key.id == key_id)
+                .ok_or(Error::NotFound)?;
+            key.revoked_at.get_or_insert_with(Utc::now);
+            Ok(key.clone())
+        })
+    }
+
+    fn count_api_keys(&self) -> Result<i64> {
+        self.read(|state| Ok(state.api_keys.len() as i64))
+    }
+
+    fn get_idempotent_request(&self, key_id: i32, key: &str) -> Result<IdempotentRequest> {
+        self.read(|state| {
+            state
+                .idempotency_keys
+                .get(&(key_id, key.to_string()))
+                .cloned()
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    fn insert_idempotent_request(
+        &self,
+        key_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<IdempotentRequest> {
+        self.write(|state| {
+            if !state.api_keys.iter().any(|api_key| api_key.id == key_id) {
+                return Err(reference_violation(
+                    "idempotency_keys",
+                    "idempotency_keys_key_id_fkey",
+                ));
+            }
+            let id = (key_id, key.to_string());
+            if state.idempotency_keys.contains_key(&id) {
+                return Err(unique_violation("idempotency_keys_pkey"));
+            }
+
+            let request = IdempotentRequest {
+                key_id,
+                idempotency_key: key.to_string(),
+                fingerprint: fingerprint.to_string(),
+                response: None,
+                created_at: Utc::now(),
+            };
+            state.idempotency_keys.insert(id, request.clone());
+            Ok(request)
+        })
+    }
+
+    fn save_idempotent_response(
+        &self,
+        key_id: i32,
+        key: &str,
+        response: &StoredResponse,
+    ) -> Result<()> {
+        self.write(|state| {
+            let request = state
+                .idempotency_keys
+                .get_mut(&(key_id, key.to_string()))
+                .ok_or(Error::NotFound)?;
+            request.response = Some(response.clone());
+            Ok(())
+        })
+    }
+
+    fn delete_idempotent_request(&self, key_id: i32, key: &str) -> Result<()> {
+        self.write(|state| {
+            state.idempotency_keys.remove(&(key_id, key.to_string()));
+            Ok(())
+        })
+    }
+
+    fn delete_idempotent_requests_before(&self, before: DateTime<Utc>) -> Result<usize> {
+        self.write(|state| {
+            let count = state.idempotency_keys.len();
+            state
+                .idempotency_keys
+                .retain(|_, request| request.created_at >= before);
+            Ok(count - state.idempotency_keys.len())
+        })
+    }
+
+    // The store is created with the latest schema, there's nothing to migrate
+    fn get_applied_migrations(&self) -> Result<Vec<String>> {
+        Ok(MIGRATIONS
+            .iter()
+            .map(|version| version.to_string())
+            .collect())
+    }
+
+    fn migrations(&self) -> &'static [&'static str] {
+        MIGRATIONS
+    }
+}
+
+fn no_transaction() -> Error {
+    Error::Internal {
+        detail: "No transaction in progress".to_string(),
+    }
+}
@@ -0,0 +1,196 @@
+/// Migrations built into the binary
+/// A server sets up and updates its own database, without the Diesel CLI.
+/// Runs are recorded in `__diesel_schema_migrations`, the same as the CLI does,
+/// so databases set up either way are interchangeable
+use std::io::Write;
+use std::path::Path;
+
+use diesel::connection::SimpleConnection;
+use diesel::migration::{Migration, RunMigrationsError};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel_migrations::MigrationConnection;
+
+use crate::models::{Error, Result};
+use crate::repository::Connection;
+
+// `MIGRATIONS` and `SQLITE_MIGRATIONS`, the version of every migration this build expects,
+// and `POSTGRES` and `SQLITE`, the migrations themselves, listed by build.rs
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+/// One of the directories of `migrations/` or `migrations_sqlite/`
+#[derive(Debug)]
+pub struct EmbeddedMigration {
+    pub name: &'static str, // The directory's name, like `2022-02-10-000000_api_keys`
+    pub version: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+impl Migration for EmbeddedMigration {
+    fn version(&self) -> &str {
+        self.version
+    }
+
+    fn run(&self, conn: &dyn SimpleConnection) -> std::result::Result<(), RunMigrationsError> {
+        conn.batch_execute(self.up).map_err(Into::into)
+    }
+
+    fn revert(&self, conn: &dyn SimpleConnection) -> std::result::Result<(), RunMigrationsError> {
+        conn.batch_execute(self.down).map_err(Into::into)
+    }
+
+    // Only used for its name, in Diesel's "Running migration" lines
+    fn file_path(&self) -> Option<&Path> {
+        Some(Path::new(self.name))
+    }
+}
+
+/// Whether a migration has been run on the database
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub name: &'static str,
+    pub applied: bool,
+}
+
+/// Run every migration the database doesn't have yet, oldest first, each in its own transaction
+pub fn run_pending(conn: &Connection, out: &mut dyn Write) -> Result<()> {
+    match conn {
+        Connection::Postgres(conn) => run(&**conn, POSTGRES, out),
+        #[cfg(feature = "sqlite")]
+        Connection::Sqlite(conn) => run(&**conn, SQLITE, out),
+        // There's no schema, the store always has the shape this build expects
+        Connection::Memory(_) => Ok(()),
+    }
+}
+
+/// Undo the most recent migration, returns its name, or `None` if nothing has been run
+pub fn revert_latest(conn: &Connection, out: &mut dyn Write) -> Result<Option<&'static str>> {
+    match conn {
+        Connection::Postgres(conn) => revert(&**conn, POSTGRES, out),
+        #[cfg(feature = "sqlite")]
+        Connection::Sqlite(conn) => revert(&**conn, SQLITE, out),
+        Connection::Memory(_) => Err(Error::Unsupported {
+            detail: "The memory backend has no migrations to revert".to_string(),
+        }),
+    }
+}
+
+/// Every migration this build has, and whether it's been run, oldest first
+pub fn status(conn: &Connection) -> Result<Vec<MigrationStatus>> {
+    match conn {
+        Connection::Postgres(conn) => list(&**conn, POSTGRES),
+        #[cfg(feature = "sqlite")]
+        Connection::Sqlite(conn) => list(&**conn, SQLITE),
+        Connection::Memory(_) => Ok(POSTGRES
+            .iter()
+            .map(|migration| MigrationStatus {
+                name: migration.name,
+                applied: true,
+            })
+            .collect()),
+    }
+}
+
+fn run<C: MigrationConnection>(
+    conn: &C,
+    set: &'static [EmbeddedMigration],
+    out: &mut dyn Write,
+) -> Result<()> {
+    let migrations = set.iter().map(|migration| migration as &dyn Migration);
+    diesel_migrations::run_migrations(conn, migrations, out).map_err(migration_error)
+}
+
+fn revert<C: MigrationConnection>(
+    conn: &C,
+    set: &'static [EmbeddedMigration],
+    out: &mut dyn Write,
+) -> Result<Option<&'static str>> {
+    use self::__diesel_schema_migrations::dsl::*;
+
+    diesel_migrations::setup_database(conn)?;
+    let latest = match conn.latest_run_migration_version()? {
+        Some(latest) => latest,
+        None => return Ok(None),
+    };
+
+    // Its down.sql is only in the build that ran it
+    let migration = set
+        .iter()
+        .find(|migration| migration.version == latest)
+        .ok_or_else(|| Error::Unsupported {
+            detail: format!("Migration {latest} isn't part of this build, so it can't be reverted"),
+        })?;
+
+    conn.transaction(|| {
+        writeln!(out, "Rolling back migration {}", migration.name)
+            .map_err(RunMigrationsError::from)?;
+        migration.revert(conn)?;
+        diesel::delete(__diesel_schema_migrations.filter(version.eq(migration.version)))
+            .execute(conn)?;
+        Ok(())
+    })
+    .map_err(migration_error)?;
+
+    Ok(Some(migration.name))
+}
+
+fn list<C: MigrationConnection>(
+    conn: &C,
+    set: &'static [EmbeddedMigration],
+) -> Result<Vec<MigrationStatus>> {
+    diesel_migrations::setup_database(conn)?;
+    let applied = conn.previously_run_migration_versions()?;
+
+    Ok(set
+        .iter()
+        .map(|migration| MigrationStatus {
+            name: migration.name,
+            applied: applied.contains(migration.version),
+        })
+        .collect())
+}
+
+fn migration_error(e: RunMigrationsError) -> Error {
+    match e {
+        RunMigrationsError::QueryError(e) => e.into(),
+        e => Error::Database {
+            detail: e.to_string(),
+        },
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use diesel::{Connection, SqliteConnection};
+
+    #[test]
+    fn sqlite_migrations_run_revert_and_run_again() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        let mut out = Vec::new();
+        let applied = |conn: &SqliteConnection| -> Vec<bool> {
+            list(conn, SQLITE)
+                .unwrap()
+                .iter()
+                .map(|m| m.applied)
+                .collect()
+        };
+
+        run(&conn, SQLITE, &mut out).unwrap();
+        assert!(applied(&conn).iter().all(|applied| *applied));
+
+        while revert(&conn, SQLITE, &mut out).unwrap().is_some() {}
+        assert!(applied(&conn).iter().all(|applied| !applied));
+
+        // Every down.sql undid its up.sql, or this would fail on a table that already exists
+        run(&conn, SQLITE, &mut out).unwrap();
+        assert!(applied(&conn).iter().all(|applied| *applied));
+    }
+}
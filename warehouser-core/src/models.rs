@@ -0,0 +1,1214 @@
+use std::{collections::HashSet, fmt::Display, str::FromStr};
+
+use bigdecimal::{BigDecimal, Signed};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "storage")]
+use diesel::{
+    backend::Backend,
+    pg::Pg,
+    result::DatabaseErrorKind,
+    serialize::WriteTuple,
+    sql_types::{Integer, Numeric, Text},
+    types::{FromSql, Record, ToSql},
+    AsExpression, FromSqlRow, Insertable, Queryable,
+};
+
+#[cfg(feature = "storage")]
+use crate::schema::{api_keys, inventory, movements, stock};
+#[allow(unused_imports)]
+// Only named by `Trash`'s schema, utoipa takes the name rather than the type
+use crate::versions::ItemV1;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+#[cfg(feature = "storage")]
+use diesel::result::Error as DError;
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::Sqlite;
+use http::StatusCode;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong while handling a request
+///
+/// Errors are sent to clients as a JSON problem document (RFC 7807),
+/// `code` is the snake_case name of the variant, and the variant's fields
+/// are included alongside it. Codes and field names are stable, messages are not
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Error {
+    /// A row the request needed doesn't exist, and we don't know what kind of row it was
+    NotFound,
+    ItemNotFound {
+        item_id: i32,
+    },
+    WarehouseNotFound {
+        warehouse_id: i32,
+    },
+    WarehouseExists {
+        warehouse_id: i32,
+    },
+    /// Only items in the trash can be restored
+    ItemNotDeleted {
+        item_id: i32,
+    },
+    /// Only warehouses in the trash can be restored
+    WarehouseNotDeleted {
+        warehouse_id: i32,
+    },
+    ApiKeyNotFound {
+        key_id: i32,
+    },
+    /// The request had no API key, or one we don't know (or that was revoked)
+    Unauthenticated,
+    /// The request's API key doesn't have a role allowed to make it
+    Forbidden {
+        role: Role,
+        required: Role,
+    },
+    /// The item is stocked by another warehouse
+    AlreadyAssigned {
+        item_id: i32,
+        warehouse_id: i32,
+        requested_warehouse_id: i32,
+    },
+    /// The item isn't stocked by the warehouse the request named
+    NotAssigned {
+        item_id: i32,
+        warehouse_id: Option<i32>,
+        requested_warehouse_id: i32,
+    },
+    InsufficientStock {
+        item_id: i32,
+        warehouse_id: i32,
+        requested: i32,
+        on_hand: i32,
+    },
+    /// Adding the units would take the quantity on hand past the largest a warehouse can hold
+    StockOverflow {
+        item_id: i32,
+        warehouse_id: i32,
+        requested: i32,
+        on_hand: i32,
+    },
+    InvalidQuantity {
+        quantity: i32,
+    },
+    /// A new item was given a quantity but no warehouse to hold it
+    QuantityWithoutWarehouse {
+        quantity: i32,
+    },
+    SelfTransfer {
+        warehouse_id: i32,
+    },
+    DuplicateItem {
+        item_id: i32,
+    },
+    /// The field can't be changed by this endpoint
+    ImmutableField {
+        item_id: i32,
+        field: String,
+    },
+    UniqueViolation {
+        column: Option<String>,
+        detail: String,
+    },
+    ReferenceViolation {
+        detail: String,
+    },
+    /// The request was understood, but some of its fields break the rules
+    Validation {
+        errors: Vec<FieldError>,
+    },
+    /// A change to an item or warehouse came without an `If-Match` header
+    PreconditionRequired,
+    /// The item or warehouse has changed since the version named by `If-Match`
+    PreconditionFailed {
+        version: i32, // Its current version
+    },
+    /// A request with the same `Idempotency-Key` is still being handled
+    IdempotencyKeyInUse,
+    /// The `Idempotency-Key` was already used for a different request
+    IdempotencyKeyReused,
+    /// Some rows of a bulk import were rejected, so none of them were imported
+    ImportFailed {
+        errors: Vec<LineError>,
+    },
+    /// An operation of a batch failed, so none of them were kept
+    BatchFailed {
+        errors: Vec<OperationError>,
+    },
+    /// The request body, path, or query string couldn't be understood
+    InvalidRequest {
+        detail: String,
+    },
+    Unsupported {
+        detail: String,
+    },
+    /// The server can't take requests yet, its database is unreachable or out of date
+    NotReady {
+        detail: String,
+        pending_migrations: Vec<String>,
+    },
+    Database {
+        detail: String,
+    },
+    Internal {
+        detail: String,
+    },
+}
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::NotFound
+            | Error::ItemNotFound { .. }
+            | Error::WarehouseNotFound { .. }
+            | Error::ApiKeyNotFound { .. } => StatusCode::NOT_FOUND,
+            Error::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Error::Forbidden { .. } => StatusCode::FORBIDDEN,
+            Error::WarehouseExists { .. }
+            | Error::ItemNotDeleted { .. }
+            | Error::WarehouseNotDeleted { .. }
+            | Error::AlreadyAssigned { .. }
+            | Error::NotAssigned { .. }
+            | Error::InsufficientStock { .. }
+            | Error::StockOverflow { .. }
+            | Error::InvalidQuantity { .. }
+            | Error::QuantityWithoutWarehouse { .. }
+            | Error::SelfTransfer { .. }
+            | Error::DuplicateItem { .. }
+            | Error::ImmutableField { .. }
+            | Error::UniqueViolation { .. }
+            | Error::ReferenceViolation { .. }
+            | Error::InvalidRequest { .. } => StatusCode::BAD_REQUEST,
+            Error::IdempotencyKeyInUse => StatusCode::CONFLICT,
+            Error::Validation { .. }
+            | Error::IdempotencyKeyReused
+            | Error::ImportFailed { .. }
+            | Error::BatchFailed { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            Error::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            Error::Unsupported { .. } => StatusCode::NOT_IMPLEMENTED,
+            Error::NotReady { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Database { .. } | Error::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The `code` clients see, the snake_case name of the variant
+    pub fn code(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|problem| Some(problem.get("code")?.as_str()?.to_string()))
+            .unwrap_or_default()
+    }
+
+    /// A human readable description, for people rather than programs
+    pub fn message(&self) -> String {
+        match self {
+            Error::NotFound => "Not found".to_string(),
+            Error::ItemNotFound { item_id } => format!("Item id {item_id} does not exist"),
+            Error::WarehouseNotFound { warehouse_id } => {
+                format!("Warehouse id {warehouse_id} does not exist")
+            }
+            Error::WarehouseExists { warehouse_id } => {
+                format!("Warehouse id {warehouse_id} already exists")
+            }
+            Error::ItemNotDeleted { item_id } => format!("Item id {item_id} is not in the trash"),
+            Error::WarehouseNotDeleted { warehouse_id } => {
+                format!("Warehouse id {warehouse_id} is not in the trash")
+            }
+            Error::ApiKeyNotFound { key_id } => format!("API key id {key_id} does not exist"),
+            Error::Unauthenticated => {
+                "A valid API key is required, send it as `Authorization: Bearer <key>`".to_string()
+            }
+            Error::Forbidden { role, required } => {
+                format!("This request needs the {required} role, the API key has the {role} role")
+            }
+            Error::AlreadyAssigned {
+                item_id,
+                warehouse_id,
+                requested_warehouse_id,
+            } => format!("Cannot assign item id {item_id} to warehouse id {requested_warehouse_id} as it already belongs to warehouse id {warehouse_id}"),
+            Error::NotAssigned {
+                item_id,
+                warehouse_id: Some(warehouse_id),
+                requested_warehouse_id,
+            } => format!("Item id {item_id} does not belong to warehouse id {requested_warehouse_id}, belongs to warehouse id {warehouse_id}"),
+            Error::NotAssigned {
+                item_id,
+                warehouse_id: None,
+                ..
+            } => format!("Item id {item_id} does not belong to any warehouse"),
+            Error::InsufficientStock {
+                item_id,
+                warehouse_id,
+                requested,
+                on_hand,
+            } => format!("Cannot remove {requested} of item id {item_id}, warehouse id {warehouse_id} only has {on_hand} on hand"),
+            Error::StockOverflow {
+                item_id,
+                warehouse_id,
+                requested,
+                on_hand,
+            } => format!("Cannot add {requested} of item id {item_id}, warehouse id {warehouse_id} already has {on_hand} on hand, and can hold at most {}", i32::MAX),
+            Error::InvalidQuantity { quantity } => {
+                format!("Quantity must be positive, got {quantity}")
+            }
+            Error::QuantityWithoutWarehouse { .. } => {
+                "Cannot create item with a quantity but no warehouse".to_string()
+            }
+            Error::SelfTransfer { warehouse_id } => {
+                format!("Cannot transfer items from warehouse id {warehouse_id} to itself")
+            }
+            Error::DuplicateItem { item_id } => format!("Item id {item_id} is listed more than once"),
+            Error::ImmutableField { field, .. } => format!(
+                "Updating an item's {field} is not supported, use the warehouse item add/remove endpoint"
+            ),
+            Error::UniqueViolation {
+                column: Some(col),
+                detail,
+            } => format!("Uniqueness violation on column {col}; {detail}"),
+            Error::UniqueViolation { column: None, detail } => {
+                format!("Uniqueness violation: {detail}")
+            }
+            Error::ReferenceViolation { detail } => format!("Reference violation: {detail}"),
+            Error::Validation { errors } => {
+                let fields: Vec<_> = errors
+                    .iter()
+                    .map(|e| format!("{} {}", e.field, e.message))
+                    .collect();
+                format!("Invalid fields: {}", fields.join(", "))
+            }
+            Error::PreconditionRequired => {
+                "Send the `ETag` of the version being changed as `If-Match`, or `*` for any version"
+                    .to_string()
+            }
+            Error::PreconditionFailed { version } => {
+                format!("It has changed since the version in `If-Match`, it is now at version {version}")
+            }
+            Error::IdempotencyKeyInUse => {
+                "A request with this `Idempotency-Key` is still being handled, retry it once it's done"
+                    .to_string()
+            }
+            Error::IdempotencyKeyReused => {
+                "This `Idempotency-Key` was already used for a different request, use a new key for each change"
+                    .to_string()
+            }
+            Error::ImportFailed { errors } => {
+                format!("{} rows were rejected, nothing was imported", errors.len())
+            }
+            Error::BatchFailed { errors } => {
+                let failed: Vec<_> = errors.iter().map(|e| e.operation.to_string()).collect();
+                format!(
+                    "Operation {} failed, none of the batch was kept",
+                    failed.join(", ")
+                )
+            }
+            Error::InvalidRequest { detail } => format!("Invalid request: {detail}"),
+            Error::Unsupported { detail }
+            | Error::NotReady { detail, .. }
+            | Error::Database { detail }
+            | Error::Internal { detail } => {
+                detail.clone()
+            }
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+/// A single rule a field of the request broke
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,   // Path to the field, like `dimensions.width` or `items[2]`
+    pub code: String,    // Name of the rule, like `range`
+    pub message: String, // What the rule requires
+}
+
+impl From<ValidationErrors> for Error {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut flat = Vec::new();
+        flatten_errors(&errors, "", &mut flat);
+        Error::Validation { errors: flat }
+    }
+}
+
+// `validator` nests the errors of nested structs and lists,
+// clients get one flat list with the path to each field instead
+fn flatten_errors(errors: &ValidationErrors, prefix: &str, out: &mut Vec<FieldError>) {
+    // Sort by field name, so the order doesn't depend on a `HashMap`
+    let mut fields: Vec<_> = errors.errors().iter().collect();
+    fields.sort_by_key(|(field, _)| *field);
+
+    for (field, kind) in fields {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{prefix}.{field}")
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errs) => {
+                out.extend(errs.iter().map(|e| {
+                    FieldError {
+                        field: path.clone(),
+                        code: e.code.to_string(),
+                        message: e
+                            .message
+                            .as_ref()
+                            .map(|msg| msg.to_string())
+                            .unwrap_or_else(|| "is invalid".to_string()),
+                    }
+                }));
+            }
+            ValidationErrorsKind::Struct(nested) => flatten_errors(nested, &path, out),
+            ValidationErrorsKind::List(list) => {
+                for (i, nested) in list {
+                    flatten_errors(nested, &format!("{path}[{i}]"), out);
+                }
+            }
+        }
+    }
+}
+
+/// Why a line of a bulk import was rejected
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LineError {
+    pub line: u64, // Line of the file, the header is line 1
+    pub message: String,
+    #[serde(flatten)]
+    pub error: Error,
+}
+
+impl LineError {
+    pub fn new(line: u64, error: Error) -> Self {
+        LineError {
+            line,
+            message: error.message(),
+            error,
+        }
+    }
+}
+
+/// Why an operation of a batch failed
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OperationError {
+    pub operation: usize, // Its place in the batch, the first is 0
+    pub message: String,
+    #[serde(flatten)]
+    pub error: Error,
+}
+
+impl OperationError {
+    pub fn new(operation: usize, error: Error) -> Self {
+        OperationError {
+            operation,
+            message: error.message(),
+            error,
+        }
+    }
+}
+
+/// The outcome of a bulk import where every row was accepted
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    pub dry_run: bool,   // When set, nothing was written
+    pub imported: usize, // Number of rows imported, or that would have been
+    pub ids: Vec<i32>,   // Ids of the imported rows, empty for a dry run
+}
+
+/// Where some units of an item are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Holding {
+    pub warehouse_id: i32,
+    pub quantity: i32,
+}
+
+/// How the stock and the movements ledger disagree about an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum InconsistencyKind {
+    WrongWarehouse,   // Held by another warehouse than the ledger says
+    WrongQuantity,    // Held by the warehouse the ledger says, with a different quantity
+    Unstocked,        // The ledger puts it in a warehouse, but no warehouse holds it
+    Unrecorded,       // Held by a warehouse, but the ledger puts it in none
+    NoHistory,        // Held by a warehouse, with no movements at all
+    Unbalanced,       // Its movements don't add up, so where the ledger has it can't be told
+    TrashedItem,      // In the trash, but still held by a warehouse
+    TrashedWarehouse, // Held by a warehouse that's in the trash
+}
+
+/// An item the stock and the movements ledger disagree about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Inconsistency {
+    pub item_id: i32,
+    pub kind: InconsistencyKind,
+    pub stock: Option<Holding>,  // Where the stock has it
+    pub ledger: Option<Holding>, // Where replaying its movements puts it
+}
+
+/// Which side to believe when repairing an inconsistency,
+/// nothing in the trash is left holding or held by anything either way,
+/// stock with no movements at all is kept and recorded, and items whose
+/// movements don't add up are only reported, they're left for a person to look into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum RepairPolicy {
+    Ledger, // Change the stock to match the movements
+    Stock,  // Record movements that bring the ledger in line with the stock
+}
+
+/// The outcome of checking every item's stock against its movements
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConsistencyReport {
+    pub inconsistencies: Vec<Inconsistency>,
+    pub repaired: Option<RepairPolicy>, // The policy they were repaired under, if they were
+}
+
+/// The body of an error response
+#[derive(Serialize, ToSchema)]
+pub struct Problem<'a> {
+    #[serde(rename = "type")]
+    pub type_: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub message: String,
+    #[serde(flatten)]
+    pub error: &'a Error,
+}
+
+impl<'a> From<&'a Error> for Problem<'a> {
+    fn from(error: &'a Error) -> Self {
+        let status = error.status();
+        Problem {
+            // We don't publish a page per problem, so every problem is of the generic type
+            type_: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            message: error.message(),
+            error,
+        }
+    }
+}
+
+/// The `code` of the error a response was made from, kept in the response's extensions
+/// so middleware can see it without reading the body
+#[derive(Debug, Clone)]
+pub struct ErrorCode(pub String);
+
+#[cfg(feature = "actix")]
+impl actix_web::ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse {
+        use actix_web::http::header;
+
+        let mut resp = actix_web::HttpResponse::build(self.status());
+        if let Error::Unauthenticated = self {
+            // Tells the client how to authenticate, required alongside a 401
+            resp.header(header::WWW_AUTHENTICATE, "Bearer");
+        }
+        let mut resp = resp
+            .content_type("application/problem+json")
+            .json(Problem::from(self));
+        resp.extensions_mut().insert(ErrorCode(self.code()));
+        resp
+    }
+}
+
+#[cfg(feature = "storage")]
+impl From<diesel::result::Error> for Error {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            DError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                // Ids are only picked by clients through the import endpoints
+                // so it's on them to provide a unique one
+                Error::UniqueViolation {
+                    column: info.column_name().map(str::to_string),
+                    detail: info.message().to_string(),
+                }
+            }
+            DError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) => {
+                Error::ReferenceViolation {
+                    detail: info.message().to_string(),
+                }
+            }
+            DError::DatabaseError(_, info) => Error::Database {
+                detail: info.message().to_string(),
+            },
+            DError::NotFound => Error::NotFound,
+            _ => Error::Database {
+                detail: "Unknown database error".to_string(),
+            },
+        }
+    }
+}
+
+// Extension trait for `std::result::Result<T, Error>`
+pub trait NotFound<T> {
+    fn not_found(self, fnerr: impl Fn() -> Error) -> Result<T>;
+}
+
+impl<T> NotFound<T> for Result<T> {
+    /// Replace a generic not-found error with a more specific one, generated lazily by `fnerr`
+    fn not_found(self, fnerr: impl Fn() -> Error) -> Result<T> {
+        if let Err(Error::NotFound) = self {
+            // The db layer doesn't know what it was looking for
+            // but the caller does
+            Err(fnerr())
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[cfg_attr(
+    feature = "storage",
+    derive(FromSqlRow, AsExpression),
+    sql_type = "PgTransport"
+)]
+#[cfg_attr(feature = "sqlite", sql_type = "SqliteTransport")]
+pub enum Transport {
+    Air,
+    Sea,
+    Land,
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "storage")]
+#[derive(SqlType)]
+#[postgres(type_name = "transport")]
+pub struct PgTransport;
+
+impl FromStr for Transport {
+    type Err = ();
+    fn from_str(string: &str) -> std::result::Result<Self, Self::Err> {
+        let variant = match string {
+            "Air" => Self::Air,
+            "Sea" => Self::Sea,
+            "Land" => Self::Land,
+            _ => return Err(()),
+        };
+        Ok(variant)
+    }
+}
+
+#[cfg(feature = "storage")]
+impl ToSql<PgTransport, Pg> for Transport {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Pg>,
+    ) -> diesel::serialize::Result {
+        let sql = format!("{:?}", self);
+        ToSql::<Text, Pg>::to_sql(&sql, out)
+    }
+}
+
+#[cfg(feature = "storage")]
+impl FromSql<PgTransport, Pg> for Transport {
+    fn from_sql(bytes: Option<&<Pg as Backend>::RawValue>) -> diesel::deserialize::Result<Self> {
+        let string: String = FromSql::<Text, Pg>::from_sql(bytes)?;
+
+        // Postgresql stops us from inserting bad values
+        // because we're using the enum data type
+        // ergo, this should never fail
+        let variant =
+            Transport::from_str(&string).expect("SQL contains an invalid variant of Transport");
+        Ok(variant)
+    }
+}
+
+// SQLite has no enum types, the column is text with a CHECK listing the variants
+#[cfg(feature = "sqlite")]
+#[derive(SqlType)]
+#[sqlite_type = "Text"]
+pub struct SqliteTransport;
+
+#[cfg(feature = "sqlite")]
+impl ToSql<SqliteTransport, Sqlite> for Transport {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Sqlite>,
+    ) -> diesel::serialize::Result {
+        let sql = format!("{:?}", self);
+        ToSql::<Text, Sqlite>::to_sql(&sql, out)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl FromSql<SqliteTransport, Sqlite> for Transport {
+    fn from_sql(
+        bytes: Option<&<Sqlite as Backend>::RawValue>,
+    ) -> diesel::deserialize::Result<Self> {
+        let string: String = FromSql::<Text, Sqlite>::from_sql(bytes)?;
+
+        // The CHECK keeps bad values out, like the enum data type does in Postgresql
+        let variant =
+            Transport::from_str(&string).expect("SQL contains an invalid variant of Transport");
+        Ok(variant)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[cfg_attr(
+    feature = "storage",
+    derive(FromSqlRow, AsExpression),
+    sql_type = "PgMovementReason"
+)]
+#[cfg_attr(feature = "sqlite", sql_type = "SqliteMovementReason")]
+pub enum MovementReason {
+    Received,
+    Removed,
+    ItemDeleted,
+    WarehouseDeleted,
+    Transferred,
+    Restored,
+}
+
+impl Display for MovementReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "storage")]
+#[derive(SqlType)]
+#[postgres(type_name = "movement_reason")]
+pub struct PgMovementReason;
+
+impl FromStr for MovementReason {
+    type Err = ();
+    fn from_str(string: &str) -> std::result::Result<Self, Self::Err> {
+        let variant = match string {
+            "Received" => Self::Received,
+            "Removed" => Self::Removed,
+            "ItemDeleted" => Self::ItemDeleted,
+            "WarehouseDeleted" => Self::WarehouseDeleted,
+            "Transferred" => Self::Transferred,
+            "Restored" => Self::Restored,
+            _ => return Err(()),
+        };
+        Ok(variant)
+    }
+}
+
+#[cfg(feature = "storage")]
+impl ToSql<PgMovementReason, Pg> for MovementReason {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Pg>,
+    ) -> diesel::serialize::Result {
+        let sql = format!("{:?}", self);
+        ToSql::<Text, Pg>::to_sql(&sql, out)
+    }
+}
+
+#[cfg(feature = "storage")]
+impl FromSql<PgMovementReason, Pg> for MovementReason {
+    fn from_sql(bytes: Option<&<Pg as Backend>::RawValue>) -> diesel::deserialize::Result<Self> {
+        let string: String = FromSql::<Text, Pg>::from_sql(bytes)?;
+
+        // Same as `Transport`, the enum data type keeps bad values out
+        let variant = MovementReason::from_str(&string)
+            .expect("SQL contains an invalid variant of MovementReason");
+        Ok(variant)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(SqlType)]
+#[sqlite_type = "Text"]
+pub struct SqliteMovementReason;
+
+#[cfg(feature = "sqlite")]
+impl ToSql<SqliteMovementReason, Sqlite> for MovementReason {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Sqlite>,
+    ) -> diesel::serialize::Result {
+        let sql = format!("{:?}", self);
+        ToSql::<Text, Sqlite>::to_sql(&sql, out)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl FromSql<SqliteMovementReason, Sqlite> for MovementReason {
+    fn from_sql(
+        bytes: Option<&<Sqlite as Backend>::RawValue>,
+    ) -> diesel::deserialize::Result<Self> {
+        let string: String = FromSql::<Text, Sqlite>::from_sql(bytes)?;
+
+        // Same as `Transport`, the CHECK keeps bad values out
+        let variant = MovementReason::from_str(&string)
+            .expect("SQL contains an invalid variant of MovementReason");
+        Ok(variant)
+    }
+}
+
+/// What an API key is allowed to do, each role can do everything the roles before it can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[cfg_attr(
+    feature = "storage",
+    derive(FromSqlRow, AsExpression),
+    sql_type = "PgRole"
+)]
+#[cfg_attr(feature = "sqlite", sql_type = "SqliteRole")]
+pub enum Role {
+    ReadOnly, // Read anything
+    Operator, // Create, update, and move stock
+    Admin,    // Delete warehouses and manage API keys
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "storage")]
+#[derive(SqlType)]
+#[postgres(type_name = "api_role")]
+pub struct PgRole;
+
+impl FromStr for Role {
+    type Err = ();
+    fn from_str(string: &str) -> std::result::Result<Self, Self::Err> {
+        let variant = match string {
+            "ReadOnly" => Self::ReadOnly,
+            "Operator" => Self::Operator,
+            "Admin" => Self::Admin,
+            _ => return Err(()),
+        };
+        Ok(variant)
+    }
+}
+
+#[cfg(feature = "storage")]
+impl ToSql<PgRole, Pg> for Role {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Pg>,
+    ) -> diesel::serialize::Result {
+        let sql = format!("{:?}", self);
+        ToSql::<Text, Pg>::to_sql(&sql, out)
+    }
+}
+
+#[cfg(feature = "storage")]
+impl FromSql<PgRole, Pg> for Role {
+    fn from_sql(bytes: Option<&<Pg as Backend>::RawValue>) -> diesel::deserialize::Result<Self> {
+        let string: String = FromSql::<Text, Pg>::from_sql(bytes)?;
+
+        // Same as `Transport`, the enum data type keeps bad values out
+        let variant = Role::from_str(&string).expect("SQL contains an invalid variant of Role");
+        Ok(variant)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(SqlType)]
+#[sqlite_type = "Text"]
+pub struct SqliteRole;
+
+#[cfg(feature = "sqlite")]
+impl ToSql<SqliteRole, Sqlite> for Role {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Sqlite>,
+    ) -> diesel::serialize::Result {
+        let sql = format!("{:?}", self);
+        ToSql::<Text, Sqlite>::to_sql(&sql, out)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl FromSql<SqliteRole, Sqlite> for Role {
+    fn from_sql(
+        bytes: Option<&<Sqlite as Backend>::RawValue>,
+    ) -> diesel::deserialize::Result<Self> {
+        let string: String = FromSql::<Text, Sqlite>::from_sql(bytes)?;
+
+        // Same as `Transport`, the CHECK keeps bad values out
+        let variant = Role::from_str(&string).expect("SQL contains an invalid variant of Role");
+        Ok(variant)
+    }
+}
+
+/// An exact amount of money in a currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate, ToSchema)]
+#[cfg_attr(
+    feature = "storage",
+    derive(FromSqlRow, AsExpression),
+    sql_type = "PgMoney"
+)]
+pub struct Money {
+    #[validate(custom = "validate_amount")]
+    #[schema(value_type = String, example = "12.50")]
+    pub amount: BigDecimal, // Serialized as a string, so no precision is lost
+    #[validate(custom = "validate_currency")]
+    pub currency: String, // ISO 4217 code, like `USD`
+}
+
+#[cfg(feature = "storage")]
+#[derive(SqlType)]
+#[postgres(type_name = "money_value")]
+pub struct PgMoney;
+
+#[cfg(feature = "storage")]
+impl ToSql<PgMoney, Pg> for Money {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Pg>,
+    ) -> diesel::serialize::Result {
+        WriteTuple::<(Numeric, Text)>::write_tuple(&(&self.amount, &self.currency), out)
+    }
+}
+
+#[cfg(feature = "storage")]
+impl FromSql<PgMoney, Pg> for Money {
+    fn from_sql(bytes: Option<&<Pg as Backend>::RawValue>) -> diesel::deserialize::Result<Self> {
+        let (amount, currency) = FromSql::<Record<(Numeric, Text)>, Pg>::from_sql(bytes)?;
+        Ok(Self { amount, currency })
+    }
+}
+
+// The `amount` column holds 15 digits before the point and 4 after
+fn validate_amount(amount: &BigDecimal) -> std::result::Result<(), ValidationError> {
+    let (code, message) = if amount.is_negative() {
+        ("range", "must not be negative")
+    } else if amount.with_scale(4) != *amount {
+        ("precision", "must not have more than 4 decimal places")
+    } else if *amount >= BigDecimal::from(1_000_000_000_000_000i64) {
+        ("range", "must be less than 10^15")
+    } else {
+        return Ok(());
+    };
+
+    let mut err = ValidationError::new(code);
+    err.message = Some(message.into());
+    Err(err)
+}
+
+fn validate_currency(currency: &str) -> std::result::Result<(), ValidationError> {
+    if currency.len() == 3 && currency.bytes().all(|b| b.is_ascii_uppercase()) {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("currency");
+        err.message = Some("must be a three letter currency code, like USD".into());
+        Err(err)
+    }
+}
+
+/// Dimensions in mm
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate, ToSchema)]
+#[cfg_attr(
+    feature = "storage",
+    derive(FromSqlRow, AsExpression),
+    sql_type = "PgDimensions"
+)]
+pub struct Dimensions {
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub width: i32,
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub height: i32,
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub depth: i32,
+}
+
+#[cfg(feature = "storage")]
+#[derive(SqlType)]
+#[postgres(type_name = "dimensions_mm")]
+pub struct PgDimensions;
+
+// SQLite has no composite types either, so `Money` and `Dimensions` are stored
+// as a column per field there, split up and put back together in `sqlite.rs`
+
+#[cfg(feature = "storage")]
+impl ToSql<PgDimensions, Pg> for Dimensions {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Pg>,
+    ) -> diesel::serialize::Result {
+        WriteTuple::<(Integer, Integer, Integer)>::write_tuple(
+            &(self.width, self.height, self.depth),
+            out,
+        )
+    }
+}
+
+#[cfg(feature = "storage")]
+impl FromSql<PgDimensions, Pg> for Dimensions {
+    fn from_sql(bytes: Option<&<Pg as Backend>::RawValue>) -> diesel::deserialize::Result<Self> {
+        let (width, height, depth) =
+            FromSql::<Record<(Integer, Integer, Integer)>, Pg>::from_sql(bytes)?;
+        Ok(Self {
+            width,
+            height,
+            depth,
+        })
+    }
+}
+
+// `warehouse` and `quantity` are not stored on the item row,
+// they are read from the item's `stock` row whenever an item is loaded
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+#[cfg_attr(feature = "storage", derive(Queryable))]
+pub struct InventoryItem {
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub id: i32, // Id of this item
+    pub warehouse: Option<i32>, // Optional warehouse id
+    #[serde(default)]
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub quantity: i32, // Units on hand, 0 when in no warehouse
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub weight_g: i64, // Weight in g
+    #[validate]
+    pub value: Money, // Value of one unit
+    pub transport: Transport,   // Transportation method
+    #[validate]
+    pub dimensions_mm: Dimensions, // Dimensions in mm
+    #[serde(default)]
+    #[schema(read_only)]
+    pub version: i32, // Goes up every time the item changes, sent as its `ETag`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(read_only)]
+    pub deleted_at: Option<DateTime<Utc>>, // When it was moved to the trash, missing until then
+}
+
+#[cfg(feature = "storage")]
+impl InventoryItem {
+    pub fn row(&self) -> ItemRow<'_> {
+        ItemRow {
+            weight_g: self.weight_g,
+            value: &self.value,
+            transport: &self.transport,
+            dimensions_mm: &self.dimensions_mm,
+        }
+    }
+}
+
+// The body of a create request, the id is assigned by the database
+#[derive(Debug, Serialize, Deserialize, Validate, ToSchema)]
+pub struct NewInventoryItem {
+    pub warehouse: Option<i32>, // Optional warehouse id
+    #[serde(default)]
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub quantity: Option<i32>, // Units on hand, defaults to 1 when a warehouse is given
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub weight_g: i64, // Weight in g
+    #[validate]
+    pub value: Money, // Value of one unit
+    pub transport: Transport,   // Transportation method
+    #[validate]
+    pub dimensions_mm: Dimensions, // Dimensions in mm
+}
+
+#[cfg(feature = "storage")]
+impl NewInventoryItem {
+    pub fn row(&self) -> ItemRow<'_> {
+        ItemRow {
+            weight_g: self.weight_g,
+            value: &self.value,
+            transport: &self.transport,
+            dimensions_mm: &self.dimensions_mm,
+        }
+    }
+}
+
+/// The columns of an item that are stored on the `inventory` row
+#[cfg(feature = "storage")]
+#[derive(Debug, Insertable, AsChangeset)]
+#[table_name = "inventory"]
+pub struct ItemRow<'a> {
+    pub weight_g: i64,
+    pub value: &'a Money,
+    pub transport: &'a Transport,
+    pub dimensions_mm: &'a Dimensions,
+}
+
+/// How many units of an item a warehouse has on hand
+#[cfg(feature = "storage")]
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "stock"]
+pub struct Stock {
+    pub item: i32,
+    pub warehouse: i32,
+    pub quantity: i32,
+}
+
+// `items` is not stored on the warehouse row,
+// it is computed from the `stock` table whenever a warehouse is loaded
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct Warehouse {
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub id: i32, // Id of this warehouse
+    #[validate(custom = "validate_item_ids")]
+    pub items: Vec<i32>, // Items in the warehouse
+    #[serde(default)]
+    #[schema(read_only)]
+    pub version: i32, // Goes up every time an item arrives or leaves, sent as its `ETag`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(read_only)]
+    pub deleted_at: Option<DateTime<Utc>>, // When it was moved to the trash, missing until then
+}
+
+/// What's in the trash, most recently deleted first
+// Documented with version 1 items, the OpenAPI document works out the version 2 schema
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Trash<Items = Vec<InventoryItem>> {
+    #[schema(value_type = Vec<ItemV1>)]
+    pub items: Items,
+    pub warehouses: Vec<Warehouse>,
+}
+
+// The outcome of moving items between warehouses
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Transfer {
+    pub from: Warehouse, // The warehouse the items left
+    pub to: Warehouse,   // The warehouse the items arrived at
+}
+
+// The body of a create request, the id is assigned by the database
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct NewWarehouse {
+    #[serde(default)]
+    #[validate(custom = "validate_item_ids")]
+    pub items: Vec<i32>, // Items to add to the warehouse
+}
+
+// A list of items may not name the same item twice
+fn validate_item_ids(items: &[i32]) -> std::result::Result<(), ValidationError> {
+    let mut seen = HashSet::with_capacity(items.len());
+    if items.iter().all(|id_| seen.insert(id_)) {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("duplicate");
+        err.message = Some("must not list the same item more than once".into());
+        Err(err)
+    }
+}
+
+/// A change to an item's stock, movements are never updated or deleted
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[cfg_attr(feature = "storage", derive(Queryable))]
+pub struct Movement {
+    pub id: i64,                     // Id of this movement, increasing over time
+    pub item: i32,                   // The item that moved
+    pub from_warehouse: Option<i32>, // Where it was, if anywhere
+    pub to_warehouse: Option<i32>,   // Where it went, if anywhere
+    pub quantity: i32,               // How many units moved
+    pub reason: MovementReason,      // Why it moved
+    pub created_at: DateTime<Utc>,   // When it moved
+    pub actor: String,               // Who moved it
+}
+
+#[cfg(feature = "storage")]
+#[derive(Debug, Insertable)]
+#[table_name = "movements"]
+pub struct NewMovement<'a> {
+    pub item: i32,
+    pub from_warehouse: Option<i32>,
+    pub to_warehouse: Option<i32>,
+    pub quantity: i32,
+    pub reason: MovementReason,
+    pub actor: &'a str,
+}
+
+/// A key clients authenticate with, only its hash is kept
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[cfg_attr(feature = "storage", derive(Queryable))]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String, // Who or what the key was issued to, recorded as the actor of its requests
+    pub role: Role,
+    #[serde(skip_serializing, default)]
+    pub key_hash: String, // SHA-256 of the key, hex encoded
+    pub prefix: String, // The start of the key, enough to tell keys apart
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>, // Revoked keys are kept, but no longer accepted
+}
+
+// The body of an issue request, the key itself is generated by the server
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct NewApiKey {
+    #[validate(length(min = 1, max = 100, message = "must be between 1 and 100 characters"))]
+    pub name: String,
+    pub role: Role,
+}
+
+#[cfg(feature = "storage")]
+#[derive(Debug, Insertable)]
+#[table_name = "api_keys"]
+pub struct ApiKeyRow<'a> {
+    pub name: &'a str,
+    pub role: Role,
+    pub key_hash: &'a str,
+    pub prefix: &'a str,
+}
+
+/// A change made with an `Idempotency-Key`, and the response it got
+#[derive(Debug, Clone)]
+pub struct IdempotentRequest {
+    pub key_id: i32, // The API key it was made with, clients only have to keep their keys unique per API key
+    pub idempotency_key: String,
+    pub fingerprint: String, // SHA-256 of its method, path and body, hex encoded
+    pub response: Option<StoredResponse>, // Missing while it's being handled
+    pub created_at: DateTime<Utc>,
+}
+
+/// A response kept to be sent again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl StoredResponse {
+    /// The response as kept in the `status`, `headers` and `body` columns,
+    /// `None` while the request is still being handled
+    pub fn from_columns(
+        status: Option<i32>,
+        headers: Option<String>,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<Self>> {
+        let (status, headers, body) = match (status, headers, body) {
+            (Some(status), Some(headers), Some(body)) => (status, headers, body),
+            _ => return Ok(None),
+        };
+        let headers = serde_json::from_str(&headers).map_err(|e| Error::Database {
+            detail: format!("Invalid stored headers: {e}"),
+        })?;
+
+        Ok(Some(StoredResponse {
+            status: status as u16,
+            headers,
+            body,
+        }))
+    }
+
+    /// The headers as kept in the `headers` column
+    pub fn headers_column(&self) -> String {
+        serde_json::to_string(&self.headers).unwrap_or_default()
+    }
+}
+
+/// A newly issued API key, the only time the key itself is ever shown
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IssuedKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
@@ -0,0 +1,494 @@
+/// Paging, sorting, and filtering for the list endpoints
+// Lists are paged with keyset cursors rather than offsets,
+// a cursor remembers the sort key and id of the last row of a page,
+// and the next page starts right after it. Rows added or removed
+// between requests don't shift later pages around
+use std::sync::OnceLock;
+
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::IntoParams;
+use validator::{Validate, ValidationError};
+
+use crate::models::{Error, FieldError, InventoryItem, Result, Transport, Warehouse};
+
+/// Default number of rows in a page, unless configured otherwise
+pub const DEFAULT_LIMIT: i64 = 100;
+
+/// The most rows a page can have, unless configured otherwise
+pub const MAX_LIMIT: i64 = 1000;
+
+/// The page sizes this server was configured with
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub default: i64,
+    pub max: i64,
+}
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// Set the page sizes, once, at startup
+pub fn set_limits(limits: Limits) {
+    LIMITS.set(limits).expect("Page limits were already set");
+}
+
+pub fn limits() -> Limits {
+    *LIMITS.get_or_init(|| Limits {
+        default: DEFAULT_LIMIT,
+        max: MAX_LIMIT,
+    })
+}
+
+// Checked against the configured maximum, so it can't be a `range` rule
+fn validate_limit(limit: i64) -> std::result::Result<(), ValidationError> {
+    let max = limits().max;
+    if (1..=max).contains(&limit) {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("range");
+        err.message = Some(format!("must be between 1 and {max}").into());
+        Err(err)
+    }
+}
+
+/// One page of a list, and the cursor to the page after it, if there is one
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: T,
+    pub next: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Page<U> {
+        Page {
+            items: f(self.items),
+            next: self.next,
+        }
+    }
+}
+
+/// The query string of a list, which can be moved along to other pages
+pub trait Paged: Clone {
+    /// The limit the client asked for, if they did
+    fn requested_limit(&self) -> Option<i64>;
+
+    /// The cursor the client gave, if they did
+    fn cursor(&self) -> Option<String>;
+
+    /// The same list, starting after `cursor` with pages of `limit` rows
+    fn at(&self, cursor: Option<String>, limit: i64) -> Self;
+}
+
+/// The query string of an item list
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ItemParams {
+    #[validate(custom = "validate_limit")]
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>, // A key, like `weight`, or `-weight` for descending
+
+    pub transport: Option<Transport>,
+    #[param(value_type = Option<String>)]
+    pub warehouse: Option<WarehouseFilter>, // An id, or `null`
+    pub currency: Option<String>,
+    #[param(value_type = Option<String>)]
+    pub weight_gt: Option<BigDecimal>, // Weights are in kg
+    #[param(value_type = Option<String>)]
+    pub weight_gte: Option<BigDecimal>,
+    #[param(value_type = Option<String>)]
+    pub weight_lt: Option<BigDecimal>,
+    #[param(value_type = Option<String>)]
+    pub weight_lte: Option<BigDecimal>,
+    #[param(value_type = Option<String>)]
+    pub value_gt: Option<BigDecimal>, // Values are compared by amount, whatever the currency
+    #[param(value_type = Option<String>)]
+    pub value_gte: Option<BigDecimal>,
+    #[param(value_type = Option<String>)]
+    pub value_lt: Option<BigDecimal>,
+    #[param(value_type = Option<String>)]
+    pub value_lte: Option<BigDecimal>,
+    pub quantity_gt: Option<i32>,
+    pub quantity_gte: Option<i32>,
+    pub quantity_lt: Option<i32>,
+    pub quantity_lte: Option<i32>,
+    pub include_deleted: Option<bool>, // Items in the trash are left out unless this is set
+}
+
+/// The query string of a warehouse list
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WarehouseParams {
+    #[validate(custom = "validate_limit")]
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+    pub include_deleted: Option<bool>, // Warehouses in the trash are left out unless this is set
+}
+
+/// `warehouse=null` for items in no warehouse, `warehouse=3` for items in warehouse 3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarehouseFilter {
+    None,
+    Id(i32),
+}
+
+impl<'de> Deserialize<'de> for WarehouseFilter {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let string = String::deserialize(d)?;
+        if string == "null" {
+            Ok(WarehouseFilter::None)
+        } else {
+            string
+                .parse()
+                .map(WarehouseFilter::Id)
+                .map_err(|_| serde::de::Error::custom("expected a warehouse id or null"))
+        }
+    }
+}
+
+impl Serialize for WarehouseFilter {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            WarehouseFilter::None => s.serialize_str("null"),
+            WarehouseFilter::Id(id) => s.serialize_str(&id.to_string()),
+        }
+    }
+}
+
+/// A column items can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemKey {
+    Id,
+    Warehouse,
+    Quantity,
+    Weight,
+    Value,
+    Transport,
+    Width,
+    Height,
+    Depth,
+}
+
+/// The sql type a sort key is compared as
+pub enum KeyType {
+    Int,
+    Decimal,
+    Text,
+}
+
+impl ItemKey {
+    const ALL: [(&'static str, ItemKey); 9] = [
+        ("id", ItemKey::Id),
+        ("warehouse", ItemKey::Warehouse),
+        ("quantity", ItemKey::Quantity),
+        ("weight", ItemKey::Weight),
+        ("value", ItemKey::Value),
+        ("transport", ItemKey::Transport),
+        ("width", ItemKey::Width),
+        ("height", ItemKey::Height),
+        ("depth", ItemKey::Depth),
+    ];
+
+    pub fn name(self) -> &'static str {
+        ItemKey::ALL
+            .iter()
+            .find(|(_, key)| *key == self)
+            .map(|(name, _)| *name)
+            .unwrap_or("id")
+    }
+
+    /// The expression the key sorts by, it's never null
+    // These are only ever our own strings, never user input
+    pub fn sql(self) -> &'static str {
+        match self {
+            ItemKey::Id => "inventory.id",
+            // Items in no warehouse sort first, warehouse ids are always positive
+            ItemKey::Warehouse => "COALESCE(stock.warehouse, 0)",
+            ItemKey::Quantity => "COALESCE(stock.quantity, 0)",
+            ItemKey::Weight => "inventory.weight_g",
+            ItemKey::Value => "(inventory.value).amount",
+            ItemKey::Transport => "CAST(inventory.transport AS TEXT)",
+            ItemKey::Width => "(inventory.dimensions_mm).width",
+            ItemKey::Height => "(inventory.dimensions_mm).height",
+            ItemKey::Depth => "(inventory.dimensions_mm).depth",
+        }
+    }
+
+    pub fn key_type(self) -> KeyType {
+        match self {
+            ItemKey::Value => KeyType::Decimal,
+            ItemKey::Transport => KeyType::Text,
+            _ => KeyType::Int,
+        }
+    }
+
+    /// The value of the key for an item, as it's kept in a cursor
+    pub fn value_of(self, item: &InventoryItem) -> String {
+        match self {
+            ItemKey::Id => item.id.to_string(),
+            ItemKey::Warehouse => item.warehouse.unwrap_or(0).to_string(),
+            ItemKey::Quantity => item.quantity.to_string(),
+            ItemKey::Weight => item.weight_g.to_string(),
+            ItemKey::Value => item.value.amount.to_string(),
+            ItemKey::Transport => item.transport.to_string(),
+            ItemKey::Width => item.dimensions_mm.width.to_string(),
+            ItemKey::Height => item.dimensions_mm.height.to_string(),
+            ItemKey::Depth => item.dimensions_mm.depth.to_string(),
+        }
+    }
+}
+
+/// How a list is sorted, ties are always broken by id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort<K> {
+    pub key: K,
+    pub descending: bool,
+}
+
+impl Sort<ItemKey> {
+    pub fn parse_item(sort: Option<&str>) -> Result<Self> {
+        parse_sort(sort, &ItemKey::ALL, ItemKey::Id)
+    }
+
+    pub fn name(&self) -> String {
+        sort_name(self.key.name(), self.descending)
+    }
+}
+
+/// Warehouses only have an id to sort by
+impl Sort<()> {
+    pub fn parse_warehouse(sort: Option<&str>) -> Result<Self> {
+        parse_sort(sort, &[("id", ())], ())
+    }
+
+    pub fn name(&self) -> String {
+        sort_name("id", self.descending)
+    }
+}
+
+fn parse_sort<K: Copy>(sort: Option<&str>, keys: &[(&str, K)], default: K) -> Result<Sort<K>> {
+    let sort = match sort {
+        Some(sort) => sort,
+        None => {
+            return Ok(Sort {
+                key: default,
+                descending: false,
+            })
+        }
+    };
+
+    let (name, descending) = match sort.strip_prefix('-') {
+        Some(name) => (name, true),
+        None => (sort, false),
+    };
+
+    match keys.iter().find(|(key_name, _)| *key_name == name) {
+        Some((_, key)) => Ok(Sort {
+            key: *key,
+            descending,
+        }),
+        None => {
+            let names: Vec<_> = keys.iter().map(|(key_name, _)| *key_name).collect();
+            Err(Error::Validation {
+                errors: vec![FieldError {
+                    field: "sort".to_string(),
+                    code: "sort".to_string(),
+                    message: format!(
+                        "must be one of {}, optionally prefixed by -",
+                        names.join(", ")
+                    ),
+                }],
+            })
+        }
+    }
+}
+
+fn sort_name(key: &str, descending: bool) -> String {
+    if descending {
+        format!("-{key}")
+    } else {
+        key.to_string()
+    }
+}
+
+/// Where the previous page ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cursor {
+    pub sort: String, // The sort the cursor was made for
+    pub key: String,  // The sort key of the last row
+    pub id: i32,      // The id of the last row
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Failed to serialize!");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Decode a client's cursor, it must have been made for the same sort
+    pub fn decode(cursor: &str, sort: &str) -> Result<Self> {
+        let decoded: Cursor = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| Error::InvalidRequest {
+                detail: "cursor is not valid".to_string(),
+            })?;
+
+        if decoded.sort != sort {
+            return Err(Error::InvalidRequest {
+                detail: format!("cursor was made for sort={}, not sort={sort}", decoded.sort),
+            });
+        }
+
+        Ok(decoded)
+    }
+}
+
+impl ItemParams {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(limits().default)
+    }
+
+    pub fn include_deleted(&self) -> bool {
+        self.include_deleted.unwrap_or(false)
+    }
+}
+
+impl Paged for ItemParams {
+    fn requested_limit(&self) -> Option<i64> {
+        self.limit
+    }
+
+    fn cursor(&self) -> Option<String> {
+        self.cursor.clone()
+    }
+
+    fn at(&self, cursor: Option<String>, limit: i64) -> Self {
+        ItemParams {
+            cursor,
+            limit: Some(limit),
+            ..self.clone()
+        }
+    }
+}
+
+impl WarehouseParams {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(limits().default)
+    }
+
+    pub fn include_deleted(&self) -> bool {
+        self.include_deleted.unwrap_or(false)
+    }
+}
+
+impl Paged for WarehouseParams {
+    fn requested_limit(&self) -> Option<i64> {
+        self.limit
+    }
+
+    fn cursor(&self) -> Option<String> {
+        self.cursor.clone()
+    }
+
+    fn at(&self, cursor: Option<String>, limit: i64) -> Self {
+        WarehouseParams {
+            cursor,
+            limit: Some(limit),
+            ..self.clone()
+        }
+    }
+}
+
+/// Cut a list fetched with one extra row down to a page,
+/// the extra row only tells us there's a next page
+pub fn paginate<T>(mut rows: Vec<T>, limit: i64, cursor: impl Fn(&T) -> Cursor) -> Page<Vec<T>> {
+    let limit = limit as usize;
+    let next = if rows.len() > limit {
+        rows.truncate(limit);
+        rows.last().map(|row| cursor(row).encode())
+    } else {
+        None
+    };
+
+    Page { items: rows, next }
+}
+
+/// The cursor after a warehouse
+pub fn warehouse_cursor(sort: &Sort<()>, whouse: &Warehouse) -> Cursor {
+    Cursor {
+        sort: sort.name(),
+        key: whouse.id.to_string(),
+        id: whouse.id,
+    }
+}
+
+/// The cursor after an item
+pub fn item_cursor(sort: &Sort<ItemKey>, item: &InventoryItem) -> Cursor {
+    Cursor {
+        sort: sort.name(),
+        key: sort.key.value_of(item),
+        id: item.id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(sort: &str) -> Cursor {
+        Cursor {
+            sort: sort.to_string(),
+            key: "12.5000".to_string(),
+            id: 7,
+        }
+    }
+
+    fn invalid_request(result: Result<Cursor>) -> String {
+        match result {
+            Err(Error::InvalidRequest { detail }) => detail,
+            other => panic!("expected an invalid request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_cursor_decodes_to_what_was_encoded() {
+        let decoded = Cursor::decode(&cursor("-value").encode(), "-value").unwrap();
+        assert_eq!(decoded.sort, "-value");
+        assert_eq!(decoded.key, "12.5000");
+        assert_eq!(decoded.id, 7);
+    }
+
+    #[test]
+    fn an_encoded_cursor_is_safe_in_a_url() {
+        let encoded = cursor("-value").encode();
+        assert!(encoded
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    }
+
+    #[test]
+    fn a_cursor_that_is_not_base64_is_turned_down() {
+        let detail = invalid_request(Cursor::decode("not a cursor!", "id"));
+        assert_eq!(detail, "cursor is not valid");
+    }
+
+    #[test]
+    fn a_cursor_that_is_not_json_is_turned_down() {
+        let encoded = base64::encode_config("{\"sort\": \"id\", ", base64::URL_SAFE_NO_PAD);
+        let detail = invalid_request(Cursor::decode(&encoded, "id"));
+        assert_eq!(detail, "cursor is not valid");
+
+        // Valid JSON that isn't a cursor is no better
+        let encoded = base64::encode_config("{\"sort\": \"id\"}", base64::URL_SAFE_NO_PAD);
+        let detail = invalid_request(Cursor::decode(&encoded, "id"));
+        assert_eq!(detail, "cursor is not valid");
+    }
+
+    #[test]
+    fn a_cursor_made_for_another_sort_is_turned_down() {
+        let detail = invalid_request(Cursor::decode(&cursor("weight").encode(), "-weight"));
+        assert_eq!(detail, "cursor was made for sort=weight, not sort=-weight");
+    }
+}
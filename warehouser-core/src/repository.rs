@@ -0,0 +1,230 @@
+/// Storage backends
+/// The service layer is written against `Repository`, so the same business rules run
+/// on Postgres (`db`), SQLite (`sqlite`), or in memory (`memory`), for tests and demos
+/// without a database
+// The trait is object safe, so the server can pick a backend at runtime
+// and hand `&dyn Repository` to the same handlers either way
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+#[cfg(feature = "sqlite")]
+use diesel::SqliteConnection;
+
+use crate::memory::{MemoryConnection, MemoryStore};
+use crate::models::{
+    ApiKey, ApiKeyRow, Error, IdempotentRequest, InventoryItem, Movement, NewInventoryItem,
+    NewMovement, Result, Stock, StoredResponse, Warehouse,
+};
+use crate::pagination::{Cursor, ItemKey, ItemParams, Sort};
+use crate::DbPool;
+#[cfg(feature = "sqlite")]
+use crate::SqlitePool;
+
+/// Everything the service layer reads and writes
+///
+/// Missing rows are reported as `Error::NotFound`, the service layer says what was missing.
+/// Items and warehouses in the trash count as missing, except to the list methods when
+/// they're asked for, and to the methods for the trash itself
+pub trait Repository {
+    /// Start a transaction, or a savepoint inside of the current one
+    fn begin(&self) -> Result<()>;
+    /// Keep the changes made since the matching `begin`
+    fn commit(&self) -> Result<()>;
+    /// Undo the changes made since the matching `begin`
+    fn rollback(&self) -> Result<()>;
+
+    fn get_items_by_id(&self, limit: i64, ids: &[i32]) -> Result<Vec<InventoryItem>>;
+    /// Items matching `params`, optionally only those in warehouse `w_id`,
+    /// in `sort` order and starting after `after`, with the ones in the trash if `params` asks.
+    /// Returns up to one more item than the limit, so the caller can tell if there's another page
+    fn list_items(
+        &self,
+        params: &ItemParams,
+        w_id: Option<i32>,
+        sort: Sort<ItemKey>,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<InventoryItem>>;
+    fn get_item(&self, id: i32) -> Result<InventoryItem>;
+    /// Insert an item's row, the item is not stocked by any warehouse
+    fn insert_item(&self, item: &NewInventoryItem) -> Result<InventoryItem>;
+    /// Insert an item's row with a client-chosen id, the item is not stocked by any warehouse
+    fn import_item(&self, item: &InventoryItem) -> Result<InventoryItem>;
+    /// Make sure ids picked by clients are never handed out to new rows
+    fn sync_id_sequences(&self) -> Result<()>;
+    /// Update the columns stored on the item's row, its stock is left alone
+    fn update_item(&self, item: &InventoryItem) -> Result<InventoryItem>;
+    /// Move an item that's in no warehouse to the trash
+    fn trash_item(&self, id: i32) -> Result<InventoryItem>;
+    /// Take an item back out of the trash, in no warehouse
+    fn restore_item(&self, id: i32) -> Result<InventoryItem>;
+    /// Items in the trash, most recently deleted first
+    fn get_trashed_items(&self, limit: i64) -> Result<Vec<InventoryItem>>;
+    /// Delete the items moved to the trash before `before` for good, returns how many there were
+    fn purge_items(&self, before: DateTime<Utc>) -> Result<usize>;
+    /// Lock an item's row for the rest of the current transaction
+    fn get_item_for_update(&self, id: i32) -> Result<InventoryItem>;
+    /// Every item in a warehouse
+    fn get_warehouse_items(&self, w_id: i32, limit: i64) -> Result<Vec<InventoryItem>>;
+
+    /// Stock an item that is in no warehouse
+    fn insert_stock(&self, item_id: i32, w_id: i32, qty: i32) -> Result<Stock>;
+    /// Change how many units of an item are on hand
+    fn set_stock_quantity(&self, item_id: i32, qty: i32) -> Result<Stock>;
+    /// Move all of an item's stock to another warehouse
+    fn move_stock(&self, item_id: i32, w_id: i32) -> Result<Stock>;
+    /// Take an item out of its warehouse entirely
+    fn delete_stock(&self, item_id: i32) -> Result<Stock>;
+    /// Remove every item from a warehouse, returns the number of items released
+    fn clear_warehouse_items(&self, w_id: i32) -> Result<usize>;
+
+    fn insert_movement(&self, movement: &NewMovement) -> Result<Movement>;
+    /// Every movement of an item, oldest first
+    fn get_item_movements(&self, item_id: i32, limit: i64) -> Result<Vec<Movement>>;
+    /// The most recent movement of an item
+    fn get_last_item_movement(&self, item_id: i32) -> Result<Movement>;
+    /// Every movement into or out of a warehouse, oldest first
+    fn get_warehouse_movements(&self, w_id: i32, limit: i64) -> Result<Vec<Movement>>;
+    /// Up to `limit` movements with an id above `after`, oldest first
+    fn get_movements_after(&self, after: i64, limit: i64) -> Result<Vec<Movement>>;
+    /// Every stock row, by item
+    fn get_all_stock(&self) -> Result<Vec<Stock>>;
+
+    fn get_warehouses_by_id(&self, limit: i64, ids: &[i32]) -> Result<Vec<Warehouse>>;
+    /// Warehouses in `sort` order, starting after `after`, along with the ones in the trash
+    /// when `trashed` is set.
+    /// Returns up to one more warehouse than the limit, so the caller can tell if there's another page
+    fn list_warehouses(
+        &self,
+        limit: i64,
+        trashed: bool,
+        sort: Sort<()>,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<Warehouse>>;
+    fn get_warehouse(&self, id: i32) -> Result<Warehouse>;
+    /// Lock a warehouse's row for the rest of the current transaction
+    fn get_warehouse_for_update(&self, id: i32) -> Result<Warehouse>;
+    /// Insert an empty warehouse
+    fn insert_warehouse(&self) -> Result<Warehouse>;
+    /// Insert an empty warehouse with a client-chosen id
+    fn import_warehouse(&self, id: i32) -> Result<Warehouse>;
+    /// Move an empty warehouse to the trash
+    fn trash_warehouse(&self, id: i32) -> Result<Warehouse>;
+    /// Take a warehouse back out of the trash, still empty
+    fn restore_warehouse(&self, id: i32) -> Result<Warehouse>;
+    /// Warehouses in the trash, most recently deleted first
+    fn get_trashed_warehouses(&self, limit: i64) -> Result<Vec<Warehouse>>;
+    /// Delete the warehouses moved to the trash before `before` for good,
+    /// returns how many there were
+    fn purge_warehouses(&self, before: DateTime<Utc>) -> Result<usize>;
+
+    fn insert_api_key(&self, key: &ApiKeyRow) -> Result<ApiKey>;
+    /// Every API key, revoked ones included, oldest first
+    fn get_api_keys(&self) -> Result<Vec<ApiKey>>;
+    /// The key with this hash, as long as it hasn't been revoked
+    fn get_active_api_key(&self, hash: &str) -> Result<ApiKey>;
+    /// Revoke a key, revoking a key twice keeps the time it was first revoked
+    fn revoke_api_key(&self, key_id: i32) -> Result<ApiKey>;
+    fn count_api_keys(&self) -> Result<i64>;
+
+    fn get_idempotent_request(&self, key_id: i32, key: &str) -> Result<IdempotentRequest>;
+    /// Record a request that's about to be handled, before it has a response
+    fn insert_idempotent_request(
+        &self,
+        key_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<IdempotentRequest>;
+    fn save_idempotent_response(
+        &self,
+        key_id: i32,
+        key: &str,
+        response: &StoredResponse,
+    ) -> Result<()>;
+    fn delete_idempotent_request(&self, key_id: i32, key: &str) -> Result<()>;
+    /// Forget every request made before `before`, returns how many there were
+    fn delete_idempotent_requests_before(&self, before: DateTime<Utc>) -> Result<usize>;
+
+    /// The version of every migration that's been run on the storage
+    fn get_applied_migrations(&self) -> Result<Vec<String>>;
+    /// The version of every migration this build expects the storage to have
+    fn migrations(&self) -> &'static [&'static str];
+}
+
+/// Run `f` in a transaction, its changes are kept only if it succeeds
+///
+/// Transactions can be nested, an inner one that fails only undoes its own changes
+pub fn transaction<R, T, E>(
+    repo: &R,
+    f: impl FnOnce() -> std::result::Result<T, E>,
+) -> std::result::Result<T, E>
+where
+    R: Repository + ?Sized,
+    E: From<Error>,
+{
+    repo.begin()?;
+    match f() {
+        Ok(value) => {
+            repo.commit()?;
+            Ok(value)
+        }
+        Err(e) => {
+            repo.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+/// Where the server keeps its data, picked when it starts
+#[derive(Clone)]
+pub enum Pool {
+    Postgres(DbPool),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqlitePool),
+    Memory(MemoryStore),
+}
+
+/// A connection from a `Pool`, good for one request
+pub enum Connection<'a> {
+    Postgres(PooledConnection<ConnectionManager<PgConnection>>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(PooledConnection<ConnectionManager<SqliteConnection>>),
+    Memory(MemoryConnection<'a>),
+}
+
+impl Pool {
+    /// A connection, waiting up to `timeout` (or the pool's own timeout) for one to be free
+    pub fn get(&self, timeout: Option<Duration>) -> std::result::Result<Connection<'_>, String> {
+        match self {
+            Pool::Postgres(pool) => {
+                let conn = match timeout {
+                    Some(timeout) => pool.get_timeout(timeout),
+                    None => pool.get(),
+                };
+                conn.map(Connection::Postgres).map_err(|e| e.to_string())
+            }
+            #[cfg(feature = "sqlite")]
+            Pool::Sqlite(pool) => {
+                let conn = match timeout {
+                    Some(timeout) => pool.get_timeout(timeout),
+                    None => pool.get(),
+                };
+                conn.map(Connection::Sqlite).map_err(|e| e.to_string())
+            }
+            // Requests take turns with the store, so there's nothing to wait for but them
+            Pool::Memory(store) => Ok(Connection::Memory(store.connect())),
+        }
+    }
+}
+
+impl Connection<'_> {
+    pub fn repo(&self) -> &dyn Repository {
+        match self {
+            Connection::Postgres(conn) => &**conn,
+            #[cfg(feature = "sqlite")]
+            Connection::Sqlite(conn) => &**conn,
+            Connection::Memory(conn) => conn,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+table! {
+    use diesel::sql_types::*;
+    use crate::models::*;
+
+    api_keys (id) {
+        id -> Int4,
+        name -> Text,
+        role -> PgRole,
+        key_hash -> Text,
+        prefix -> Text,
+        created_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    idempotency_keys (key_id, idempotency_key) {
+        key_id -> Int4,
+        idempotency_key -> Text,
+        fingerprint -> Text,
+        status -> Nullable<Int4>,
+        headers -> Nullable<Text>,
+        body -> Nullable<Bytea>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::models::*;
+
+    inventory (id) {
+        id -> Int4,
+        transport -> PgTransport,
+        weight_g -> Int8,
+        value -> PgMoney,
+        dimensions_mm -> PgDimensions,
+        version -> Int4,
+        deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::models::*;
+
+    movements (id) {
+        id -> Int8,
+        item -> Int4,
+        from_warehouse -> Nullable<Int4>,
+        to_warehouse -> Nullable<Int4>,
+        quantity -> Int4,
+        reason -> PgMovementReason,
+        created_at -> Timestamptz,
+        actor -> Text,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    stock (item) {
+        item -> Int4,
+        warehouse -> Int4,
+        quantity -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    warehouses (id) {
+        id -> Int4,
+        version -> Int4,
+        deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+joinable!(idempotency_keys -> api_keys (key_id));
+joinable!(stock -> inventory (item));
+joinable!(stock -> warehouses (warehouse));
+
+allow_tables_to_appear_in_same_query!(
+    api_keys,
+    idempotency_keys,
+    inventory,
+    movements,
+    stock,
+    warehouses,
+);
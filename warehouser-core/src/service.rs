@@ -0,0 +1,1935 @@
+use crate::batch::{self, Batch, Operation, Output};
+use crate::conditional::IfMatch;
+use crate::keys::{generate_key, hash_key};
+use crate::models::{
+    ApiKey, ApiKeyRow, ConsistencyReport, Error, Holding, IdempotentRequest, ImportReport,
+    Inconsistency, InconsistencyKind, InventoryItem, IssuedKey, LineError, Movement,
+    MovementReason, NewApiKey, NewInventoryItem, NewMovement, NewWarehouse, OperationError,
+    RepairPolicy, Result, Role, StoredResponse, Transfer, Trash,
+};
+use crate::pagination::{
+    item_cursor, paginate, warehouse_cursor, Cursor, ItemParams, Page, Sort, WarehouseParams,
+};
+use crate::util::{ItemImport, ItemRecord, WarehouseImport, WarehouseRecord};
+use crate::versions::ItemUpdate;
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use validator::Validate;
+
+// The api layer should use service functions instead of the repository directly
+// this indirection allows us to modify the internal behaviour of the functions
+// in this layer, without breaking the api layer or any other dependents.
+// Everything here is written against `Repository`, so it runs the same on every backend
+use crate::repository::{transaction, Repository};
+use crate::{models::NotFound, models::Warehouse};
+
+/// Every API key, revoked ones included, oldest first
+pub fn get_api_keys<R: Repository + ?Sized>(repo: &R) -> Result<Vec<ApiKey>> {
+    repo.get_api_keys()
+}
+
+// Reports a missing item as such
+pub fn get_item<R: Repository + ?Sized>(repo: &R, item_id: i32) -> Result<InventoryItem> {
+    repo.get_item(item_id)
+        .not_found(|| Error::ItemNotFound { item_id })
+}
+
+// Reports a missing warehouse as such
+pub fn get_warehouse<R: Repository + ?Sized>(repo: &R, w_id: i32) -> Result<Warehouse> {
+    repo.get_warehouse(w_id)
+        .not_found(|| Error::WarehouseNotFound { warehouse_id: w_id })
+}
+
+// Every function here that touches more than one row
+// runs inside of a single transaction, either all of the changes
+// land or none of them do. Nested calls, like `create_warehouse`
+// calling `warehouse_add_item`, are turned into savepoints
+
+// Anything a client sends us is validated against the rules on its model
+// before we go near the database, see the `#[validate(...)]` attributes in `models`
+
+/// Add `quantity` units of an item to a warehouse
+///
+/// An item is stocked by at most one warehouse,
+/// adding more of an item to the warehouse that has it increases the quantity on hand
+pub fn warehouse_add_item<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    w_id: i32,
+    item_id: i32,
+    quantity: i32,
+) -> Result<Warehouse> {
+    transaction(repo, || {
+        check_quantity(quantity)?;
+
+        repo.get_warehouse(w_id)
+            .not_found(|| Error::WarehouseNotFound { warehouse_id: w_id })?;
+
+        // Lock the item so nobody can assign it elsewhere while we're working
+        let item = repo
+            .get_item_for_update(item_id)
+            .not_found(|| Error::ItemNotFound { item_id })?;
+
+        match item.warehouse {
+            Some(id_) if id_ != w_id => {
+                return Err(Error::AlreadyAssigned {
+                    item_id,
+                    warehouse_id: id_,
+                    requested_warehouse_id: w_id,
+                });
+            }
+            Some(_) => {
+                let total = item
+                    .quantity
+                    .checked_add(quantity)
+                    .ok_or(Error::StockOverflow {
+                        item_id,
+                        warehouse_id: w_id,
+                        requested: quantity,
+                        on_hand: item.quantity,
+                    })?;
+                repo.set_stock_quantity(item_id, total)?;
+            }
+            None => {
+                repo.insert_stock(item_id, w_id, quantity)?;
+            }
+        }
+
+        repo.insert_movement(&NewMovement {
+            item: item_id,
+            from_warehouse: None,
+            to_warehouse: Some(w_id),
+            quantity,
+            reason: MovementReason::Received,
+            actor,
+        })?;
+
+        repo.get_warehouse(w_id)
+    })
+}
+
+/// Remove `quantity` units of an item from a warehouse
+///
+/// Once none are left on hand the item no longer belongs to the warehouse
+pub fn warehouse_remove_item<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    w_id: i32,
+    item_id: i32,
+    quantity: i32,
+) -> Result<Warehouse> {
+    transaction(repo, || {
+        check_quantity(quantity)?;
+
+        repo.get_warehouse(w_id)
+            .not_found(|| Error::WarehouseNotFound { warehouse_id: w_id })?;
+
+        let item = repo
+            .get_item_for_update(item_id)
+            .not_found(|| Error::ItemNotFound { item_id })?;
+
+        if item.warehouse != Some(w_id) {
+            return Err(Error::NotAssigned {
+                item_id,
+                warehouse_id: item.warehouse,
+                requested_warehouse_id: w_id,
+            });
+        }
+
+        if quantity > item.quantity {
+            return Err(Error::InsufficientStock {
+                item_id,
+                warehouse_id: w_id,
+                requested: quantity,
+                on_hand: item.quantity,
+            });
+        }
+
+        if quantity == item.quantity {
+            // None left, the item leaves the warehouse
+            repo.delete_stock(item_id)?;
+        } else {
+            repo.set_stock_quantity(item_id, item.quantity - quantity)?;
+        }
+
+        repo.insert_movement(&NewMovement {
+            item: item_id,
+            from_warehouse: Some(w_id),
+            to_warehouse: None,
+            quantity,
+            reason: MovementReason::Removed,
+            actor,
+        })?;
+
+        repo.get_warehouse(w_id)
+    })
+}
+
+/// Move every unit of `items` from one warehouse to another
+///
+/// All of the items are checked before anything moves,
+/// and either every item moves or none of them do
+pub fn transfer_items<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    from: i32,
+    to: i32,
+    items: &[i32],
+) -> Result<Transfer> {
+    transaction(repo, || {
+        if from == to {
+            return Err(Error::SelfTransfer { warehouse_id: from });
+        }
+
+        repo.get_warehouse(from)
+            .not_found(|| Error::WarehouseNotFound { warehouse_id: from })?;
+        repo.get_warehouse(to)
+            .not_found(|| Error::WarehouseNotFound { warehouse_id: to })?;
+
+        // Lock in a consistent order, so two transfers can't deadlock each other
+        let mut sorted = items.to_vec();
+        sorted.sort_unstable();
+
+        let mut moving = Vec::with_capacity(sorted.len());
+        for (i, &item_id) in sorted.iter().enumerate() {
+            if i > 0 && sorted[i - 1] == item_id {
+                return Err(Error::DuplicateItem { item_id });
+            }
+
+            let item = repo
+                .get_item_for_update(item_id)
+                .not_found(|| Error::ItemNotFound { item_id })?;
+
+            if item.warehouse != Some(from) {
+                return Err(Error::NotAssigned {
+                    item_id,
+                    warehouse_id: item.warehouse,
+                    requested_warehouse_id: from,
+                });
+            }
+
+            moving.push(item);
+        }
+
+        // Everything checks out, make the moves
+        for item in moving {
+            repo.move_stock(item.id, to)?;
+            repo.insert_movement(&NewMovement {
+                item: item.id,
+                from_warehouse: Some(from),
+                to_warehouse: Some(to),
+                quantity: item.quantity,
+                reason: MovementReason::Transferred,
+                actor,
+            })?;
+        }
+
+        Ok(Transfer {
+            from: repo.get_warehouse(from)?,
+            to: repo.get_warehouse(to)?,
+        })
+    })
+}
+
+fn check_quantity(quantity: i32) -> Result<()> {
+    if quantity <= 0 {
+        return Err(Error::InvalidQuantity { quantity });
+    }
+    Ok(())
+}
+
+// `Repository::trash_item` only marks the row, this also empties its stock and records the movement
+/// Delete an item, moving it to the trash
+pub fn delete_item<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    item_id: i32,
+    if_match: &IfMatch,
+) -> Result<InventoryItem> {
+    transaction(repo, || {
+        let current = repo
+            .get_item_for_update(item_id)
+            .not_found(|| Error::ItemNotFound { item_id })?;
+        if_match.check(current.version)?;
+
+        // Nothing in the trash is in a warehouse
+        if current.warehouse.is_some() {
+            repo.delete_stock(item_id)?;
+        }
+        let trashed = repo.trash_item(item_id)?;
+
+        // Recorded even when the item was in no warehouse,
+        // so its history shows where it ended, and restoring it knows where to put it back
+        repo.insert_movement(&NewMovement {
+            item: item_id,
+            from_warehouse: current.warehouse,
+            to_warehouse: None,
+            quantity: current.quantity,
+            reason: MovementReason::ItemDeleted,
+            actor,
+        })?;
+
+        // We return the deleted item as it was, so the caller can see where it was
+        Ok(InventoryItem {
+            warehouse: current.warehouse,
+            quantity: current.quantity,
+            ..trashed
+        })
+    })
+}
+
+/// Take an item out of the trash
+///
+/// It goes back to the warehouse it was deleted from, with the units it had,
+/// unless that warehouse is gone too, then it's restored in no warehouse
+pub fn restore_item<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    item_id: i32,
+) -> Result<InventoryItem> {
+    transaction(repo, || {
+        let item = repo.restore_item(item_id).map_err(|e| match e {
+            Error::NotFound if repo.get_item(item_id).is_ok() => Error::ItemNotDeleted { item_id },
+            Error::NotFound => Error::ItemNotFound { item_id },
+            e => e,
+        })?;
+
+        // Where it was when it was deleted, its last movement
+        let last = repo.get_last_item_movement(item_id).ok();
+        let stock = last
+            .filter(|m| m.reason == MovementReason::ItemDeleted)
+            .and_then(|m| Some((m.from_warehouse?, m.quantity)))
+            .filter(|(w_id, quantity)| {
+                *quantity > 0 && repo.get_warehouse_for_update(*w_id).is_ok()
+            });
+
+        if let Some((w_id, quantity)) = stock {
+            repo.insert_stock(item_id, w_id, quantity)?;
+        }
+        repo.insert_movement(&NewMovement {
+            item: item_id,
+            from_warehouse: None,
+            to_warehouse: stock.map(|(w_id, _)| w_id),
+            quantity: stock.map_or(0, |(_, quantity)| quantity),
+            reason: MovementReason::Restored,
+            actor,
+        })?;
+
+        match stock {
+            Some(_) => repo.get_item(item.id),
+            None => Ok(item),
+        }
+    })
+}
+
+pub fn create_item<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    item: &NewInventoryItem,
+) -> Result<InventoryItem> {
+    item.validate()?;
+
+    transaction(repo, || {
+        let stock = initial_stock(repo, item.warehouse, item.quantity)?;
+        let created = repo.insert_item(item)?;
+        stock_new_item(repo, actor, created, stock)
+    })
+}
+
+/// Create an item, keeping the id provided by the client
+pub fn import_item<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    item: &InventoryItem,
+) -> Result<InventoryItem> {
+    import_item_row(repo, actor, item, true)
+}
+
+// Moving the id sequences past the item's id can't be undone,
+// so a dry run leaves `sync_ids` unset
+fn import_item_row<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    item: &InventoryItem,
+    sync_ids: bool,
+) -> Result<InventoryItem> {
+    item.validate()?;
+
+    transaction(repo, || {
+        // A quantity of 0 is what older clients send, by leaving the field out
+        let quantity = Some(item.quantity).filter(|&q| q != 0);
+
+        let stock = initial_stock(repo, item.warehouse, quantity)?;
+        let created = repo.import_item(item)?;
+        if sync_ids {
+            repo.sync_id_sequences()?;
+        }
+        stock_new_item(repo, actor, created, stock)
+    })
+}
+
+/// Import the rows of a csv file, all of them or none of them
+pub fn import_items_csv<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    rows: Vec<(u64, Result<ItemRecord>)>,
+    dry_run: bool,
+) -> Result<ImportReport> {
+    import_rows(repo, rows, dry_run, |record| {
+        match record.into_import()? {
+            ItemImport::Import(item) => {
+                import_item_row(repo, actor, &item, !dry_run).map(|item| item.id)
+            }
+            ItemImport::Create(item) => create_item(repo, actor, &item).map(|item| item.id),
+        }
+    })
+}
+
+// Why an import's transaction was rolled back
+enum Abort {
+    Failed(Error),
+    DryRun(ImportReport),
+}
+
+impl From<Error> for Abort {
+    fn from(e: Error) -> Self {
+        Abort::Failed(e)
+    }
+}
+
+// Every row is tried, each in its own savepoint, so a bad row
+// doesn't hide the problems with the rows after it.
+// If any row fails, or for a dry run, the whole import is rolled back
+fn import_rows<T, R: Repository + ?Sized>(
+    repo: &R,
+    rows: Vec<(u64, Result<T>)>,
+    dry_run: bool,
+    import: impl Fn(T) -> Result<i32>,
+) -> Result<ImportReport> {
+    let outcome = transaction(repo, || {
+        let mut ids = Vec::with_capacity(rows.len());
+        let mut errors = Vec::new();
+
+        for (line, row) in rows {
+            match row.and_then(|row| transaction(repo, || import(row))) {
+                Ok(id) => ids.push(id),
+                Err(e) => errors.push(LineError::new(line, e)),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Abort::Failed(Error::ImportFailed { errors }));
+        }
+
+        let report = ImportReport {
+            dry_run,
+            imported: ids.len(),
+            ids,
+        };
+
+        if dry_run {
+            Err(Abort::DryRun(report))
+        } else {
+            Ok(report)
+        }
+    });
+
+    match outcome {
+        Ok(report) => Ok(report),
+        // The ids of a dry run were never really handed out
+        Err(Abort::DryRun(report)) => Ok(ImportReport {
+            ids: Vec::new(),
+            ..report
+        }),
+        Err(Abort::Failed(e)) => Err(e),
+    }
+}
+
+// Work out the warehouse and quantity a new item starts out with
+// A new item may only name a warehouse that exists,
+// and only has units on hand when it's in a warehouse
+fn initial_stock<R: Repository + ?Sized>(
+    repo: &R,
+    w_id: Option<i32>,
+    quantity: Option<i32>,
+) -> Result<Option<(i32, i32)>> {
+    match (w_id, quantity) {
+        (Some(w_id), quantity) => {
+            // Check for warehouse existence
+            repo.get_warehouse(w_id)
+                .not_found(|| Error::WarehouseNotFound { warehouse_id: w_id })?;
+
+            let quantity = quantity.unwrap_or(1);
+            check_quantity(quantity)?;
+
+            Ok(Some((w_id, quantity)))
+        }
+        (None, None) | (None, Some(0)) => Ok(None),
+        (None, Some(quantity)) => Err(Error::QuantityWithoutWarehouse { quantity }),
+    }
+}
+
+fn stock_new_item<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    item: InventoryItem,
+    stock: Option<(i32, i32)>,
+) -> Result<InventoryItem> {
+    match stock {
+        Some((w_id, quantity)) => {
+            repo.insert_stock(item.id, w_id, quantity)?;
+            repo.insert_movement(&NewMovement {
+                item: item.id,
+                from_warehouse: None,
+                to_warehouse: Some(w_id),
+                quantity,
+                reason: MovementReason::Received,
+                actor,
+            })?;
+            repo.get_item(item.id)
+        }
+        None => Ok(item),
+    }
+}
+
+pub fn create_warehouse<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    whouse: &NewWarehouse,
+) -> Result<Warehouse> {
+    whouse.validate()?;
+
+    transaction(repo, || {
+        let created = repo.insert_warehouse()?;
+        add_initial_items(repo, actor, created.id, &whouse.items)
+    })
+}
+
+/// Import the rows of a csv file, all of them or none of them
+pub fn import_warehouses_csv<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    rows: Vec<(u64, Result<WarehouseRecord>)>,
+    dry_run: bool,
+) -> Result<ImportReport> {
+    import_rows(repo, rows, dry_run, |record| {
+        match record.into_import()? {
+            WarehouseImport::Import(whouse) => {
+                import_warehouse_row(repo, actor, &whouse, !dry_run).map(|whouse| whouse.id)
+            }
+            WarehouseImport::Create(whouse) => {
+                create_warehouse(repo, actor, &whouse).map(|whouse| whouse.id)
+            }
+        }
+    })
+}
+
+/// Create a warehouse, keeping the id provided by the client
+pub fn import_warehouse<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    whouse: &Warehouse,
+) -> Result<Warehouse> {
+    import_warehouse_row(repo, actor, whouse, true)
+}
+
+// Like `import_item_row`, a dry run leaves `sync_ids` unset
+fn import_warehouse_row<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    whouse: &Warehouse,
+    sync_ids: bool,
+) -> Result<Warehouse> {
+    whouse.validate()?;
+
+    transaction(repo, || {
+        if repo.get_warehouse(whouse.id).is_ok() {
+            return Err(Error::WarehouseExists {
+                warehouse_id: whouse.id,
+            });
+        }
+
+        repo.import_warehouse(whouse.id)?;
+        if sync_ids {
+            repo.sync_id_sequences()?;
+        }
+        add_initial_items(repo, actor, whouse.id, &whouse.items)
+    })
+}
+
+// Fill a freshly created warehouse
+fn add_initial_items<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    w_id: i32,
+    items: &[i32],
+) -> Result<Warehouse> {
+    for &item_id in items {
+        // Give a more helpful message than `warehouse_add_item` would
+        let item = repo
+            .get_item_for_update(item_id)
+            .not_found(|| Error::ItemNotFound { item_id })?;
+
+        if let Some(id_) = item.warehouse {
+            return Err(Error::AlreadyAssigned {
+                item_id,
+                warehouse_id: id_,
+                requested_warehouse_id: w_id,
+            });
+        }
+
+        warehouse_add_item(repo, actor, w_id, item_id, 1)?;
+    }
+
+    repo.get_warehouse(w_id)
+}
+
+pub fn delete_warehouse<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    w_id: i32,
+    if_match: &IfMatch,
+) -> Result<Warehouse> {
+    transaction(repo, || {
+        // Locked, so no item can arrive after the version is checked
+        let whouse = repo
+            .get_warehouse_for_update(w_id)
+            .not_found(|| Error::WarehouseNotFound { warehouse_id: w_id })?;
+        if_match.check(whouse.version)?;
+
+        for item in repo.get_warehouse_items(w_id, i64::MAX)? {
+            repo.insert_movement(&NewMovement {
+                item: item.id,
+                from_warehouse: Some(w_id),
+                to_warehouse: None,
+                quantity: item.quantity,
+                reason: MovementReason::WarehouseDeleted,
+                actor,
+            })?;
+        }
+
+        // Nothing in the trash holds any items
+        repo.clear_warehouse_items(w_id)?;
+        let trashed = repo.trash_warehouse(w_id)?;
+
+        // If we did `Ok(trashed)` it wouldn't show the items
+        Ok(Warehouse {
+            items: whouse.items,
+            ..trashed
+        })
+    })
+}
+
+/// Take a warehouse out of the trash
+///
+/// The items it held when it was deleted come back with it,
+/// except the ones that have been deleted, or stocked somewhere else, since
+pub fn restore_warehouse<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    w_id: i32,
+) -> Result<Warehouse> {
+    transaction(repo, || {
+        repo.restore_warehouse(w_id).map_err(|e| match e {
+            Error::NotFound if repo.get_warehouse(w_id).is_ok() => {
+                Error::WarehouseNotDeleted { warehouse_id: w_id }
+            }
+            Error::NotFound => Error::WarehouseNotFound { warehouse_id: w_id },
+            e => e,
+        })?;
+
+        let released = repo
+            .get_warehouse_movements(w_id, i64::MAX)?
+            .into_iter()
+            .filter(|m| m.reason == MovementReason::WarehouseDeleted && m.quantity > 0);
+
+        for released in released {
+            // Only an item that hasn't moved since the warehouse let go of it
+            let untouched = repo
+                .get_last_item_movement(released.item)
+                .is_ok_and(|last| last.id == released.id);
+            let unassigned = repo
+                .get_item_for_update(released.item)
+                .is_ok_and(|item| item.warehouse.is_none());
+            if !(untouched && unassigned) {
+                continue;
+            }
+
+            repo.insert_stock(released.item, w_id, released.quantity)?;
+            repo.insert_movement(&NewMovement {
+                item: released.item,
+                from_warehouse: None,
+                to_warehouse: Some(w_id),
+                quantity: released.quantity,
+                reason: MovementReason::Restored,
+                actor,
+            })?;
+        }
+
+        repo.get_warehouse(w_id)
+    })
+}
+
+/// What's in the trash, up to `limit` items and `limit` warehouses
+pub fn get_trash<R: Repository + ?Sized>(repo: &R, limit: i64) -> Result<Trash> {
+    Ok(Trash {
+        items: repo.get_trashed_items(limit)?,
+        warehouses: repo.get_trashed_warehouses(limit)?,
+    })
+}
+
+/// Delete what's been in the trash for longer than `retention` for good,
+/// returns how many items and warehouses went
+pub fn purge_trash<R: Repository + ?Sized>(
+    repo: &R,
+    retention: std::time::Duration,
+) -> Result<(usize, usize)> {
+    let retention = Duration::from_std(retention).map_err(|e| Error::Internal {
+        detail: format!("Couldn't count back the trash's retention: {e}"),
+    })?;
+    let before = Utc::now() - retention;
+    transaction(repo, || {
+        Ok((repo.purge_items(before)?, repo.purge_warehouses(before)?))
+    })
+}
+
+// How many movements are replayed at a time when checking consistency
+const LEDGER_PAGE: i64 = 1000;
+
+/// Check every item's stock against its movements
+///
+/// Nothing in the schema ties the stock to the ledger, so edits made straight to the
+/// database, or a restore of only some tables, can leave them telling different stories
+pub fn check_consistency<R: Repository + ?Sized>(repo: &R) -> Result<ConsistencyReport> {
+    transaction(repo, || {
+        Ok(ConsistencyReport {
+            inconsistencies: find_inconsistencies(repo)?,
+            repaired: None,
+        })
+    })
+}
+
+/// Check every item's stock against its movements, and fix what disagrees under `policy`
+pub fn repair_consistency<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    policy: RepairPolicy,
+) -> Result<ConsistencyReport> {
+    transaction(repo, || {
+        let inconsistencies = find_inconsistencies(repo)?;
+        for inconsistency in &inconsistencies {
+            repair_inconsistency(repo, actor, policy, inconsistency)?;
+        }
+
+        Ok(ConsistencyReport {
+            inconsistencies,
+            repaired: Some(policy),
+        })
+    })
+}
+
+// Every item the stock and the ledger disagree about, by id
+fn find_inconsistencies<R: Repository + ?Sized>(repo: &R) -> Result<Vec<Inconsistency>> {
+    // Where the ledger puts each item, replayed a page at a time
+    let mut ledger: BTreeMap<i32, Replayed> = BTreeMap::new();
+    let mut after = 0;
+    loop {
+        let page = repo.get_movements_after(after, LEDGER_PAGE)?;
+        let Some(last) = page.last() else { break };
+        after = last.id;
+        for movement in &page {
+            let held = ledger.entry(movement.item).or_default();
+            *held = replay(*held, movement);
+        }
+    }
+
+    let stock: BTreeMap<i32, Holding> = repo
+        .get_all_stock()?
+        .into_iter()
+        .map(|row| {
+            let held = Holding {
+                warehouse_id: row.warehouse,
+                quantity: row.quantity,
+            };
+            (row.item, held)
+        })
+        .collect();
+    let trashed_items: HashSet<i32> = repo
+        .get_trashed_items(i64::MAX)?
+        .into_iter()
+        .map(|item| item.id)
+        .collect();
+    let trashed_warehouses: HashSet<i32> = repo
+        .get_trashed_warehouses(i64::MAX)?
+        .into_iter()
+        .map(|whouse| whouse.id)
+        .collect();
+
+    // An item the ledger puts somewhere that nothing holds may have been purged since
+    let unstocked: Vec<i32> = ledger
+        .iter()
+        .filter(|(item_id, replayed)| {
+            **replayed != Replayed::Held(None) && !stock.contains_key(item_id)
+        })
+        .map(|(&item_id, _)| item_id)
+        .collect();
+    let live: HashSet<i32> = repo
+        .get_items_by_id(i64::MAX, &unstocked)?
+        .into_iter()
+        .map(|item| item.id)
+        .collect();
+
+    let item_ids: BTreeSet<i32> = ledger.keys().chain(stock.keys()).copied().collect();
+    let mut inconsistencies = Vec::new();
+    for item_id in item_ids {
+        let held = stock.get(&item_id).copied();
+        let replayed = ledger.get(&item_id).copied();
+        let recorded = match replayed {
+            Some(Replayed::Held(recorded)) => recorded,
+            Some(Replayed::Unbalanced) | None => None,
+        };
+        let kind = match (held, recorded) {
+            (Some(_), _) if trashed_items.contains(&item_id) => InconsistencyKind::TrashedItem,
+            (Some(held), _) if trashed_warehouses.contains(&held.warehouse_id) => {
+                InconsistencyKind::TrashedWarehouse
+            }
+            _ if replayed == Some(Replayed::Unbalanced) => {
+                if held.is_none() && !live.contains(&item_id) {
+                    continue;
+                }
+                InconsistencyKind::Unbalanced
+            }
+            (Some(held), Some(recorded)) if held == recorded => continue,
+            (Some(held), Some(recorded)) if held.warehouse_id == recorded.warehouse_id => {
+                InconsistencyKind::WrongQuantity
+            }
+            (Some(_), Some(_)) => InconsistencyKind::WrongWarehouse,
+            (Some(_), None) if ledger.contains_key(&item_id) => InconsistencyKind::Unrecorded,
+            (Some(_), None) => InconsistencyKind::NoHistory,
+            (None, Some(_)) if live.contains(&item_id) => InconsistencyKind::Unstocked,
+            (None, _) => continue,
+        };
+        inconsistencies.push(Inconsistency {
+            item_id,
+            kind,
+            stock: held,
+            ledger: recorded,
+        });
+    }
+
+    Ok(inconsistencies)
+}
+
+// Where an item's movements put it, so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Replayed {
+    Held(Option<Holding>),
+    // They took out more than was there, or put in more than a quantity holds,
+    // the ledger is corrupt and can't say where the item is
+    Unbalanced,
+}
+
+impl Default for Replayed {
+    fn default() -> Self {
+        Replayed::Held(None)
+    }
+}
+
+// Where a movement leaves an item that was at `replayed` before it
+fn replay(replayed: Replayed, movement: &Movement) -> Replayed {
+    let holding = |warehouse_id, quantity: Option<i32>| match quantity {
+        Some(quantity) if quantity >= 0 => Replayed::Held(
+            Some(Holding {
+                warehouse_id,
+                quantity,
+            })
+            .filter(|held| held.quantity > 0),
+        ),
+        _ => Replayed::Unbalanced,
+    };
+
+    match movement.reason {
+        MovementReason::Received => match (replayed, movement.to_warehouse) {
+            (Replayed::Held(Some(held)), Some(w_id)) if held.warehouse_id == w_id => {
+                holding(w_id, held.quantity.checked_add(movement.quantity))
+            }
+            // There's no knowing what it was added to
+            (Replayed::Unbalanced, _) => Replayed::Unbalanced,
+            (_, Some(w_id)) => holding(w_id, Some(movement.quantity)),
+            (replayed, None) => replayed,
+        },
+        MovementReason::Removed => match replayed {
+            Replayed::Held(Some(held)) if Some(held.warehouse_id) == movement.from_warehouse => {
+                holding(
+                    held.warehouse_id,
+                    held.quantity.checked_sub(movement.quantity),
+                )
+            }
+            replayed => replayed,
+        },
+        // These put the item somewhere whatever came before, so it adds up again after them
+        MovementReason::Transferred | MovementReason::Restored => match movement.to_warehouse {
+            Some(w_id) => holding(w_id, Some(movement.quantity)),
+            None => Replayed::Held(None),
+        },
+        MovementReason::ItemDeleted | MovementReason::WarehouseDeleted => Replayed::Held(None),
+    }
+}
+
+// Bring the stock and the ledger to where `policy` says the item should be
+fn repair_inconsistency<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    policy: RepairPolicy,
+    inconsistency: &Inconsistency,
+) -> Result<()> {
+    let Inconsistency {
+        item_id,
+        kind,
+        stock,
+        ledger,
+    } = *inconsistency;
+
+    let believed = match (kind, policy) {
+        // Nothing in the trash holds or is held by anything, whichever side is believed
+        (InconsistencyKind::TrashedItem | InconsistencyKind::TrashedWarehouse, _) => None,
+        // With no movements there's nothing to believe over the stock, it's only recorded
+        (InconsistencyKind::NoHistory, _) => stock,
+        // Nothing says where the ledger has it, so neither side can be brought to the other
+        (InconsistencyKind::Unbalanced, _) => return Ok(()),
+        (_, RepairPolicy::Ledger) => ledger,
+        (_, RepairPolicy::Stock) => stock,
+    };
+    // The ledger can name a warehouse that's been deleted since, it can't be restocked
+    let target = believed.filter(|held| repo.get_warehouse(held.warehouse_id).is_ok());
+
+    match (stock, target) {
+        (Some(_), None) => {
+            repo.delete_stock(item_id)?;
+        }
+        (None, Some(target)) => {
+            repo.insert_stock(item_id, target.warehouse_id, target.quantity)?;
+        }
+        (Some(held), Some(target)) => {
+            if held.warehouse_id != target.warehouse_id {
+                repo.move_stock(item_id, target.warehouse_id)?;
+            }
+            if held.quantity != target.quantity {
+                repo.set_stock_quantity(item_id, target.quantity)?;
+            }
+        }
+        (None, None) => {}
+    }
+
+    // The ledger is never rewritten, it's corrected by taking out
+    // what it says is there and putting in what really is
+    if ledger != target {
+        if let Some(recorded) = ledger {
+            repo.insert_movement(&NewMovement {
+                item: item_id,
+                from_warehouse: Some(recorded.warehouse_id),
+                to_warehouse: None,
+                quantity: recorded.quantity,
+                reason: MovementReason::Removed,
+                actor,
+            })?;
+        }
+        if let Some(target) = target {
+            repo.insert_movement(&NewMovement {
+                item: item_id,
+                from_warehouse: None,
+                to_warehouse: Some(target.warehouse_id),
+                quantity: target.quantity,
+                reason: MovementReason::Received,
+                actor,
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn update_warehouse<R: Repository + ?Sized>(_: &R, _: &Warehouse) -> Result<Warehouse> {
+    // Technically a warehouse can be updated in the db, db::update_warehouse does exist
+    // but it doesn't make sense to update the id, and we're not supporting updating
+    // the list of items, for that use the add and remove item endpoints
+
+    let err = Error::Unsupported {
+        detail: "Updating warehouses is not supported, to add and remove items use the respective endpoints".to_string()
+    };
+    Err(err)
+}
+
+pub fn update_item<R: Repository + ?Sized>(
+    repo: &R,
+    update: &ItemUpdate,
+    if_match: &IfMatch,
+) -> Result<InventoryItem> {
+    transaction(repo, || {
+        let item_id = update.id();
+        let db_item = repo
+            .get_item_for_update(item_id)
+            .not_found(|| Error::ItemNotFound { item_id })?;
+        if_match.check(db_item.version)?;
+
+        // A version 1 item is merged with the stored one, so it can't be checked before
+        let item = &update.apply(&db_item);
+        item.validate()?;
+
+        // We want to enforce that you can't update an item's warehouse via this endpoint
+        if item.warehouse != db_item.warehouse {
+            return Err(Error::ImmutableField {
+                item_id: item.id,
+                field: "warehouse".to_string(),
+            });
+        }
+
+        // The quantity on hand is managed by the same endpoints
+        // `Repository::update_item` leaves it alone, so it's ignored here
+        repo.update_item(item)
+    })
+}
+
+/// Change only some of an item's fields,
+/// `patch` is given the item as it's stored and returns it as it should be
+///
+/// Like `update_item`, an item's warehouse can't be changed this way, and neither can its stock
+pub fn patch_item<R: Repository + ?Sized>(
+    repo: &R,
+    id: i32,
+    if_match: &IfMatch,
+    patch: impl FnOnce(InventoryItem) -> Result<InventoryItem>,
+) -> Result<InventoryItem> {
+    transaction(repo, || {
+        let db_item = repo
+            .get_item_for_update(id)
+            .not_found(|| Error::ItemNotFound { item_id: id })?;
+        if_match.check(db_item.version)?;
+        let item = patch(db_item.clone())?;
+
+        // The item is the one in the path, a patch can't turn it into another
+        if item.id != id {
+            return Err(Error::InvalidRequest {
+                detail: format!("The id of item {id} can't be changed"),
+            });
+        }
+        let changed = [
+            ("warehouse", item.warehouse != db_item.warehouse),
+            ("quantity", item.quantity != db_item.quantity),
+        ];
+        if let Some((field, _)) = changed.iter().find(|(_, changed)| *changed) {
+            return Err(Error::ImmutableField {
+                item_id: id,
+                field: field.to_string(),
+            });
+        }
+
+        item.validate()?;
+        repo.update_item(&item)
+    })
+}
+
+/// Run the operations of a batch in order, in a single transaction
+///
+/// The first one to fail undoes the whole batch. With `continue_on_error` the rest are run
+/// anyway, each in its own savepoint, and only the ones that failed are undone
+pub fn run_batch<R: Repository + ?Sized>(
+    repo: &R,
+    actor: &str,
+    batch: &Batch,
+    continue_on_error: bool,
+) -> Result<Vec<Result<Output>>> {
+    transaction(repo, || {
+        let mut results = Vec::with_capacity(batch.0.len());
+        for (i, op) in batch.0.iter().enumerate() {
+            match transaction(repo, || run_operation(repo, actor, op)) {
+                Err(e) if !continue_on_error => {
+                    return Err(Error::BatchFailed {
+                        errors: vec![OperationError::new(i, e)],
+                    })
+                }
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    })
+}
+
+// Each operation is run by the function behind the endpoint it stands in for
+fn run_operation<R: Repository + ?Sized>(repo: &R, actor: &str, op: &Operation) -> Result<Output> {
+    match op {
+        Operation::CreateItem { item } => create_item(repo, actor, item).map(Output::Created),
+        Operation::UpdateItem { item, version } => {
+            update_item(repo, item, &batch::if_match(*version)).map(Output::Item)
+        }
+        Operation::DeleteItem { id, version } => {
+            delete_item(repo, actor, *id, &batch::if_match(*version)).map(Output::Item)
+        }
+        Operation::CreateWarehouse { warehouse } => {
+            create_warehouse(repo, actor, warehouse).map(Output::CreatedWarehouse)
+        }
+        Operation::DeleteWarehouse { id, version } => {
+            delete_warehouse(repo, actor, *id, &batch::if_match(*version)).map(Output::Warehouse)
+        }
+        Operation::AddItem {
+            warehouse,
+            item,
+            quantity,
+        } => warehouse_add_item(repo, actor, *warehouse, *item, quantity.unwrap_or(1))
+            .map(Output::Warehouse),
+        Operation::RemoveItem {
+            warehouse,
+            item,
+            quantity,
+        } => warehouse_remove_item(repo, actor, *warehouse, *item, quantity.unwrap_or(1))
+            .map(Output::Warehouse),
+        Operation::Transfer { from, to, items } => {
+            transfer_items(repo, actor, *from, *to, items).map(Output::Transfer)
+        }
+    }
+}
+
+/// A page of the items matching `params`
+pub fn list_items<R: Repository + ?Sized>(
+    repo: &R,
+    params: &ItemParams,
+) -> Result<Page<Vec<InventoryItem>>> {
+    item_page(repo, params, None)
+}
+
+/// A page of the items in a warehouse matching `params`
+pub fn warehouse_get_items<R: Repository + ?Sized>(
+    repo: &R,
+    w_id: i32,
+    params: &ItemParams,
+) -> Result<Page<Vec<InventoryItem>>> {
+    repo.get_warehouse(w_id)
+        .not_found(|| Error::WarehouseNotFound { warehouse_id: w_id })?;
+
+    item_page(repo, params, Some(w_id))
+}
+
+fn item_page<R: Repository + ?Sized>(
+    repo: &R,
+    params: &ItemParams,
+    w_id: Option<i32>,
+) -> Result<Page<Vec<InventoryItem>>> {
+    params.validate()?;
+    let sort = Sort::parse_item(params.sort.as_deref())?;
+    let after = params
+        .cursor
+        .as_deref()
+        .map(|cursor| Cursor::decode(cursor, &sort.name()))
+        .transpose()?;
+
+    let items = repo.list_items(params, w_id, sort, after.as_ref())?;
+    Ok(paginate(items, params.limit(), |item| {
+        item_cursor(&sort, item)
+    }))
+}
+
+/// A page of warehouses
+pub fn list_warehouses<R: Repository + ?Sized>(
+    repo: &R,
+    params: &WarehouseParams,
+) -> Result<Page<Vec<Warehouse>>> {
+    params.validate()?;
+    let sort = Sort::parse_warehouse(params.sort.as_deref())?;
+    let after = params
+        .cursor
+        .as_deref()
+        .map(|cursor| Cursor::decode(cursor, &sort.name()))
+        .transpose()?;
+
+    let whouses = repo.list_warehouses(
+        params.limit(),
+        params.include_deleted(),
+        sort,
+        after.as_ref(),
+    )?;
+    Ok(paginate(whouses, params.limit(), |whouse| {
+        warehouse_cursor(&sort, whouse)
+    }))
+}
+
+pub fn item_history<R: Repository + ?Sized>(
+    repo: &R,
+    item_id: i32,
+    limit: i64,
+) -> Result<Vec<Movement>> {
+    // Deliberately no existence check, the history of a deleted item is still available
+    repo.get_item_movements(item_id, limit)
+}
+
+pub fn warehouse_movements<R: Repository + ?Sized>(
+    repo: &R,
+    w_id: i32,
+    limit: i64,
+) -> Result<Vec<Movement>> {
+    repo.get_warehouse_movements(w_id, limit)
+}
+
+/// Issue a new API key, this is the only time the key itself is known
+pub fn issue_api_key<R: Repository + ?Sized>(repo: &R, new_key: &NewApiKey) -> Result<IssuedKey> {
+    new_key.validate()?;
+
+    let (key, prefix) = generate_key();
+    let row = ApiKeyRow {
+        name: &new_key.name,
+        role: new_key.role,
+        key_hash: &hash_key(&key),
+        prefix: &prefix,
+    };
+    let api_key = repo.insert_api_key(&row)?;
+
+    Ok(IssuedKey { api_key, key })
+}
+
+// Reports a missing key as such
+pub fn revoke_api_key<R: Repository + ?Sized>(repo: &R, key_id: i32) -> Result<ApiKey> {
+    repo.revoke_api_key(key_id)
+        .not_found(|| Error::ApiKeyNotFound { key_id })
+}
+
+/// The API key a request was made with, unknown and revoked keys are turned away
+pub fn authenticate<R: Repository + ?Sized>(repo: &R, key: &str) -> Result<ApiKey> {
+    repo.get_active_api_key(&hash_key(key))
+        .not_found(|| Error::Unauthenticated)
+}
+
+/// Issue an admin key when there are no keys at all,
+/// without one nobody could call the endpoint that issues keys
+pub fn bootstrap_api_key<R: Repository + ?Sized>(repo: &R) -> Result<Option<IssuedKey>> {
+    transaction(repo, || {
+        if repo.count_api_keys()? > 0 {
+            return Ok(None);
+        }
+
+        let admin = NewApiKey {
+            name: "bootstrap".to_string(),
+            role: Role::Admin,
+        };
+        issue_api_key(repo, &admin).map(Some)
+    })
+}
+
+/// Longest `Idempotency-Key` we keep
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// The headers that change what a request does, or what it gets back
+pub const FINGERPRINT_HEADERS: [&str; 3] = ["content-type", "accept", "if-match"];
+
+/// What a request with an `Idempotency-Key` has to match to be taken as a retry,
+/// its method, path and query, the values of `FINGERPRINT_HEADERS` in order, and body
+pub fn request_fingerprint(method: &str, uri: &str, headers: &[&str], body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    let headers = headers.iter().map(|value| value.as_bytes());
+    for part in [method.as_bytes(), uri.as_bytes()]
+        .into_iter()
+        .chain(headers)
+        .chain([body])
+    {
+        // Each part's length first, so no two requests run together the same way
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Claim an `Idempotency-Key` for a change about to be made,
+/// or get the response to the change when it was already made with this key.
+/// Keys are only unique per API key, and forgotten after `window`. A claim still without
+/// a response after `abandoned` is taken to be abandoned, by a server that stopped
+/// mid-request, and the change is made again
+pub fn begin_idempotent<R: Repository + ?Sized>(
+    repo: &R,
+    key_id: i32,
+    key: &str,
+    fingerprint: &str,
+    window: std::time::Duration,
+    abandoned: std::time::Duration,
+) -> Result<Option<StoredResponse>> {
+    let visible = key.bytes().all(|byte| byte.is_ascii_graphic());
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN || !visible {
+        return Err(Error::InvalidRequest {
+            detail: format!(
+                "`Idempotency-Key` must be 1 to {MAX_IDEMPOTENCY_KEY_LEN} visible characters"
+            ),
+        });
+    }
+
+    transaction(repo, || {
+        let now = Utc::now();
+        // A window too long to count back from keeps every key
+        let expired = Duration::from_std(window)
+            .ok()
+            .and_then(|window| now.checked_sub_signed(window));
+        if let Some(expired) = expired {
+            repo.delete_idempotent_requests_before(expired)?;
+        }
+        // Likewise a wait too long to count back from never gives up on a claim
+        let abandoned = Duration::from_std(abandoned)
+            .ok()
+            .and_then(|abandoned| now.checked_sub_signed(abandoned));
+
+        match repo.get_idempotent_request(key_id, key) {
+            Ok(found) if found.fingerprint != fingerprint => {
+                return Err(Error::IdempotencyKeyReused)
+            }
+            Ok(IdempotentRequest {
+                response: Some(response),
+                ..
+            }) => return Ok(Some(response)),
+            Ok(found) if abandoned.is_none_or(|abandoned| found.created_at > abandoned) => {
+                return Err(Error::IdempotencyKeyInUse)
+            }
+            Ok(_) => repo.delete_idempotent_request(key_id, key)?,
+            Err(Error::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+
+        // Another request with the same key got to it first
+        repo.insert_idempotent_request(key_id, key, fingerprint)
+            .map_err(|e| match e {
+                Error::UniqueViolation { .. } => Error::IdempotencyKeyInUse,
+                e => e,
+            })?;
+        Ok(None)
+    })
+}
+
+/// Keep the response to a change made with an `Idempotency-Key`, for its retries
+pub fn save_idempotent_response<R: Repository + ?Sized>(
+    repo: &R,
+    key_id: i32,
+    key: &str,
+    response: &StoredResponse,
+) -> Result<()> {
+    repo.save_idempotent_response(key_id, key, response)
+}
+
+/// Give up an `Idempotency-Key` without a response, so a retry makes the change again
+pub fn forget_idempotent_request<R: Repository + ?Sized>(
+    repo: &R,
+    key_id: i32,
+    key: &str,
+) -> Result<()> {
+    repo.delete_idempotent_request(key_id, key)
+}
+
+/// Check the database has every migration this build expects
+pub fn check_migrations<R: Repository + ?Sized>(repo: &R) -> Result<()> {
+    let expected = repo.migrations();
+    let applied = repo.get_applied_migrations().map_err(|e| Error::NotReady {
+        // Most likely the table is missing, because nothing has been run
+        detail: format!("Couldn't read the applied migrations: {e}"),
+        pending_migrations: expected.iter().map(|version| version.to_string()).collect(),
+    })?;
+
+    let pending: Vec<String> = expected
+        .iter()
+        .filter(|version| !applied.iter().any(|applied| applied == *version))
+        .map(|version| version.to_string())
+        .collect();
+
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::NotReady {
+            detail: format!("{} migrations haven't been run", pending.len()),
+            pending_migrations: pending,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conditional::Tags;
+    use crate::memory::MemoryStore;
+    use crate::models::{Dimensions, Money, Transport};
+    use crate::versions::{ApiVersion, DimensionsV1, ItemV1, NewItemV1, Patch};
+
+    const ACTOR: &str = "test";
+    const ANY: IfMatch = IfMatch(Tags::Any);
+
+    fn new_item(warehouse: Option<i32>, quantity: Option<i32>) -> NewInventoryItem {
+        NewInventoryItem {
+            warehouse,
+            quantity,
+            weight_g: 1500,
+            value: Money {
+                amount: "12.50".parse().unwrap(),
+                currency: "USD".to_string(),
+            },
+            transport: Transport::Land,
+            dimensions_mm: Dimensions {
+                width: 10,
+                height: 20,
+                depth: 30,
+            },
+        }
+    }
+
+    fn new_warehouse<R: Repository + ?Sized>(repo: &R) -> i32 {
+        create_warehouse(repo, ACTOR, &NewWarehouse { items: Vec::new() })
+            .unwrap()
+            .id
+    }
+
+    #[test]
+    fn creating_an_item_in_a_warehouse_stocks_it() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let w_id = new_warehouse(&repo);
+
+        let item = create_item(&repo, ACTOR, &new_item(Some(w_id), Some(5))).unwrap();
+
+        assert_eq!((item.warehouse, item.quantity), (Some(w_id), 5));
+        assert_eq!(get_warehouse(&repo, w_id).unwrap().items, vec![item.id]);
+        assert_eq!(item_history(&repo, item.id, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn an_item_is_in_one_warehouse_at_a_time() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let (first, second) = (new_warehouse(&repo), new_warehouse(&repo));
+        let item = create_item(&repo, ACTOR, &new_item(Some(first), None)).unwrap();
+
+        let err = warehouse_add_item(&repo, ACTOR, second, item.id, 1).unwrap_err();
+
+        assert!(matches!(
+            err,
+            Error::AlreadyAssigned { warehouse_id, .. } if warehouse_id == first
+        ));
+        assert!(get_warehouse(&repo, second).unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn removing_more_than_is_on_hand_fails() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let w_id = new_warehouse(&repo);
+        let item = create_item(&repo, ACTOR, &new_item(Some(w_id), Some(2))).unwrap();
+
+        let err = warehouse_remove_item(&repo, ACTOR, w_id, item.id, 3).unwrap_err();
+        assert!(matches!(err, Error::InsufficientStock { on_hand: 2, .. }));
+
+        // Removing the last of it takes the item out of the warehouse
+        warehouse_remove_item(&repo, ACTOR, w_id, item.id, 2).unwrap();
+        assert_eq!(get_item(&repo, item.id).unwrap().warehouse, None);
+    }
+
+    #[test]
+    fn adding_past_the_most_a_warehouse_holds_fails() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let w_id = new_warehouse(&repo);
+        let item = create_item(&repo, ACTOR, &new_item(Some(w_id), Some(i32::MAX - 1))).unwrap();
+
+        let err = warehouse_add_item(&repo, ACTOR, w_id, item.id, 2).unwrap_err();
+        assert!(matches!(err, Error::StockOverflow { requested: 2, .. }));
+        assert_eq!(err.status(), http::StatusCode::BAD_REQUEST);
+
+        warehouse_add_item(&repo, ACTOR, w_id, item.id, 1).unwrap();
+        assert_eq!(get_item(&repo, item.id).unwrap().quantity, i32::MAX);
+    }
+
+    #[test]
+    fn a_transfer_moves_every_item_or_none() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let (from, to) = (new_warehouse(&repo), new_warehouse(&repo));
+        let moved = create_item(&repo, ACTOR, &new_item(Some(from), None)).unwrap();
+        let elsewhere = create_item(&repo, ACTOR, &new_item(None, None)).unwrap();
+
+        let err = transfer_items(&repo, ACTOR, from, to, &[moved.id, elsewhere.id]).unwrap_err();
+        assert!(matches!(err, Error::NotAssigned { item_id, .. } if item_id == elsewhere.id));
+        assert_eq!(get_item(&repo, moved.id).unwrap().warehouse, Some(from));
+
+        let transfer = transfer_items(&repo, ACTOR, from, to, &[moved.id]).unwrap();
+        assert!(transfer.from.items.is_empty());
+        assert_eq!(transfer.to.items, vec![moved.id]);
+    }
+
+    #[test]
+    fn a_dry_run_import_writes_nothing() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let record = || WarehouseRecord {
+            id: None,
+            items: None,
+        };
+
+        let report = import_warehouses_csv(
+            &repo,
+            ACTOR,
+            vec![(2, Ok(record())), (3, Ok(record()))],
+            true,
+        )
+        .unwrap();
+
+        assert_eq!((report.dry_run, report.imported), (true, 2));
+        assert!(report.ids.is_empty());
+        assert!(repo.get_warehouses_by_id(10, &[1, 2]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_dry_run_item_import_hands_out_no_ids() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let record = |id| ItemRecord {
+            id,
+            warehouse: None,
+            quantity: None,
+            weight_g: 1500,
+            value: "12.50".to_string(),
+            currency: "EUR".to_string(),
+            transport: Transport::Sea,
+            width_mm: 10,
+            height_mm: 20,
+            depth_mm: 30,
+        };
+
+        let rows = vec![(2, Ok(record(Some(500)))), (3, Ok(record(None)))];
+        let report = import_items_csv(&repo, ACTOR, rows, true).unwrap();
+
+        assert_eq!((report.dry_run, report.imported), (true, 2));
+        assert!(report.ids.is_empty());
+        assert!(get_item(&repo, 500).is_err());
+        // The id picked in the dry run is still free, and ids carry on from where they were
+        assert_eq!(
+            create_item(&repo, ACTOR, &new_item(None, None)).unwrap().id,
+            1
+        );
+
+        let rows = vec![(2, Ok(record(Some(500)))), (3, Ok(record(None)))];
+        let report = import_items_csv(&repo, ACTOR, rows, false).unwrap();
+        assert_eq!(report.ids, [500, 501]);
+    }
+
+    #[test]
+    fn deleting_a_warehouse_releases_its_items() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let w_id = new_warehouse(&repo);
+        let item = create_item(&repo, ACTOR, &new_item(Some(w_id), Some(4))).unwrap();
+
+        let deleted = delete_warehouse(&repo, ACTOR, w_id, &ANY).unwrap();
+
+        assert_eq!(deleted.items, vec![item.id]);
+        assert_eq!(get_item(&repo, item.id).unwrap().warehouse, None);
+        let history = item_history(&repo, item.id, 10).unwrap();
+        assert_eq!(
+            history.last().unwrap().reason,
+            MovementReason::WarehouseDeleted
+        );
+    }
+
+    #[test]
+    fn fsck_finds_stock_changed_behind_the_ledger_and_repairs_it() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let (first, second) = (new_warehouse(&repo), new_warehouse(&repo));
+        let [moved, counted, lost, trashed, sold] = [3, 2, 1, 1, 1].map(|quantity| {
+            create_item(&repo, ACTOR, &new_item(Some(first), Some(quantity))).unwrap()
+        });
+        let untracked = create_item(&repo, ACTOR, &new_item(None, None)).unwrap();
+        delete_item(&repo, ACTOR, trashed.id, &ANY).unwrap();
+        warehouse_remove_item(&repo, ACTOR, first, sold.id, 1).unwrap();
+        assert!(check_consistency(&repo).unwrap().inconsistencies.is_empty());
+
+        // Straight to the repository, like an edit made in the database would
+        repo.move_stock(moved.id, second).unwrap();
+        repo.set_stock_quantity(counted.id, 5).unwrap();
+        repo.delete_stock(lost.id).unwrap();
+        repo.insert_stock(trashed.id, first, 1).unwrap();
+        repo.insert_stock(sold.id, first, 1).unwrap();
+        repo.insert_stock(untracked.id, first, 1).unwrap();
+
+        let found: Vec<_> = check_consistency(&repo)
+            .unwrap()
+            .inconsistencies
+            .into_iter()
+            .map(|inconsistency| (inconsistency.item_id, inconsistency.kind))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (moved.id, InconsistencyKind::WrongWarehouse),
+                (counted.id, InconsistencyKind::WrongQuantity),
+                (lost.id, InconsistencyKind::Unstocked),
+                (trashed.id, InconsistencyKind::TrashedItem),
+                (sold.id, InconsistencyKind::Unrecorded),
+                (untracked.id, InconsistencyKind::NoHistory),
+            ]
+        );
+
+        // Believing the stock keeps it as it is, and records what the ledger missed
+        let report = repair_consistency(&repo, ACTOR, RepairPolicy::Stock).unwrap();
+        assert_eq!(report.inconsistencies.len(), 6);
+        assert!(check_consistency(&repo).unwrap().inconsistencies.is_empty());
+        let now = get_item(&repo, moved.id).unwrap();
+        assert_eq!((now.warehouse, now.quantity), (Some(second), 3));
+        assert_eq!(get_item(&repo, counted.id).unwrap().quantity, 5);
+        assert_eq!(get_item(&repo, lost.id).unwrap().warehouse, None);
+        assert_eq!(get_item(&repo, sold.id).unwrap().warehouse, Some(first));
+        assert_eq!(
+            get_item(&repo, untracked.id).unwrap().warehouse,
+            Some(first)
+        );
+        assert!(repo
+            .get_all_stock()
+            .unwrap()
+            .iter()
+            .all(|row| row.item != trashed.id));
+
+        // Believing the ledger puts the stock back where the movements say,
+        // but stock they say nothing at all about is kept, and only recorded
+        repo.set_stock_quantity(moved.id, 9).unwrap();
+        let untracked = create_item(&repo, ACTOR, &new_item(None, None)).unwrap();
+        repo.insert_stock(untracked.id, second, 2).unwrap();
+        repair_consistency(&repo, ACTOR, RepairPolicy::Ledger).unwrap();
+        assert!(check_consistency(&repo).unwrap().inconsistencies.is_empty());
+        assert_eq!(get_item(&repo, moved.id).unwrap().quantity, 3);
+        let kept = get_item(&repo, untracked.id).unwrap();
+        assert_eq!((kept.warehouse, kept.quantity), (Some(second), 2));
+    }
+
+    #[test]
+    fn fsck_reports_movements_that_dont_add_up_and_leaves_them_be() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let (first, second) = (new_warehouse(&repo), new_warehouse(&repo));
+        let [overdrawn, overfilled] = [2, 2].map(|quantity| {
+            create_item(&repo, ACTOR, &new_item(Some(first), Some(quantity))).unwrap()
+        });
+
+        // Movements the service would never record, taking out more than was put in,
+        // and putting in more than a quantity holds
+        let corrupt = |item, reason, quantity| NewMovement {
+            item,
+            from_warehouse: Some(first),
+            to_warehouse: Some(first),
+            quantity,
+            reason,
+            actor: ACTOR,
+        };
+        repo.insert_movement(&corrupt(overdrawn.id, MovementReason::Removed, 5))
+            .unwrap();
+        repo.insert_movement(&corrupt(overfilled.id, MovementReason::Received, i32::MAX))
+            .unwrap();
+
+        let report = repair_consistency(&repo, ACTOR, RepairPolicy::Ledger).unwrap();
+        let found: Vec<_> = report
+            .inconsistencies
+            .iter()
+            .map(|inconsistency| {
+                (
+                    inconsistency.item_id,
+                    inconsistency.kind,
+                    inconsistency.ledger,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (overdrawn.id, InconsistencyKind::Unbalanced, None),
+                (overfilled.id, InconsistencyKind::Unbalanced, None),
+            ]
+        );
+        assert_eq!(get_item(&repo, overdrawn.id).unwrap().quantity, 2);
+        assert_eq!(get_item(&repo, overfilled.id).unwrap().quantity, 2);
+        assert_eq!(check_consistency(&repo).unwrap().inconsistencies.len(), 2);
+
+        // A transfer says where an item is whatever came before it
+        transfer_items(&repo, ACTOR, first, second, &[overdrawn.id, overfilled.id]).unwrap();
+        assert!(check_consistency(&repo).unwrap().inconsistencies.is_empty());
+    }
+
+    #[test]
+    fn a_restored_warehouse_gets_back_the_items_still_free() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let (w_id, elsewhere) = (new_warehouse(&repo), new_warehouse(&repo));
+        let kept = create_item(&repo, ACTOR, &new_item(Some(w_id), Some(4))).unwrap();
+        let moved = create_item(&repo, ACTOR, &new_item(Some(w_id), Some(2))).unwrap();
+        let trashed = create_item(&repo, ACTOR, &new_item(Some(w_id), Some(1))).unwrap();
+
+        delete_item(&repo, ACTOR, trashed.id, &ANY).unwrap();
+        delete_warehouse(&repo, ACTOR, w_id, &ANY).unwrap();
+        assert!(matches!(
+            get_warehouse(&repo, w_id),
+            Err(Error::WarehouseNotFound { .. })
+        ));
+        warehouse_add_item(&repo, ACTOR, elsewhere, moved.id, 1).unwrap();
+
+        let restored = restore_warehouse(&repo, ACTOR, w_id).unwrap();
+        assert_eq!(restored.items, vec![kept.id]);
+        assert_eq!(get_item(&repo, kept.id).unwrap().quantity, 4);
+
+        // The item deleted before its warehouse goes back to it, once it's restored
+        let item = restore_item(&repo, ACTOR, trashed.id).unwrap();
+        assert_eq!((item.warehouse, item.quantity), (Some(w_id), 1));
+        assert!(matches!(
+            restore_item(&repo, ACTOR, trashed.id),
+            Err(Error::ItemNotDeleted { .. })
+        ));
+    }
+
+    #[test]
+    fn a_v1_patch_only_rounds_the_fields_it_changes() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let w_id = new_warehouse(&repo);
+        let item = create_item(&repo, ACTOR, &new_item(Some(w_id), Some(2))).unwrap();
+
+        let patch = |patch: serde_json::Value| Patch {
+            version: ApiVersion::V1,
+            patch,
+        };
+        let patched = patch_item(&repo, item.id, &ANY, |item| {
+            patch(serde_json::json!({"weight": 3})).apply(item)
+        })
+        .unwrap();
+
+        assert_eq!(patched.weight_g, 3000);
+        assert_eq!(patched.value.amount, item.value.amount);
+        assert_eq!(patched.dimensions_mm.width, 10);
+
+        let moved = patch_item(&repo, item.id, &ANY, |item| {
+            patch(serde_json::json!({"warehouse": null})).apply(item)
+        });
+        assert!(matches!(moved, Err(Error::ImmutableField { field, .. }) if field == "warehouse"));
+    }
+
+    #[test]
+    fn a_v1_update_keeps_the_precision_of_what_it_didnt_change() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let new = NewInventoryItem {
+            weight_g: 1234,
+            value: Money {
+                amount: "10.00".parse().unwrap(),
+                currency: "EUR".to_string(),
+            },
+            dimensions_mm: Dimensions {
+                width: 15,
+                height: 25,
+                depth: 35,
+            },
+            ..new_item(None, None)
+        };
+        let item = create_item(&repo, ACTOR, &new).unwrap();
+
+        // What a version 1 client reads, and sends back with only the transport changed
+        let mut sent = ItemV1::from(&item);
+        sent.transport = Transport::Air;
+        let updated = update_item(&repo, &sent.into(), &ANY).unwrap();
+
+        assert_eq!(updated.transport, Transport::Air);
+        assert_eq!(updated.weight_g, 1234);
+        assert_eq!(updated.value, item.value);
+        assert_eq!(updated.dimensions_mm, item.dimensions_mm);
+
+        // The fields it does change are whole numbers, in the item's own currency
+        let mut sent = ItemV1::from(&updated);
+        (sent.weight, sent.value, sent.dimensions.width) = (3, 12, 2);
+        let updated = update_item(&repo, &sent.into(), &ANY).unwrap();
+
+        assert_eq!(updated.weight_g, 3000);
+        assert_eq!(updated.value.amount, "12".parse().unwrap());
+        assert_eq!(updated.value.currency, "EUR");
+        assert_eq!(updated.dimensions_mm.width, 2000);
+        assert_eq!(updated.dimensions_mm.height, 25);
+    }
+
+    #[test]
+    fn items_round_trip_between_versions() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+
+        // Created in version 1, it reads back in version 1 as it was sent
+        let new = NewItemV1 {
+            warehouse: None,
+            quantity: None,
+            weight: 4,
+            value: 250,
+            transport: Transport::Sea,
+            dimensions: DimensionsV1 {
+                width: 1,
+                height: 2,
+                depth: 3,
+            },
+        };
+        let item = create_item(&repo, ACTOR, &new.into()).unwrap();
+        assert_eq!(item.weight_g, 4000);
+        assert_eq!(item.value.amount, "250".parse().unwrap());
+        assert_eq!(item.value.currency, "USD");
+        assert_eq!(item.dimensions_mm.depth, 3000);
+
+        let read = ItemV1::from(&item);
+        assert_eq!(
+            (read.weight, read.value, read.transport),
+            (4, 250, Transport::Sea)
+        );
+        assert_eq!(
+            (
+                read.dimensions.width,
+                read.dimensions.height,
+                read.dimensions.depth
+            ),
+            (1, 2, 3)
+        );
+
+        // Created in version 2, version 1 sees it rounded, and dimensions rounded up
+        let item = create_item(&repo, ACTOR, &new_item(None, None)).unwrap();
+        let read = ItemV1::from(&item);
+        assert_eq!((read.weight, read.value), (2, 13));
+        assert_eq!(
+            (
+                read.dimensions.width,
+                read.dimensions.height,
+                read.dimensions.depth
+            ),
+            (1, 1, 1)
+        );
+
+        // and sending that back unchanged leaves it exactly as it was
+        let back = read.onto(&item);
+        assert_eq!(back.weight_g, item.weight_g);
+        assert_eq!(back.value, item.value);
+        assert_eq!(back.dimensions_mm, item.dimensions_mm);
+        assert_eq!(back.transport, item.transport);
+        assert_eq!(back.version, item.version);
+    }
+
+    #[test]
+    fn a_change_to_a_stale_version_is_turned_down() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let w_id = new_warehouse(&repo);
+        let item = create_item(&repo, ACTOR, &new_item(None, None)).unwrap();
+        let read = IfMatch(Tags::Versions(vec![item.version]));
+
+        // Stocking the item changes it, so the version read before is out of date
+        warehouse_add_item(&repo, ACTOR, w_id, item.id, 1).unwrap();
+
+        let updated = update_item(&repo, &item.clone().into(), &read);
+        assert!(
+            matches!(updated, Err(Error::PreconditionFailed { version }) if version > item.version)
+        );
+
+        let current = IfMatch(Tags::Versions(vec![
+            get_item(&repo, item.id).unwrap().version,
+        ]));
+        delete_item(&repo, ACTOR, item.id, &current).unwrap();
+    }
+
+    #[test]
+    fn a_retry_with_the_same_idempotency_key_gets_the_first_response() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let key_id = bootstrap_api_key(&repo).unwrap().unwrap().api_key.id;
+        let (window, abandoned) = (
+            std::time::Duration::from_secs(600),
+            std::time::Duration::from_secs(60),
+        );
+        let json = ["application/json", "", "*"];
+        let add = request_fingerprint("POST", "/api/warehouse/1/add", &json, b"{}");
+
+        assert_eq!(
+            begin_idempotent(&repo, key_id, "scan-1", &add, window, abandoned).unwrap(),
+            None
+        );
+        // The first attempt is still being handled
+        let retried = begin_idempotent(&repo, key_id, "scan-1", &add, window, abandoned);
+        assert!(matches!(retried, Err(Error::IdempotencyKeyInUse)));
+
+        let response = StoredResponse {
+            status: 200,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: b"{}".to_vec(),
+        };
+        save_idempotent_response(&repo, key_id, "scan-1", &response).unwrap();
+        let retried = begin_idempotent(&repo, key_id, "scan-1", &add, window, abandoned).unwrap();
+        assert_eq!(retried, Some(response));
+
+        let remove = request_fingerprint("POST", "/api/warehouse/1/remove", &json, b"{}");
+        let reused = begin_idempotent(&repo, key_id, "scan-1", &remove, window, abandoned);
+        assert!(matches!(reused, Err(Error::IdempotencyKeyReused)));
+
+        // Asking for the other version's response is another request too
+        let v2 = [
+            "application/json",
+            "application/vnd.warehouser.v2+json",
+            "*",
+        ];
+        let add_v2 = request_fingerprint("POST", "/api/warehouse/1/add", &v2, b"{}");
+        let reused = begin_idempotent(&repo, key_id, "scan-1", &add_v2, window, abandoned);
+        assert!(matches!(reused, Err(Error::IdempotencyKeyReused)));
+    }
+
+    #[test]
+    fn a_claim_is_only_given_up_once_it_has_been_abandoned() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let key_id = bootstrap_api_key(&repo).unwrap().unwrap().api_key.id;
+        let window = std::time::Duration::from_secs(600);
+        let import = request_fingerprint("POST", "/api/item/import", &["text/csv", "", ""], b"");
+        let begin = |abandoned: u64| {
+            let abandoned = std::time::Duration::from_secs(abandoned);
+            begin_idempotent(&repo, key_id, "import-1", &import, window, abandoned)
+        };
+
+        assert_eq!(begin(60).unwrap(), None);
+        // A slow change is still being made, however long its retries wait
+        assert!(matches!(begin(60), Err(Error::IdempotencyKeyInUse)));
+        assert!(matches!(begin(u64::MAX), Err(Error::IdempotencyKeyInUse)));
+
+        // Once it's been abandoned the retry claims the key for itself
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(begin(0).unwrap(), None);
+        assert!(matches!(begin(60), Err(Error::IdempotencyKeyInUse)));
+    }
+
+    #[test]
+    fn a_batch_is_kept_whole_or_not_at_all() {
+        let store = MemoryStore::new();
+        let repo = store.connect();
+        let w_id = new_warehouse(&repo);
+        let batch = || {
+            Batch(vec![
+                Operation::CreateItem {
+                    item: new_item(Some(w_id), Some(2)),
+                },
+                // The new item's id is 1, so this takes it out of the warehouse
+                Operation::RemoveItem {
+                    warehouse: w_id,
+                    item: 1,
+                    quantity: Some(2),
+                },
+                Operation::RemoveItem {
+                    warehouse: w_id,
+                    item: 1,
+                    quantity: None,
+                },
+            ])
+        };
+
+        let err = run_batch(&repo, ACTOR, &batch(), false).unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::BatchFailed { errors } if errors[0].operation == 2
+        ));
+        assert!(get_item(&repo, 1).is_err());
+
+        let results = run_batch(&repo, ACTOR, &batch(), true).unwrap();
+        assert!(results[0].is_ok() && results[1].is_ok());
+        assert!(matches!(results[2], Err(Error::NotAssigned { .. })));
+        assert_eq!(get_item(&repo, 1).unwrap().warehouse, None);
+    }
+}
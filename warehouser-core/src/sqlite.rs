@@ -0,0 +1,1523 @@
+/// The SQLite `Repository`, for edge sites and developer laptops
+/// The same operations as `crate::db`, against the schema in `migrations_sqlite/`
+// SQLite has no enum or composite types, so items are stored a little differently:
+// - enums are text, read and written through the `Sqlite*` types in `models`
+// - `Money` and `Dimensions` get a column per field
+// - amounts are whole ten-thousandths, so they stay exact and sort as numbers
+// and Diesel 1.x can't use `RETURNING` on SQLite, so rows are read back after they're written
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, Signed, ToPrimitive};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::connection::{SimpleConnection, TransactionManager};
+use diesel::dsl::sql;
+use diesel::expression::{AsExpression, BoxableExpression};
+use diesel::query_builder::{BoxedSelectStatement, QueryFragment};
+use diesel::query_source::joins::{Join, JoinOn, JoinTo, LeftOuter};
+use diesel::sql_types::{BigInt, Bool, Integer, Nullable, Text, Timestamp};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, NullableExpressionMethods, QueryDsl,
+    RunQueryDsl,
+};
+
+use crate::migrations::SQLITE_MIGRATIONS;
+use crate::models::{
+    ApiKey, ApiKeyRow, Dimensions, Error, FieldError, IdempotentRequest, InventoryItem, Money,
+    Movement, MovementReason, NewInventoryItem, NewMovement, Result, Role, Stock, StoredResponse,
+    Transport, Warehouse,
+};
+use crate::pagination::{Cursor, ItemKey, ItemParams, KeyType, Sort, WarehouseFilter};
+use crate::repository::Repository;
+use crate::sqlite_schema::{inventory, stock};
+
+/// Set on every connection the pool opens, SQLite leaves these off by default
+#[derive(Debug)]
+pub struct Pragmas;
+
+impl r2d2::CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for Pragmas {
+    fn on_acquire(
+        &self,
+        conn: &mut SqliteConnection,
+    ) -> std::result::Result<(), diesel::r2d2::Error> {
+        // Without `foreign_keys` the REFERENCES clauses aren't enforced,
+        // and without a `busy_timeout` a connection gives up the moment another one is writing
+        conn.batch_execute("PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000;")
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+// Amounts are stored as a whole number of ten-thousandths of the currency
+const AMOUNT_SCALE: i64 = 10_000;
+
+// The sql types of the columns `StoredItem` is loaded from
+type ItemSqlType = (
+    Integer,
+    Nullable<Integer>,
+    Integer,
+    BigInt,
+    BigInt,
+    Text,
+    crate::models::SqliteTransport,
+    Integer,
+    Integer,
+    Integer,
+    Integer,
+    Nullable<Timestamp>,
+);
+
+type ItemSource = JoinOn<
+    Join<inventory::table, stock::table, LeftOuter>,
+    <inventory::table as JoinTo<stock::table>>::OnClause,
+>;
+
+type ItemQuery<'a> = BoxedSelectStatement<'a, ItemSqlType, ItemSource, Sqlite>;
+
+sql_function!(fn coalesce(x: Nullable<Integer>, y: Integer) -> Integer);
+
+// An item as it's stored, with its composite fields spread over columns
+#[derive(Queryable)]
+struct StoredItem {
+    id: i32,
+    warehouse: Option<i32>,
+    quantity: i32,
+    weight_g: i64,
+    value_amount: i64,
+    value_currency: String,
+    transport: Transport,
+    width_mm: i32,
+    height_mm: i32,
+    depth_mm: i32,
+    version: i32,
+    deleted_at: Option<NaiveDateTime>,
+}
+
+impl From<StoredItem> for InventoryItem {
+    fn from(row: StoredItem) -> Self {
+        InventoryItem {
+            id: row.id,
+            warehouse: row.warehouse,
+            quantity: row.quantity,
+            weight_g: row.weight_g,
+            value: Money {
+                // The same scale Postgres gives a NUMERIC(19, 4)
+                amount: (BigDecimal::from(row.value_amount) / BigDecimal::from(AMOUNT_SCALE))
+                    .with_scale(4),
+                currency: row.value_currency,
+            },
+            transport: row.transport,
+            dimensions_mm: Dimensions {
+                width: row.width_mm,
+                height: row.height_mm,
+                depth: row.depth_mm,
+            },
+            version: row.version,
+            deleted_at: row.deleted_at.map(utc),
+        }
+    }
+}
+
+// The columns of an item that are stored on the `inventory` row
+#[derive(Insertable, AsChangeset)]
+#[table_name = "inventory"]
+struct ItemRow<'a> {
+    weight_g: i64,
+    value_amount: i64,
+    value_currency: &'a str,
+    transport: &'a Transport,
+    width_mm: i32,
+    height_mm: i32,
+    depth_mm: i32,
+}
+
+impl<'a> ItemRow<'a> {
+    fn new(
+        weight_g: i64,
+        value: &'a Money,
+        transport: &'a Transport,
+        dimensions_mm: &Dimensions,
+    ) -> Result<Self> {
+        // Validation allows amounts up to 10^15, but a 64 bit integer of
+        // ten-thousandths runs out a little before that
+        let value_amount = (&value.amount * BigDecimal::from(AMOUNT_SCALE))
+            .to_i64()
+            .ok_or_else(|| Error::Validation {
+                errors: vec![FieldError {
+                    field: "value.amount".to_string(),
+                    code: "range".to_string(),
+                    message: format!("must be at most {} with SQLite", max_amount()),
+                }],
+            })?;
+
+        Ok(ItemRow {
+            weight_g,
+            value_amount,
+            value_currency: &value.currency,
+            transport,
+            width_mm: dimensions_mm.width,
+            height_mm: dimensions_mm.height,
+            depth_mm: dimensions_mm.depth,
+        })
+    }
+}
+
+fn max_amount() -> BigDecimal {
+    (BigDecimal::from(i64::MAX) / BigDecimal::from(AMOUNT_SCALE)).with_scale(4)
+}
+
+fn new_item_row(item: &NewInventoryItem) -> Result<ItemRow<'_>> {
+    ItemRow::new(
+        item.weight_g,
+        &item.value,
+        &item.transport,
+        &item.dimensions_mm,
+    )
+}
+
+fn item_row(item: &InventoryItem) -> Result<ItemRow<'_>> {
+    ItemRow::new(
+        item.weight_g,
+        &item.value,
+        &item.transport,
+        &item.dimensions_mm,
+    )
+}
+
+#[derive(Queryable)]
+struct StoredMovement {
+    id: i64,
+    item: i32,
+    from_warehouse: Option<i32>,
+    to_warehouse: Option<i32>,
+    quantity: i32,
+    reason: MovementReason,
+    created_at: NaiveDateTime,
+    actor: String,
+}
+
+impl From<StoredMovement> for Movement {
+    fn from(row: StoredMovement) -> Self {
+        Movement {
+            id: row.id,
+            item: row.item,
+            from_warehouse: row.from_warehouse,
+            to_warehouse: row.to_warehouse,
+            quantity: row.quantity,
+            reason: row.reason,
+            created_at: utc(row.created_at),
+            actor: row.actor,
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct StoredApiKey {
+    id: i32,
+    name: String,
+    role: Role,
+    key_hash: String,
+    prefix: String,
+    created_at: NaiveDateTime,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+impl From<StoredApiKey> for ApiKey {
+    fn from(row: StoredApiKey) -> Self {
+        ApiKey {
+            id: row.id,
+            name: row.name,
+            role: row.role,
+            key_hash: row.key_hash,
+            prefix: row.prefix,
+            created_at: utc(row.created_at),
+            revoked_at: row.revoked_at.map(utc),
+        }
+    }
+}
+
+// SQLite has no time zones, every timestamp we store is in UTC
+fn utc(time: NaiveDateTime) -> DateTime<Utc> {
+    DateTime::<Utc>::from_utc(time, Utc)
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// The id SQLite gave the row this connection inserted last
+fn last_insert_id(conn: &SqliteConnection) -> Result<i64> {
+    diesel::select(sql::<BigInt>("last_insert_rowid()"))
+        .get_result(conn)
+        .map_err(Into::into)
+}
+
+// An item's warehouse and quantity live in the stock table
+// so every item we hand out is read through this join
+fn items<'a>() -> ItemQuery<'a> {
+    inventory::table
+        .left_join(stock::table)
+        .select((
+            inventory::id,
+            stock::warehouse.nullable(),
+            coalesce(stock::quantity.nullable(), 0),
+            inventory::weight_g,
+            inventory::value_amount,
+            inventory::value_currency,
+            inventory::transport,
+            inventory::width_mm,
+            inventory::height_mm,
+            inventory::depth_mm,
+            inventory::version,
+            inventory::deleted_at,
+        ))
+        .into_boxed()
+}
+
+// The items that aren't in the trash
+fn live_items<'a>() -> ItemQuery<'a> {
+    items().filter(inventory::deleted_at.is_null())
+}
+
+fn load_items(query: ItemQuery, conn: &SqliteConnection) -> Result<Vec<InventoryItem>> {
+    let rows: Vec<StoredItem> = query.load(conn)?;
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+// The expression a sort key is compared by, `ItemKey::sql` has the Postgres ones
+fn sort_sql(key: ItemKey) -> &'static str {
+    match key {
+        ItemKey::Id => "inventory.id",
+        // Items in no warehouse sort first, warehouse ids are always positive
+        ItemKey::Warehouse => "COALESCE(stock.warehouse, 0)",
+        ItemKey::Quantity => "COALESCE(stock.quantity, 0)",
+        ItemKey::Weight => "inventory.weight_g",
+        ItemKey::Value => "inventory.value_amount",
+        ItemKey::Transport => "inventory.transport",
+        ItemKey::Width => "inventory.width_mm",
+        ItemKey::Height => "inventory.height_mm",
+        ItemKey::Depth => "inventory.depth_mm",
+    }
+}
+
+pub fn get_items_by_id(
+    conn: &SqliteConnection,
+    limit: i64,
+    ids: &[i32],
+) -> Result<Vec<InventoryItem>> {
+    load_items(
+        live_items().limit(limit).filter(inventory::id.eq_any(ids)),
+        conn,
+    )
+}
+
+/// Items matching `params`, optionally only those in warehouse `w_id`,
+/// in `sort` order and starting after `after`.
+/// Returns up to one more item than the limit, so the caller can tell if there's another page
+pub fn list_items(
+    conn: &SqliteConnection,
+    params: &ItemParams,
+    w_id: Option<i32>,
+    sort: Sort<ItemKey>,
+    after: Option<&Cursor>,
+) -> Result<Vec<InventoryItem>> {
+    let mut query = if params.include_deleted() {
+        items()
+    } else {
+        live_items()
+    };
+
+    if let Some(w_id) = w_id {
+        query = query.filter(stock::warehouse.eq(w_id));
+    }
+
+    match params.warehouse {
+        Some(WarehouseFilter::None) => query = query.filter(stock::warehouse.nullable().is_null()),
+        Some(WarehouseFilter::Id(w_id)) => query = query.filter(stock::warehouse.eq(w_id)),
+        None => {}
+    }
+
+    if let Some(transport) = &params.transport {
+        query = query.filter(inventory::transport.eq(transport.clone()));
+    }
+
+    if let Some(currency) = &params.currency {
+        query = query.filter(inventory::value_currency.eq(currency.clone()));
+    }
+
+    // Weights are filtered in kg, but stored in grams, amounts are stored in ten-thousandths
+    let scaled = |bound: &Option<BigDecimal>, scale: i64| {
+        bound.as_ref().map(|bound| bound * BigDecimal::from(scale))
+    };
+    let ranges = [
+        (
+            ">",
+            scaled(&params.weight_gt, 1000),
+            scaled(&params.value_gt, AMOUNT_SCALE),
+            params.quantity_gt,
+        ),
+        (
+            ">=",
+            scaled(&params.weight_gte, 1000),
+            scaled(&params.value_gte, AMOUNT_SCALE),
+            params.quantity_gte,
+        ),
+        (
+            "<",
+            scaled(&params.weight_lt, 1000),
+            scaled(&params.value_lt, AMOUNT_SCALE),
+            params.quantity_lt,
+        ),
+        (
+            "<=",
+            scaled(&params.weight_lte, 1000),
+            scaled(&params.value_lte, AMOUNT_SCALE),
+            params.quantity_lte,
+        ),
+    ];
+
+    for (op, weight, value, quantity) in ranges {
+        if let Some(weight) = weight {
+            let bound = whole(&weight, op);
+            query = query.filter(compare::<BigInt, _>(sort_sql(ItemKey::Weight), op, bound));
+        }
+        if let Some(value) = value {
+            let bound = whole(&value, op);
+            query = query.filter(compare::<BigInt, _>(sort_sql(ItemKey::Value), op, bound));
+        }
+        if let Some(quantity) = quantity {
+            query = query.filter(compare::<Integer, _>(
+                sort_sql(ItemKey::Quantity),
+                op,
+                quantity,
+            ));
+        }
+    }
+
+    let dir = if sort.descending { "DESC" } else { "ASC" };
+
+    if let Some(after) = after {
+        query = query.filter(after_item(sort, after)?);
+    }
+
+    let query = query
+        .order(sql::<Bool>(&format!(
+            "{} {dir}, inventory.id {dir}",
+            sort_sql(sort.key)
+        )))
+        .limit(params.limit() + 1);
+
+    load_items(query, conn)
+}
+
+// Stored weights and amounts are whole numbers, so a bound with a fraction is rounded
+// to the whole number that keeps the same rows, `> 1.5` is `> 1` and `>= 1.5` is `>= 2`
+fn whole(bound: &BigDecimal, op: &str) -> i64 {
+    let truncated = bound.with_scale(0);
+    let rounded = match op {
+        ">" | "<=" if truncated > *bound => truncated - BigDecimal::from(1),
+        ">=" | "<" if truncated < *bound => truncated + BigDecimal::from(1),
+        _ => truncated,
+    };
+
+    // A bound past what a column can hold keeps all of its rows or none of them
+    rounded.to_i64().unwrap_or(if rounded.is_negative() {
+        i64::MIN
+    } else {
+        i64::MAX
+    })
+}
+
+type ItemPredicate<'a> = Box<dyn BoxableExpression<ItemSource, Sqlite, SqlType = Bool> + 'a>;
+
+// `expr op value`, with `value` sent as a bind parameter
+// `expr` and `op` are always our own strings, never user input
+fn compare<'a, ST, T>(expr: &str, op: &str, value: T) -> ItemPredicate<'a>
+where
+    ST: 'a,
+    T: AsExpression<ST>,
+    T::Expression: QueryFragment<Sqlite> + 'a,
+{
+    Box::new(sql::<Bool>(&format!("{expr} {op} ")).bind::<ST, _>(value))
+}
+
+// Items that come after the cursor in `sort` order
+fn after_item<'a>(sort: Sort<ItemKey>, after: &Cursor) -> Result<ItemPredicate<'a>> {
+    let op = if sort.descending { "<" } else { ">" };
+    let start = sql::<Bool>(&format!("({}, inventory.id) {op} (", sort_sql(sort.key)));
+    let invalid = || Error::InvalidRequest {
+        detail: "cursor is not valid".to_string(),
+    };
+
+    let predicate: ItemPredicate = match sort.key.key_type() {
+        KeyType::Int => {
+            let key: i64 = after.key.parse().map_err(|_| invalid())?;
+            Box::new(
+                start
+                    .bind::<BigInt, _>(key)
+                    .sql(", ")
+                    .bind::<Integer, _>(after.id)
+                    .sql(")"),
+            )
+        }
+        // Cursors hold the amount, the column holds ten-thousandths of it
+        KeyType::Decimal => {
+            let key = BigDecimal::from_str(&after.key).map_err(|_| invalid())?
+                * BigDecimal::from(AMOUNT_SCALE);
+            if key.with_scale(0) != key {
+                return Err(invalid());
+            }
+            let key = key.to_i64().ok_or_else(invalid)?;
+            Box::new(
+                start
+                    .bind::<BigInt, _>(key)
+                    .sql(", ")
+                    .bind::<Integer, _>(after.id)
+                    .sql(")"),
+            )
+        }
+        KeyType::Text => Box::new(
+            start
+                .bind::<Text, _>(after.key.clone())
+                .sql(", ")
+                .bind::<Integer, _>(after.id)
+                .sql(")"),
+        ),
+    };
+
+    Ok(predicate)
+}
+
+pub fn get_item(conn: &SqliteConnection, id_: i32) -> Result<InventoryItem> {
+    let row: StoredItem = live_items().filter(inventory::id.eq(id_)).first(conn)?;
+    Ok(row.into())
+}
+
+// An item whether it's in the trash or not
+fn get_any_item(conn: &SqliteConnection, id_: i32) -> Result<InventoryItem> {
+    let row: StoredItem = items().filter(inventory::id.eq(id_)).first(conn)?;
+    Ok(row.into())
+}
+
+/// Insert an item's row, the item is not stocked by any warehouse
+pub fn insert_item(conn: &SqliteConnection, item: &NewInventoryItem) -> Result<InventoryItem> {
+    diesel::insert_into(inventory::table)
+        .values(new_item_row(item)?)
+        .execute(conn)?;
+
+    get_item(conn, last_insert_id(conn)? as i32)
+}
+
+/// Insert an item's row with a client-chosen id, the item is not stocked by any warehouse
+pub fn import_item(conn: &SqliteConnection, item: &InventoryItem) -> Result<InventoryItem> {
+    // With AUTOINCREMENT, SQLite never hands out an id below one a client picked,
+    // so there's no sequence to move past it like there is on Postgres
+    diesel::insert_into(inventory::table)
+        .values((inventory::id.eq(item.id), item_row(item)?))
+        .execute(conn)?;
+
+    get_item(conn, item.id)
+}
+
+/// Update the columns stored on the item's row, its stock is left alone
+pub fn update_item(conn: &SqliteConnection, item: &InventoryItem) -> Result<InventoryItem> {
+    diesel::update(inventory::table)
+        .filter(inventory::id.eq(item.id))
+        .filter(inventory::deleted_at.is_null())
+        .set(item_row(item)?)
+        .execute(conn)?;
+
+    get_item(conn, item.id)
+}
+
+/// Move an item that's in no warehouse to the trash
+pub fn trash_item(conn: &SqliteConnection, id_: i32) -> Result<InventoryItem> {
+    let trashed = diesel::update(inventory::table)
+        .filter(inventory::id.eq(id_))
+        .filter(inventory::deleted_at.is_null())
+        .set(inventory::deleted_at.eq(now()))
+        .execute(conn)?;
+
+    match trashed {
+        0 => Err(Error::NotFound),
+        _ => get_any_item(conn, id_),
+    }
+}
+
+/// Take an item back out of the trash, in no warehouse
+pub fn restore_item(conn: &SqliteConnection, id_: i32) -> Result<InventoryItem> {
+    let restored = diesel::update(inventory::table)
+        .filter(inventory::id.eq(id_))
+        .filter(inventory::deleted_at.is_not_null())
+        .set(inventory::deleted_at.eq(None::<NaiveDateTime>))
+        .execute(conn)?;
+
+    match restored {
+        0 => Err(Error::NotFound),
+        _ => get_item(conn, id_),
+    }
+}
+
+/// Items in the trash, most recently deleted first
+pub fn get_trashed_items(conn: &SqliteConnection, limit: i64) -> Result<Vec<InventoryItem>> {
+    load_items(
+        items()
+            .filter(inventory::deleted_at.is_not_null())
+            .order((inventory::deleted_at.desc(), inventory::id.desc()))
+            .limit(limit),
+        conn,
+    )
+}
+
+/// Delete the items moved to the trash before `before` for good, returns how many there were
+pub fn purge_items(conn: &SqliteConnection, before: DateTime<Utc>) -> Result<usize> {
+    diesel::delete(inventory::table.filter(inventory::deleted_at.lt(before.naive_utc())))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+/// Every item in a warehouse
+pub fn get_warehouse_items(
+    conn: &SqliteConnection,
+    w_id: i32,
+    limit: i64,
+) -> Result<Vec<InventoryItem>> {
+    load_items(
+        items()
+            .filter(stock::warehouse.eq(w_id))
+            .order(inventory::id)
+            .limit(limit),
+        conn,
+    )
+}
+
+fn get_stock(conn: &SqliteConnection, item_id: i32) -> Result<Stock> {
+    stock::table.find(item_id).first(conn).map_err(Into::into)
+}
+
+/// Stock an item that is in no warehouse
+pub fn insert_stock(conn: &SqliteConnection, item_id: i32, w_id: i32, qty: i32) -> Result<Stock> {
+    use crate::sqlite_schema::stock::dsl::*;
+
+    diesel::insert_into(stock)
+        .values((item.eq(item_id), warehouse.eq(w_id), quantity.eq(qty)))
+        .execute(conn)?;
+
+    get_stock(conn, item_id)
+}
+
+/// Change how many units of an item are on hand
+pub fn set_stock_quantity(conn: &SqliteConnection, item_id: i32, qty: i32) -> Result<Stock> {
+    use crate::sqlite_schema::stock::dsl::*;
+
+    diesel::update(stock)
+        .filter(item.eq(item_id))
+        .set(quantity.eq(qty))
+        .execute(conn)?;
+
+    get_stock(conn, item_id)
+}
+
+/// Move all of an item's stock to another warehouse
+pub fn move_stock(conn: &SqliteConnection, item_id: i32, w_id: i32) -> Result<Stock> {
+    use crate::sqlite_schema::stock::dsl::*;
+
+    diesel::update(stock)
+        .filter(item.eq(item_id))
+        .set(warehouse.eq(w_id))
+        .execute(conn)?;
+
+    get_stock(conn, item_id)
+}
+
+/// Take an item out of its warehouse entirely
+pub fn delete_stock(conn: &SqliteConnection, item_id: i32) -> Result<Stock> {
+    use crate::sqlite_schema::stock::dsl::*;
+
+    let deleted = get_stock(conn, item_id)?;
+
+    diesel::delete(stock)
+        .filter(item.eq(item_id))
+        .execute(conn)?;
+
+    Ok(deleted)
+}
+
+pub fn insert_movement(conn: &SqliteConnection, movement: &NewMovement) -> Result<Movement> {
+    use crate::sqlite_schema::movements::dsl::*;
+
+    diesel::insert_into(movements)
+        .values((
+            item.eq(movement.item),
+            from_warehouse.eq(movement.from_warehouse),
+            to_warehouse.eq(movement.to_warehouse),
+            quantity.eq(movement.quantity),
+            reason.eq(movement.reason),
+            created_at.eq(now()),
+            actor.eq(movement.actor),
+        ))
+        .execute(conn)?;
+
+    let row: StoredMovement = movements.find(last_insert_id(conn)?).first(conn)?;
+    Ok(row.into())
+}
+
+/// Every movement of an item, oldest first
+pub fn get_item_movements(
+    conn: &SqliteConnection,
+    item_id: i32,
+    limit: i64,
+) -> Result<Vec<Movement>> {
+    use crate::sqlite_schema::movements::dsl::*;
+
+    let rows: Vec<StoredMovement> = movements
+        .filter(item.eq(item_id))
+        .order(id)
+        .limit(limit)
+        .load(conn)?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// The most recent movement of an item
+pub fn get_last_item_movement(conn: &SqliteConnection, item_id: i32) -> Result<Movement> {
+    use crate::sqlite_schema::movements::dsl::*;
+
+    let row: StoredMovement = movements
+        .filter(item.eq(item_id))
+        .order(id.desc())
+        .first(conn)?;
+
+    Ok(row.into())
+}
+
+/// Every movement into or out of a warehouse, oldest first
+pub fn get_warehouse_movements(
+    conn: &SqliteConnection,
+    w_id: i32,
+    limit: i64,
+) -> Result<Vec<Movement>> {
+    use crate::sqlite_schema::movements::dsl::*;
+
+    let rows: Vec<StoredMovement> = movements
+        .filter(from_warehouse.eq(w_id).or(to_warehouse.eq(w_id)))
+        .order(id)
+        .limit(limit)
+        .load(conn)?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Up to `limit` movements with an id above `after`, oldest first
+pub fn get_movements_after(
+    conn: &SqliteConnection,
+    after: i64,
+    limit: i64,
+) -> Result<Vec<Movement>> {
+    use crate::sqlite_schema::movements::dsl::*;
+
+    let rows: Vec<StoredMovement> = movements
+        .filter(id.gt(after))
+        .order(id)
+        .limit(limit)
+        .load(conn)?;
+
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Every stock row, by item
+pub fn get_all_stock(conn: &SqliteConnection) -> Result<Vec<Stock>> {
+    use crate::sqlite_schema::stock::dsl::*;
+
+    stock.order(item).load(conn).map_err(Into::into)
+}
+
+/// Remove every item from a warehouse, returns the number of items released
+pub fn clear_warehouse_items(conn: &SqliteConnection, w_id: i32) -> Result<usize> {
+    use crate::sqlite_schema::stock::dsl::*;
+
+    diesel::delete(stock)
+        .filter(warehouse.eq(w_id))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+// The columns of a warehouse's row, its id, version, and when it was moved to the trash
+type WarehouseRow = (i32, i32, Option<NaiveDateTime>);
+
+// Warehouses don't store their items,
+// so every warehouse we hand out is assembled from its row
+// and the items that point at it
+fn with_items(conn: &SqliteConnection, rows: Vec<WarehouseRow>) -> Result<Vec<Warehouse>> {
+    use crate::sqlite_schema::stock::dsl::*;
+
+    let ids: Vec<i32> = rows.iter().map(|(id_, _, _)| *id_).collect();
+    let owned: Vec<(i32, i32)> = stock
+        .select((warehouse, item))
+        .filter(warehouse.eq_any(&ids))
+        .order(item)
+        .load(conn)?;
+
+    let mut whouses: Vec<Warehouse> = rows
+        .into_iter()
+        .map(|(id_, version_, deleted_at_)| Warehouse {
+            id: id_,
+            items: Vec::new(),
+            version: version_,
+            deleted_at: deleted_at_.map(utc),
+        })
+        .collect();
+
+    let index: HashMap<i32, usize> = whouses
+        .iter()
+        .enumerate()
+        .map(|(i, whouse)| (whouse.id, i))
+        .collect();
+
+    for (w_id, item_id) in owned {
+        if let Some(&i) = index.get(&w_id) {
+            whouses[i].items.push(item_id);
+        }
+    }
+
+    Ok(whouses)
+}
+
+pub fn get_warehouses_by_id(
+    conn: &SqliteConnection,
+    limit: i64,
+    ids: &[i32],
+) -> Result<Vec<Warehouse>> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    let found = warehouses
+        .select((id, version, deleted_at))
+        .limit(limit)
+        .filter(id.eq_any(ids))
+        .filter(deleted_at.is_null())
+        .get_results(conn)?;
+
+    with_items(conn, found)
+}
+
+/// Warehouses in `sort` order, starting after `after`, along with the ones in the trash
+/// when `trashed` is set.
+/// Returns up to one more warehouse than the limit, so the caller can tell if there's another page
+pub fn list_warehouses(
+    conn: &SqliteConnection,
+    limit: i64,
+    trashed: bool,
+    sort: Sort<()>,
+    after: Option<&Cursor>,
+) -> Result<Vec<Warehouse>> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    let mut query = warehouses.select((id, version, deleted_at)).into_boxed();
+
+    if !trashed {
+        query = query.filter(deleted_at.is_null());
+    }
+
+    query = match (sort.descending, after) {
+        (false, Some(after)) => query.filter(id.gt(after.id)),
+        (true, Some(after)) => query.filter(id.lt(after.id)),
+        (_, None) => query,
+    };
+
+    query = if sort.descending {
+        query.order(id.desc())
+    } else {
+        query.order(id.asc())
+    };
+
+    let found = query.limit(limit + 1).get_results(conn)?;
+
+    with_items(conn, found)
+}
+
+pub fn get_warehouse(conn: &SqliteConnection, id_: i32) -> Result<Warehouse> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    let found = warehouses
+        .select((id, version, deleted_at))
+        .find(id_)
+        .filter(deleted_at.is_null())
+        .first(conn)?;
+
+    let mut whouses = with_items(conn, vec![found])?;
+    Ok(whouses.remove(0))
+}
+
+// A warehouse whether it's in the trash or not
+fn get_any_warehouse(conn: &SqliteConnection, id_: i32) -> Result<Warehouse> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    let found = warehouses
+        .select((id, version, deleted_at))
+        .find(id_)
+        .first(conn)?;
+
+    let mut whouses = with_items(conn, vec![found])?;
+    Ok(whouses.remove(0))
+}
+
+/// Insert an empty warehouse
+pub fn insert_warehouse(conn: &SqliteConnection) -> Result<Warehouse> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    diesel::insert_into(warehouses)
+        .default_values()
+        .execute(conn)?;
+
+    get_warehouse(conn, last_insert_id(conn)? as i32)
+}
+
+/// Insert an empty warehouse with a client-chosen id
+pub fn import_warehouse(conn: &SqliteConnection, id_: i32) -> Result<Warehouse> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    diesel::insert_into(warehouses)
+        .values(id.eq(id_))
+        .execute(conn)?;
+
+    get_warehouse(conn, id_)
+}
+
+/// Move an empty warehouse to the trash
+pub fn trash_warehouse(conn: &SqliteConnection, id_: i32) -> Result<Warehouse> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    let trashed = diesel::update(warehouses)
+        .filter(id.eq(id_))
+        .filter(deleted_at.is_null())
+        .set(deleted_at.eq(now()))
+        .execute(conn)?;
+
+    match trashed {
+        0 => Err(Error::NotFound),
+        _ => get_any_warehouse(conn, id_),
+    }
+}
+
+/// Take a warehouse back out of the trash, still empty
+pub fn restore_warehouse(conn: &SqliteConnection, id_: i32) -> Result<Warehouse> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    let restored = diesel::update(warehouses)
+        .filter(id.eq(id_))
+        .filter(deleted_at.is_not_null())
+        .set(deleted_at.eq(None::<NaiveDateTime>))
+        .execute(conn)?;
+
+    match restored {
+        0 => Err(Error::NotFound),
+        _ => get_warehouse(conn, id_),
+    }
+}
+
+/// Warehouses in the trash, most recently deleted first
+pub fn get_trashed_warehouses(conn: &SqliteConnection, limit: i64) -> Result<Vec<Warehouse>> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    let found = warehouses
+        .select((id, version, deleted_at))
+        .filter(deleted_at.is_not_null())
+        .order((deleted_at.desc(), id.desc()))
+        .limit(limit)
+        .get_results(conn)?;
+
+    with_items(conn, found)
+}
+
+/// Delete the warehouses moved to the trash before `before` for good,
+/// returns how many there were
+pub fn purge_warehouses(conn: &SqliteConnection, before: DateTime<Utc>) -> Result<usize> {
+    use crate::sqlite_schema::warehouses::dsl::*;
+
+    diesel::delete(warehouses.filter(deleted_at.lt(before.naive_utc())))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+pub fn insert_api_key(conn: &SqliteConnection, key: &ApiKeyRow) -> Result<ApiKey> {
+    use crate::sqlite_schema::api_keys::dsl::*;
+
+    diesel::insert_into(api_keys)
+        .values((
+            name.eq(key.name),
+            role.eq(key.role),
+            key_hash.eq(key.key_hash),
+            prefix.eq(key.prefix),
+            created_at.eq(now()),
+        ))
+        .execute(conn)?;
+
+    let row: StoredApiKey = api_keys.find(last_insert_id(conn)? as i32).first(conn)?;
+    Ok(row.into())
+}
+
+/// Every API key, revoked ones included, oldest first
+pub fn get_api_keys(conn: &SqliteConnection) -> Result<Vec<ApiKey>> {
+    use crate::sqlite_schema::api_keys::dsl::*;
+
+    let rows: Vec<StoredApiKey> = api_keys.order(id).load(conn)?;
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// The key with this hash, as long as it hasn't been revoked
+pub fn get_active_api_key(conn: &SqliteConnection, hash: &str) -> Result<ApiKey> {
+    use crate::sqlite_schema::api_keys::dsl::*;
+
+    let row: StoredApiKey = api_keys
+        .filter(key_hash.eq(hash))
+        .filter(revoked_at.is_null())
+        .first(conn)?;
+
+    Ok(row.into())
+}
+
+/// Revoke a key, revoking a key twice keeps the time it was first revoked
+pub fn revoke_api_key(conn: &SqliteConnection, key_id: i32) -> Result<ApiKey> {
+    use crate::sqlite_schema::api_keys::dsl::*;
+
+    diesel::update(api_keys.find(key_id).filter(revoked_at.is_null()))
+        .set(revoked_at.eq(now()))
+        .execute(conn)?;
+
+    let row: StoredApiKey = api_keys.find(key_id).first(conn)?;
+    Ok(row.into())
+}
+
+pub fn count_api_keys(conn: &SqliteConnection) -> Result<i64> {
+    use crate::sqlite_schema::api_keys::dsl::*;
+
+    api_keys.count().get_result(conn).map_err(Into::into)
+}
+
+// A row of `idempotency_keys`, its response is kept in three columns.
+// The body is read as hex, Diesel can't read an empty blob, which SQLite gives as a null pointer
+type IdempotentRow = (
+    i32,
+    String,
+    String,
+    Option<i32>,
+    Option<String>,
+    Option<String>,
+    NaiveDateTime,
+);
+
+fn idempotent_request(row: IdempotentRow) -> Result<IdempotentRequest> {
+    let (key_id, idempotency_key, fingerprint, status, headers, body, created_at) = row;
+    let body = body
+        .map(|body| {
+            hex::decode(body).map_err(|e| Error::Database {
+                detail: format!("Invalid stored body: {e}"),
+            })
+        })
+        .transpose()?;
+    Ok(IdempotentRequest {
+        key_id,
+        idempotency_key,
+        fingerprint,
+        response: StoredResponse::from_columns(status, headers, body)?,
+        created_at: utc(created_at),
+    })
+}
+
+pub fn get_idempotent_request(
+    conn: &SqliteConnection,
+    key_id_: i32,
+    key: &str,
+) -> Result<IdempotentRequest> {
+    use crate::sqlite_schema::idempotency_keys::dsl::*;
+
+    let row = idempotency_keys
+        .find((key_id_, key))
+        .select((
+            key_id,
+            idempotency_key,
+            fingerprint,
+            status,
+            headers,
+            sql::<Nullable<Text>>("hex(body)"),
+            created_at,
+        ))
+        .first(conn)?;
+    idempotent_request(row)
+}
+
+pub fn insert_idempotent_request(
+    conn: &SqliteConnection,
+    key_id_: i32,
+    key: &str,
+    fingerprint_: &str,
+) -> Result<IdempotentRequest> {
+    use crate::sqlite_schema::idempotency_keys::dsl::*;
+
+    diesel::insert_into(idempotency_keys)
+        .values((
+            key_id.eq(key_id_),
+            idempotency_key.eq(key),
+            fingerprint.eq(fingerprint_),
+            created_at.eq(now()),
+        ))
+        .execute(conn)?;
+
+    get_idempotent_request(conn, key_id_, key)
+}
+
+pub fn save_idempotent_response(
+    conn: &SqliteConnection,
+    key_id_: i32,
+    key: &str,
+    response: &StoredResponse,
+) -> Result<()> {
+    use crate::sqlite_schema::idempotency_keys::dsl::*;
+
+    let updated = diesel::update(idempotency_keys.find((key_id_, key)))
+        .set((
+            status.eq(response.status as i32),
+            headers.eq(response.headers_column()),
+            body.eq(&response.body),
+        ))
+        .execute(conn)?;
+
+    match updated {
+        0 => Err(Error::NotFound),
+        _ => Ok(()),
+    }
+}
+
+pub fn delete_idempotent_request(conn: &SqliteConnection, key_id_: i32, key: &str) -> Result<()> {
+    use crate::sqlite_schema::idempotency_keys::dsl::*;
+
+    diesel::delete(idempotency_keys.find((key_id_, key))).execute(conn)?;
+    Ok(())
+}
+
+pub fn delete_idempotent_requests_before(
+    conn: &SqliteConnection,
+    before: DateTime<Utc>,
+) -> Result<usize> {
+    use crate::sqlite_schema::idempotency_keys::dsl::*;
+
+    diesel::delete(idempotency_keys.filter(created_at.lt(before.naive_utc())))
+        .execute(conn)
+        .map_err(Into::into)
+}
+
+#[derive(QueryableByName)]
+struct AppliedMigration {
+    #[sql_type = "Text"]
+    version: String,
+}
+
+/// The version of every migration that's been run on the database, as recorded by Diesel
+pub fn get_applied_migrations(conn: &SqliteConnection) -> Result<Vec<String>> {
+    let applied: Vec<AppliedMigration> =
+        diesel::sql_query("SELECT version FROM __diesel_schema_migrations").load(conn)?;
+
+    Ok(applied.into_iter().map(|m| m.version).collect())
+}
+
+// Diesel nests transactions as savepoints, the same as `conn.transaction` does
+impl Repository for SqliteConnection {
+    fn begin(&self) -> Result<()> {
+        let manager = self.transaction_manager();
+
+        // SQLite has no row locks, so the outermost transaction takes the write lock up front.
+        // A transaction that read first and wrote later could otherwise fail with SQLITE_BUSY
+        if TransactionManager::<SqliteConnection>::get_transaction_depth(manager) == 0 {
+            manager.begin_transaction_sql(self, "BEGIN IMMEDIATE")
+        } else {
+            manager.begin_transaction(self)
+        }
+        .map_err(Into::into)
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.transaction_manager()
+            .commit_transaction(self)
+            .map_err(Into::into)
+    }
+
+    fn rollback(&self) -> Result<()> {
+        self.transaction_manager()
+            .rollback_transaction(self)
+            .map_err(Into::into)
+    }
+
+    fn get_items_by_id(&self, limit: i64, ids: &[i32]) -> Result<Vec<InventoryItem>> {
+        get_items_by_id(self, limit, ids)
+    }
+
+    fn list_items(
+        &self,
+        params: &ItemParams,
+        w_id: Option<i32>,
+        sort: Sort<ItemKey>,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<InventoryItem>> {
+        list_items(self, params, w_id, sort, after)
+    }
+
+    fn get_item(&self, id: i32) -> Result<InventoryItem> {
+        get_item(self, id)
+    }
+
+    fn insert_item(&self, item: &NewInventoryItem) -> Result<InventoryItem> {
+        insert_item(self, item)
+    }
+
+    fn import_item(&self, item: &InventoryItem) -> Result<InventoryItem> {
+        import_item(self, item)
+    }
+
+    // AUTOINCREMENT already never hands out an id below one a client picked
+    fn sync_id_sequences(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn update_item(&self, item: &InventoryItem) -> Result<InventoryItem> {
+        update_item(self, item)
+    }
+
+    fn trash_item(&self, id: i32) -> Result<InventoryItem> {
+        trash_item(self, id)
+    }
+
+    fn restore_item(&self, id: i32) -> Result<InventoryItem> {
+        restore_item(self, id)
+    }
+
+    fn get_trashed_items(&self, limit: i64) -> Result<Vec<InventoryItem>> {
+        get_trashed_items(self, limit)
+    }
+
+    fn purge_items(&self, before: DateTime<Utc>) -> Result<usize> {
+        purge_items(self, before)
+    }
+
+    // `begin` already holds the database's write lock, which covers every row
+    fn get_item_for_update(&self, id: i32) -> Result<InventoryItem> {
+        get_item(self, id)
+    }
+
+    fn get_warehouse_items(&self, w_id: i32, limit: i64) -> Result<Vec<InventoryItem>> {
+        get_warehouse_items(self, w_id, limit)
+    }
+
+    fn insert_stock(&self, item_id: i32, w_id: i32, qty: i32) -> Result<Stock> {
+        insert_stock(self, item_id, w_id, qty)
+    }
+
+    fn set_stock_quantity(&self, item_id: i32, qty: i32) -> Result<Stock> {
+        set_stock_quantity(self, item_id, qty)
+    }
+
+    fn move_stock(&self, item_id: i32, w_id: i32) -> Result<Stock> {
+        move_stock(self, item_id, w_id)
+    }
+
+    fn delete_stock(&self, item_id: i32) -> Result<Stock> {
+        delete_stock(self, item_id)
+    }
+
+    fn clear_warehouse_items(&self, w_id: i32) -> Result<usize> {
+        clear_warehouse_items(self, w_id)
+    }
+
+    fn insert_movement(&self, movement: &NewMovement) -> Result<Movement> {
+        insert_movement(self, movement)
+    }
+
+    fn get_item_movements(&self, item_id: i32, limit: i64) -> Result<Vec<Movement>> {
+        get_item_movements(self, item_id, limit)
+    }
+
+    fn get_last_item_movement(&self, item_id: i32) -> Result<Movement> {
+        get_last_item_movement(self, item_id)
+    }
+
+    fn get_warehouse_movements(&self, w_id: i32, limit: i64) -> Result<Vec<Movement>> {
+        get_warehouse_movements(self, w_id, limit)
+    }
+
+    fn get_movements_after(&self, after: i64, limit: i64) -> Result<Vec<Movement>> {
+        get_movements_after(self, after, limit)
+    }
+
+    fn get_all_stock(&self) -> Result<Vec<Stock>> {
+        get_all_stock(self)
+    }
+
+    fn get_warehouses_by_id(&self, limit: i64, ids: &[i32]) -> Result<Vec<Warehouse>> {
+        get_warehouses_by_id(self, limit, ids)
+    }
+
+    fn list_warehouses(
+        &self,
+        limit: i64,
+        trashed: bool,
+        sort: Sort<()>,
+        after: Option<&Cursor>,
+    ) -> Result<Vec<Warehouse>> {
+        list_warehouses(self, limit, trashed, sort, after)
+    }
+
+    fn get_warehouse(&self, id: i32) -> Result<Warehouse> {
+        get_warehouse(self, id)
+    }
+
+    fn get_warehouse_for_update(&self, id: i32) -> Result<Warehouse> {
+        get_warehouse(self, id)
+    }
+
+    fn insert_warehouse(&self) -> Result<Warehouse> {
+        insert_warehouse(self)
+    }
+
+    fn import_warehouse(&self, id: i32) -> Result<Warehouse> {
+        import_warehouse(self, id)
+    }
+
+    fn trash_warehouse(&self, id: i32) -> Result<Warehouse> {
+        trash_warehouse(self, id)
+    }
+
+    fn restore_warehouse(&self, id: i32) -> Result<Warehouse> {
+        restore_warehouse(self, id)
+    }
+
+    fn get_trashed_warehouses(&self, limit: i64) -> Result<Vec<Warehouse>> {
+        get_trashed_warehouses(self, limit)
+    }
+
+    fn purge_warehouses(&self, before: DateTime<Utc>) -> Result<usize> {
+        purge_warehouses(self, before)
+    }
+
+    fn insert_api_key(&self, key: &ApiKeyRow) -> Result<ApiKey> {
+        insert_api_key(self, key)
+    }
+
+    fn get_api_keys(&self) -> Result<Vec<ApiKey>> {
+        get_api_keys(self)
+    }
+
+    fn get_active_api_key(&self, hash: &str) -> Result<ApiKey> {
+        get_active_api_key(self, hash)
+    }
+
+    fn revoke_api_key(&self, key_id: i32) -> Result<ApiKey> {
+        revoke_api_key(self, key_id)
+    }
+
+    fn count_api_keys(&self) -> Result<i64> {
+        count_api_keys(self)
+    }
+
+    fn get_idempotent_request(&self, key_id: i32, key: &str) -> Result<IdempotentRequest> {
+        get_idempotent_request(self, key_id, key)
+    }
+
+    fn insert_idempotent_request(
+        &self,
+        key_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<IdempotentRequest> {
+        insert_idempotent_request(self, key_id, key, fingerprint)
+    }
+
+    fn save_idempotent_response(
+        &self,
+        key_id: i32,
+        key: &str,
+        response: &StoredResponse,
+    ) -> Result<()> {
+        save_idempotent_response(self, key_id, key, response)
+    }
+
+    fn delete_idempotent_request(&self, key_id: i32, key: &str) -> Result<()> {
+        delete_idempotent_request(self, key_id, key)
+    }
+
+    fn delete_idempotent_requests_before(&self, before: DateTime<Utc>) -> Result<usize> {
+        delete_idempotent_requests_before(self, before)
+    }
+
+    fn get_applied_migrations(&self) -> Result<Vec<String>> {
+        get_applied_migrations(self)
+    }
+
+    fn migrations(&self) -> &'static [&'static str] {
+        SQLITE_MIGRATIONS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NewInventoryItem;
+
+    // A fresh in-memory database with the SQLite schema
+    fn connect() -> SqliteConnection {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("PRAGMA foreign_keys = ON;").unwrap();
+        for up in [
+            include_str!("../migrations_sqlite/2022-02-14-000000_schema/up.sql"),
+            include_str!("../migrations_sqlite/2022-02-17-000000_versions/up.sql"),
+            include_str!("../migrations_sqlite/2022-02-21-000000_idempotency_keys/up.sql"),
+            include_str!("../migrations_sqlite/2022-02-24-000000_trash/up.sql"),
+        ] {
+            conn.batch_execute(up).unwrap();
+        }
+        conn
+    }
+
+    fn new_item(amount: &str) -> NewInventoryItem {
+        NewInventoryItem {
+            warehouse: None,
+            quantity: None,
+            weight_g: 1500,
+            value: Money {
+                amount: amount.parse().unwrap(),
+                currency: "USD".to_string(),
+            },
+            transport: Transport::Sea,
+            dimensions_mm: Dimensions {
+                width: 10,
+                height: 20,
+                depth: 30,
+            },
+        }
+    }
+
+    #[test]
+    fn items_are_stored_and_loaded_whole() {
+        let conn = connect();
+        let w_id = insert_warehouse(&conn).unwrap().id;
+
+        let item = insert_item(&conn, &new_item("12.5")).unwrap();
+        insert_stock(&conn, item.id, w_id, 3).unwrap();
+        let item = get_item(&conn, item.id).unwrap();
+
+        assert_eq!(item.value.amount.to_string(), "12.5000");
+        assert_eq!((item.warehouse, item.quantity), (Some(w_id), 3));
+        assert_eq!(item.transport, Transport::Sea);
+        assert_eq!(item.dimensions_mm.depth, 30);
+
+        // Foreign keys are enforced, and purging the item takes its stock with it
+        trash_warehouse(&conn, w_id).unwrap();
+        trash_item(&conn, item.id).unwrap();
+        let later = Utc::now() + chrono::Duration::seconds(1);
+        assert!(matches!(
+            purge_warehouses(&conn, later),
+            Err(Error::ReferenceViolation { .. })
+        ));
+        assert_eq!(purge_items(&conn, later).unwrap(), 1);
+        assert_eq!(purge_warehouses(&conn, later).unwrap(), 1);
+    }
+
+    #[test]
+    fn amounts_are_filtered_and_paged_exactly() {
+        let conn = connect();
+        for amount in ["1.25", "5.5", "9", "5.5"] {
+            insert_item(&conn, &new_item(amount)).unwrap();
+        }
+
+        let sort = Sort {
+            key: ItemKey::Value,
+            descending: true,
+        };
+        let params = ItemParams {
+            limit: Some(2),
+            value_gt: Some("1.2501".parse().unwrap()),
+            ..ItemParams::default()
+        };
+        let amounts = |items: Vec<InventoryItem>| -> Vec<(String, i32)> {
+            items
+                .into_iter()
+                .map(|item| (item.value.amount.to_string(), item.id))
+                .collect()
+        };
+
+        let first = list_items(&conn, &params, None, sort, None).unwrap();
+        assert_eq!(
+            amounts(first),
+            [("9.0000", 3), ("5.5000", 4), ("5.5000", 2)].map(|(a, id)| (a.to_string(), id))
+        );
+
+        let after = Cursor {
+            sort: "-value".to_string(),
+            key: "5.5000".to_string(),
+            id: 4,
+        };
+        let rest = list_items(&conn, &params, None, sort, Some(&after)).unwrap();
+        assert_eq!(amounts(rest), [("5.5000".to_string(), 2)]);
+    }
+
+    #[test]
+    fn amounts_past_the_column_are_rejected() {
+        let conn = connect();
+
+        let err = insert_item(&conn, &new_item("999999999999999.9999")).unwrap_err();
+        assert!(matches!(err, Error::Validation { .. }));
+    }
+
+    #[test]
+    fn versions_count_changes_to_items_and_their_stock() {
+        let conn = connect();
+        let (from, to) = (
+            insert_warehouse(&conn).unwrap().id,
+            insert_warehouse(&conn).unwrap().id,
+        );
+        let item = insert_item(&conn, &new_item("1")).unwrap();
+        let versions = || {
+            (
+                get_item(&conn, item.id).unwrap().version,
+                get_warehouse(&conn, from).unwrap().version,
+                get_warehouse(&conn, to).unwrap().version,
+            )
+        };
+        assert_eq!(versions(), (1, 1, 1));
+
+        update_item(&conn, &item).unwrap();
+        assert_eq!(versions(), (2, 1, 1));
+
+        insert_stock(&conn, item.id, from, 1).unwrap();
+        set_stock_quantity(&conn, item.id, 2).unwrap();
+        assert_eq!(versions(), (4, 2, 1));
+
+        move_stock(&conn, item.id, to).unwrap();
+        assert_eq!(versions(), (5, 3, 2));
+
+        delete_stock(&conn, item.id).unwrap();
+        assert_eq!(versions(), (6, 3, 3));
+    }
+
+    #[test]
+    fn an_empty_response_is_kept() {
+        let conn = connect();
+        let row = ApiKeyRow {
+            name: "scanner",
+            role: Role::Operator,
+            key_hash: "hash",
+            prefix: "wh_test",
+        };
+        let key_id = insert_api_key(&conn, &row).unwrap().id;
+        let claimed = insert_idempotent_request(&conn, key_id, "scan-1", "fp").unwrap();
+        assert_eq!(claimed.response, None);
+
+        // Like a 204's, which is read back as empty rather than missing
+        let response = StoredResponse {
+            status: 204,
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        save_idempotent_response(&conn, key_id, "scan-1", &response).unwrap();
+        let request = get_idempotent_request(&conn, key_id, "scan-1").unwrap();
+        assert_eq!(request.response, Some(response));
+    }
+}
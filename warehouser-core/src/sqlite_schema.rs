@@ -0,0 +1,96 @@
+// The tables of `migrations_sqlite/`, the same as `schema.rs` with SQLite's types
+table! {
+    use diesel::sql_types::*;
+    use crate::models::*;
+
+    api_keys (id) {
+        id -> Integer,
+        name -> Text,
+        role -> SqliteRole,
+        key_hash -> Text,
+        prefix -> Text,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    idempotency_keys (key_id, idempotency_key) {
+        key_id -> Integer,
+        idempotency_key -> Text,
+        fingerprint -> Text,
+        status -> Nullable<Integer>,
+        headers -> Nullable<Text>,
+        body -> Nullable<Binary>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::models::*;
+
+    inventory (id) {
+        id -> Integer,
+        transport -> SqliteTransport,
+        weight_g -> BigInt,
+        value_amount -> BigInt,
+        value_currency -> Text,
+        width_mm -> Integer,
+        height_mm -> Integer,
+        depth_mm -> Integer,
+        version -> Integer,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::models::*;
+
+    movements (id) {
+        id -> BigInt,
+        item -> Integer,
+        from_warehouse -> Nullable<Integer>,
+        to_warehouse -> Nullable<Integer>,
+        quantity -> Integer,
+        reason -> SqliteMovementReason,
+        created_at -> Timestamp,
+        actor -> Text,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    stock (item) {
+        item -> Integer,
+        warehouse -> Integer,
+        quantity -> Integer,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    warehouses (id) {
+        id -> Integer,
+        version -> Integer,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+joinable!(idempotency_keys -> api_keys (key_id));
+joinable!(stock -> inventory (item));
+joinable!(stock -> warehouses (warehouse));
+
+allow_tables_to_appear_in_same_query!(
+    api_keys,
+    idempotency_keys,
+    inventory,
+    movements,
+    stock,
+    warehouses,
+);
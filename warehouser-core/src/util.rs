@@ -0,0 +1,585 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use utoipa::IntoParams;
+
+use crate::models::{
+    Dimensions, Error, FieldError, InventoryItem, Money, NewInventoryItem, NewWarehouse, Result,
+    Transport, Warehouse,
+};
+
+/// A row of a csv export
+pub trait CsvRow {
+    /// Every column, in the order they're exported by default
+    const COLUMNS: &'static [&'static str];
+
+    /// The value of one of `COLUMNS` for this row
+    fn field(&self, column: &str) -> String;
+}
+
+impl CsvRow for InventoryItem {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "warehouse",
+        "quantity",
+        "weight_g",
+        "value",
+        "currency",
+        "transport",
+        "width_mm",
+        "height_mm",
+        "depth_mm",
+    ];
+
+    fn field(&self, column: &str) -> String {
+        match column {
+            "id" => self.id.to_string(),
+            // An empty field, rather than `null`, so spreadsheets see a blank cell
+            "warehouse" => self.warehouse.map(|id| id.to_string()).unwrap_or_default(),
+            "quantity" => self.quantity.to_string(),
+            "weight_g" => self.weight_g.to_string(),
+            "value" => self.value.amount.to_string(),
+            "currency" => self.value.currency.clone(),
+            "transport" => self.transport.to_string(),
+            "width_mm" => self.dimensions_mm.width.to_string(),
+            "height_mm" => self.dimensions_mm.height.to_string(),
+            "depth_mm" => self.dimensions_mm.depth.to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl CsvRow for Warehouse {
+    const COLUMNS: &'static [&'static str] = &["id", "items"];
+
+    fn field(&self, column: &str) -> String {
+        match column {
+            "id" => self.id.to_string(),
+            "items" => {
+                let items: Vec<_> = self.items.iter().map(|id| id.to_string()).collect();
+                items.join(", ")
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+/// The query string options of a csv export
+#[derive(Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CsvPayload {
+    pub columns: Option<String>, // Comma separated, all columns when missing
+    pub delimiter: Option<String>, // A single character, `,` when missing
+}
+
+/// How to write a csv export, checked against the columns of `T`
+#[derive(Debug, Clone)]
+pub struct CsvFormat {
+    columns: Vec<&'static str>,
+    delimiter: u8,
+}
+
+impl CsvFormat {
+    pub fn new<T: CsvRow>(payload: &CsvPayload) -> Result<Self> {
+        let mut errors = Vec::new();
+
+        let columns = match payload.columns.as_deref() {
+            None => T::COLUMNS.to_vec(),
+            Some(columns) => {
+                let mut found = Vec::new();
+                for column in columns.split(',').map(str::trim) {
+                    match T::COLUMNS.iter().find(|known| **known == column) {
+                        Some(known) => found.push(*known),
+                        None => errors.push(FieldError {
+                            field: "columns".to_string(),
+                            code: "column".to_string(),
+                            message: format!(
+                                "must only list columns from {}, found {column:?}",
+                                T::COLUMNS.join(", ")
+                            ),
+                        }),
+                    }
+                }
+                found
+            }
+        };
+
+        // Quotes and line breaks would make the output ambiguous
+        let delimiter = match payload.delimiter.as_deref().map(str::as_bytes) {
+            None => b',',
+            Some(&[byte]) if byte.is_ascii() && !matches!(byte, b'"' | b'\r' | b'\n') => byte,
+            Some(_) => {
+                errors.push(FieldError {
+                    field: "delimiter".to_string(),
+                    code: "delimiter".to_string(),
+                    message: "must be a single character, other than a quote or a line break"
+                        .to_string(),
+                });
+                b','
+            }
+        };
+
+        if errors.is_empty() {
+            Ok(CsvFormat { columns, delimiter })
+        } else {
+            Err(Error::Validation { errors })
+        }
+    }
+
+    /// Write `rows` as RFC 4180 csv, preceded by the header row when `header` is set
+    pub fn write<T: CsvRow>(&self, rows: &[T], header: bool) -> Result<Vec<u8>> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .terminator(csv::Terminator::CRLF)
+            .from_writer(Vec::new());
+
+        let failed = |e: csv::Error| Error::Internal {
+            detail: format!("Couldn't write csv: {e}"),
+        };
+
+        if header {
+            writer.write_record(&self.columns).map_err(failed)?;
+        }
+
+        for row in rows {
+            writer
+                .write_record(self.columns.iter().map(|column| row.field(column)))
+                .map_err(failed)?;
+        }
+
+        writer.into_inner().map_err(|e| Error::Internal {
+            detail: format!("Couldn't write csv: {e}"),
+        })
+    }
+}
+
+impl CsvFormat {
+    /// Read csv with a header row, in the delimiter of this format
+    ///
+    /// Columns are matched by name, in any order. Each row comes back with its line number,
+    /// so a bad row can be reported without giving up on the rest
+    pub fn read<T: DeserializeOwned>(&self, csv: &[u8]) -> Result<Vec<(u64, Result<T>)>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .trim(csv::Trim::All)
+            .from_reader(csv);
+
+        // Without a header there's no telling which column is which
+        let headers = reader
+            .headers()
+            .map_err(|e| Error::InvalidRequest {
+                detail: format!("Couldn't read the csv header: {e}"),
+            })?
+            .clone();
+
+        // csv's own messages start with a position, which is already on the row's error
+        let invalid = |e: csv::Error| {
+            let detail = match e.kind() {
+                csv::ErrorKind::Deserialize { err, .. } => {
+                    match err.field().and_then(|i| headers.get(i as usize)) {
+                        Some(column) => format!("{column}: {}", err.kind()),
+                        None => err.kind().to_string(),
+                    }
+                }
+                csv::ErrorKind::UnequalLengths {
+                    expected_len, len, ..
+                } => format!("has {len} fields, the header has {expected_len}"),
+                _ => e.to_string(),
+            };
+            Error::InvalidRequest { detail }
+        };
+
+        // csv counts the `\n` of a `\r\n` when it starts on the next record, which would put
+        // every row of a CRLF file a line early, so lines are counted from where the row starts
+        let breaks: Vec<usize> = (0..csv.len()).filter(|&i| csv[i] == b'\n').collect();
+        let line = |pos: &csv::Position| {
+            let start = pos.byte() as usize;
+            let start = csv[start..]
+                .iter()
+                .position(|byte| !matches!(byte, b'\r' | b'\n'))
+                .map_or(csv.len(), |skipped| start + skipped);
+            1 + breaks.partition_point(|&i| i < start) as u64
+        };
+
+        let rows = reader
+            .records()
+            .map(|record| match record {
+                Ok(record) => (
+                    record.position().map_or(0, line),
+                    record.deserialize(Some(&headers)).map_err(invalid),
+                ),
+                Err(e) => (e.position().map_or(0, line), Err(invalid(e))),
+            })
+            .collect();
+
+        Ok(rows)
+    }
+}
+
+/// An item row of a csv import, in the same layout as the export
+///
+/// Rows with an `id` keep it, like `POST /api/item/import`,
+/// rows without one are given one like `POST /api/item`
+#[derive(Debug, Deserialize)]
+pub struct ItemRecord {
+    #[serde(default)]
+    pub id: Option<i32>,
+    #[serde(default)]
+    pub warehouse: Option<i32>,
+    #[serde(default)]
+    pub quantity: Option<i32>,
+    pub weight_g: i64,
+    pub value: String, // Parsed by us, csv would read it as a float
+    pub currency: String,
+    pub transport: Transport,
+    pub width_mm: i32,
+    pub height_mm: i32,
+    pub depth_mm: i32,
+}
+
+/// What to do with an item row
+pub enum ItemImport {
+    Import(InventoryItem),
+    Create(NewInventoryItem),
+}
+
+impl ItemRecord {
+    pub fn into_import(self) -> Result<ItemImport> {
+        let amount = BigDecimal::from_str(&self.value).map_err(|_| Error::InvalidRequest {
+            detail: format!("value {:?} is not a number", self.value),
+        })?;
+
+        let value = Money {
+            amount,
+            currency: self.currency,
+        };
+        let dimensions_mm = Dimensions {
+            width: self.width_mm,
+            height: self.height_mm,
+            depth: self.depth_mm,
+        };
+
+        let import = match self.id {
+            Some(id) => ItemImport::Import(InventoryItem {
+                id,
+                warehouse: self.warehouse,
+                quantity: self.quantity.unwrap_or(0),
+                weight_g: self.weight_g,
+                value,
+                transport: self.transport,
+                dimensions_mm,
+                version: 0,
+                deleted_at: None,
+            }),
+            None => ItemImport::Create(NewInventoryItem {
+                warehouse: self.warehouse,
+                quantity: self.quantity,
+                weight_g: self.weight_g,
+                value,
+                transport: self.transport,
+                dimensions_mm,
+            }),
+        };
+
+        Ok(import)
+    }
+}
+
+/// A warehouse row of a csv import, in the same layout as the export
+#[derive(Debug, Deserialize)]
+pub struct WarehouseRecord {
+    #[serde(default)]
+    pub id: Option<i32>,
+    #[serde(default)]
+    pub items: Option<String>, // Comma separated item ids, one unit of each is added
+}
+
+/// What to do with a warehouse row
+pub enum WarehouseImport {
+    Import(Warehouse),
+    Create(NewWarehouse),
+}
+
+impl WarehouseRecord {
+    pub fn into_import(self) -> Result<WarehouseImport> {
+        let items = self
+            .items
+            .as_deref()
+            .unwrap_or("")
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                id.parse().map_err(|_| Error::InvalidRequest {
+                    detail: format!("item id {id:?} is not a number"),
+                })
+            })
+            .collect::<Result<Vec<i32>>>()?;
+
+        let import = match self.id {
+            Some(id) => WarehouseImport::Import(Warehouse {
+                id,
+                items,
+                version: 0,
+                deleted_at: None,
+            }),
+            None => WarehouseImport::Create(NewWarehouse { items }),
+        };
+
+        Ok(import)
+    }
+}
+
+/// Apply a JSON Merge Patch (RFC 7396) to `target`
+///
+/// Objects are merged key by key, a `null` removes the key, anything else replaces what was there
+pub fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    use serde_json::{Map, Value};
+
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(currency: &str) -> InventoryItem {
+        InventoryItem {
+            id: 3,
+            warehouse: Some(1),
+            quantity: 2,
+            weight_g: 1500,
+            value: Money {
+                amount: "12.50".parse().unwrap(),
+                currency: currency.to_string(),
+            },
+            transport: Transport::Land,
+            dimensions_mm: Dimensions {
+                width: 10,
+                height: 20,
+                depth: 30,
+            },
+            version: 1,
+            deleted_at: None,
+        }
+    }
+
+    fn format<T: CsvRow>(columns: Option<&str>, delimiter: Option<&str>) -> Result<CsvFormat> {
+        CsvFormat::new::<T>(&CsvPayload {
+            columns: columns.map(str::to_string),
+            delimiter: delimiter.map(str::to_string),
+        })
+    }
+
+    fn written<T: CsvRow>(format: &CsvFormat, rows: &[T], header: bool) -> String {
+        String::from_utf8(format.write(rows, header).unwrap()).unwrap()
+    }
+
+    fn field_errors(result: Result<CsvFormat>) -> Vec<(String, String)> {
+        match result {
+            Err(Error::Validation { errors }) => errors
+                .into_iter()
+                .map(|error| (error.field, error.code))
+                .collect(),
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rows_end_in_crlf_after_the_header() {
+        let format = format::<InventoryItem>(Some("id, quantity"), None).unwrap();
+        let csv = written(&format, &[item("USD")], true);
+        assert_eq!(csv, "id,quantity\r\n3,2\r\n");
+
+        let csv = written(&format, &[item("USD")], false);
+        assert_eq!(csv, "3,2\r\n");
+    }
+
+    #[test]
+    fn a_field_with_the_delimiter_is_quoted() {
+        let warehouses = [Warehouse {
+            id: 1,
+            items: vec![3, 4],
+            version: 1,
+            deleted_at: None,
+        }];
+
+        let comma = format::<Warehouse>(None, None).unwrap();
+        assert_eq!(written(&comma, &warehouses, false), "1,\"3, 4\"\r\n");
+
+        // With another delimiter the commas are just text
+        let semicolon = format::<Warehouse>(None, Some(";")).unwrap();
+        assert_eq!(written(&semicolon, &warehouses, false), "1;3, 4\r\n");
+    }
+
+    #[test]
+    fn a_field_with_quotes_or_line_breaks_is_quoted() {
+        let format = format::<InventoryItem>(Some("id,currency"), None).unwrap();
+
+        let csv = written(&format, &[item("U\"SD")], false);
+        assert_eq!(csv, "3,\"U\"\"SD\"\r\n");
+
+        let csv = written(&format, &[item("U\nSD"), item("U\r\nSD")], false);
+        assert_eq!(csv, "3,\"U\nSD\"\r\n3,\"U\r\nSD\"\r\n");
+    }
+
+    #[test]
+    fn the_delimiter_must_be_a_single_character_other_than_a_quote_or_a_line_break() {
+        for delimiter in ["", ";;", "\"", "\r", "\n", "é"] {
+            assert_eq!(
+                field_errors(format::<InventoryItem>(None, Some(delimiter))),
+                vec![("delimiter".to_string(), "delimiter".to_string())],
+                "{delimiter:?}"
+            );
+        }
+
+        assert!(format::<InventoryItem>(None, Some("\t")).is_ok());
+    }
+
+    #[test]
+    fn every_unknown_column_and_a_bad_delimiter_are_reported_together() {
+        let errors = field_errors(format::<InventoryItem>(Some("id,colour,size"), Some("")));
+        let fields: Vec<_> = errors.iter().map(|(field, _)| field.as_str()).collect();
+        assert_eq!(fields, ["columns", "columns", "delimiter"]);
+    }
+
+    const ITEM_HEADER: &str =
+        "id,warehouse,quantity,weight_g,value,currency,transport,width_mm,height_mm,depth_mm";
+
+    fn read_items(csv: &str) -> Vec<(u64, Result<ItemRecord>)> {
+        let format = format::<InventoryItem>(None, None).unwrap();
+        format.read(csv.as_bytes()).unwrap()
+    }
+
+    fn invalid_request<T: std::fmt::Debug>(result: &Result<T>) -> &str {
+        match result {
+            Err(Error::InvalidRequest { detail }) => detail,
+            other => panic!("expected an invalid request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_bad_row_is_reported_on_its_own_line() {
+        let csv = format!(
+            "{ITEM_HEADER}\r\n\
+             1,,0,1500,12.50,USD,Land,10,20,30\r\n\
+             2,,0,heavy,12.50,USD,Land,10,20,30\r\n\
+             3,,0,1500,12.50,\"U\nSD\",Land,10,20,30\r\n\
+             4,,0,1500,12.50,USD,Rail,10,20,30\r\n"
+        );
+        let rows = read_items(&csv);
+
+        // A quoted line break doesn't start a new row, but does move the rows after it down
+        let lines: Vec<_> = rows.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [2, 3, 4, 6]);
+
+        assert!(rows[0].1.is_ok());
+        assert_eq!(
+            invalid_request(&rows[1].1),
+            "weight_g: invalid digit found in string"
+        );
+        assert_eq!(rows[2].1.as_ref().unwrap().currency, "U\nSD");
+        assert_eq!(
+            invalid_request(&rows[3].1),
+            "unknown variant `Rail`, expected one of `Air`, `Sea`, `Land`"
+        );
+    }
+
+    #[test]
+    fn columns_are_matched_by_name_not_position() {
+        let csv = "transport;currency;value;weight_g;depth_mm;height_mm;width_mm;id\n\
+                   Air;EUR;3;250;30;20;10;9\n";
+        let format = format::<InventoryItem>(None, Some(";")).unwrap();
+        let rows: Vec<(u64, Result<ItemRecord>)> = format.read(csv.as_bytes()).unwrap();
+
+        let record = rows[0].1.as_ref().unwrap();
+        assert_eq!(record.id, Some(9));
+        assert_eq!(record.transport, Transport::Air);
+        assert_eq!(record.currency, "EUR");
+        assert_eq!(record.width_mm, 10);
+    }
+
+    #[test]
+    fn every_row_fails_when_the_header_is_missing_a_column() {
+        let csv = "id,warehouse,quantity,value,currency,transport,width_mm,height_mm,depth_mm\n\
+                   1,,0,12.50,USD,Land,10,20,30\n\
+                   2,,0,12.50,USD,Land,10,20,30\n";
+        let rows = read_items(csv);
+
+        assert_eq!(rows.len(), 2);
+        for (_, row) in &rows {
+            assert_eq!(invalid_request(row), "missing field `weight_g`");
+        }
+    }
+
+    #[test]
+    fn a_row_with_the_wrong_number_of_fields_is_turned_down() {
+        let csv = format!("{ITEM_HEADER}\n1,,0,1500,12.50,USD,Land,10,20\n");
+        let rows = read_items(&csv);
+
+        assert_eq!(rows[0].0, 2);
+        assert_eq!(
+            invalid_request(&rows[0].1),
+            "has 9 fields, the header has 10"
+        );
+    }
+
+    #[test]
+    fn a_row_with_an_empty_id_is_created_rather_than_imported() {
+        let csv = format!(
+            "{ITEM_HEADER}\n\
+             ,1,4,1500,12.50,USD,Land,10,20,30\n\
+             7,1,4,1500,12.50,USD,Land,10,20,30\n\
+             seven,1,4,1500,12.50,USD,Land,10,20,30\n"
+        );
+        let mut rows = read_items(&csv).into_iter().map(|(_, row)| row);
+
+        match rows.next().unwrap().unwrap().into_import().unwrap() {
+            ItemImport::Create(item) => {
+                assert_eq!(item.warehouse, Some(1));
+                assert_eq!(item.quantity, Some(4));
+            }
+            ItemImport::Import(item) => panic!("expected a new item, got {item:?}"),
+        }
+        match rows.next().unwrap().unwrap().into_import().unwrap() {
+            ItemImport::Import(item) => assert_eq!((item.id, item.quantity), (7, 4)),
+            ItemImport::Create(item) => panic!("expected item 7, got {item:?}"),
+        }
+        assert_eq!(
+            invalid_request(&rows.next().unwrap()),
+            "id: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn a_value_that_is_not_a_number_is_turned_down() {
+        let csv = format!("{ITEM_HEADER}\n1,,0,1500,lots,USD,Land,10,20,30\n");
+        let record = read_items(&csv).remove(0).1.unwrap();
+
+        let detail = match record.into_import() {
+            Err(Error::InvalidRequest { detail }) => detail,
+            _ => panic!("expected an invalid request"),
+        };
+        assert_eq!(detail, "value \"lots\" is not a number");
+    }
+}
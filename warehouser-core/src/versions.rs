@@ -0,0 +1,472 @@
+/// Versions of the json api
+/// Version 2 is the shape of the models themselves,
+/// version 1 is the shape items had while weights, values, and dimensions were whole numbers
+// Clients opt in to version 2 with its media type,
+// in `Accept` for what they get back, and in `Content-Type` for what they send.
+// Everyone else keeps getting and sending version 1, so old clients keep working
+use std::ops::Deref;
+
+use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::conditional::{etag, v2_etag};
+use crate::models::{
+    Dimensions, Error, FieldError, InventoryItem, Money, NewInventoryItem, OperationError, Result,
+    Transport,
+};
+use crate::util::merge_patch;
+
+/// The media type of version 2
+pub const V2_MEDIA_TYPE: &str = "application/vnd.warehouser.v2+json";
+
+/// Version 1 only knew about dollars
+const V1_CURRENCY: &str = "USD";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    /// Version 2 if any of the media types in a header's values is ours
+    pub fn from_media_types<'a>(values: impl IntoIterator<Item = &'a str>) -> Self {
+        let v2 = values
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(|media| media.split(';').next())
+            .any(|media| media.trim().eq_ignore_ascii_case(V2_MEDIA_TYPE));
+
+        if v2 {
+            ApiVersion::V2
+        } else {
+            ApiVersion::V1
+        }
+    }
+
+    /// The `ETag` of an item at `version` sent in this version's shape
+    pub fn etag(self, version: i32) -> String {
+        match self {
+            ApiVersion::V1 => etag(version),
+            ApiVersion::V2 => v2_etag(version),
+        }
+    }
+
+    pub fn item(self, item: InventoryItem) -> Versioned<InventoryItem, ItemV1> {
+        match self {
+            ApiVersion::V1 => Versioned::V1(ItemV1::from(&item)),
+            ApiVersion::V2 => Versioned::V2(item),
+        }
+    }
+
+    pub fn items(self, items: Vec<InventoryItem>) -> Versioned<Vec<InventoryItem>, Vec<ItemV1>> {
+        match self {
+            ApiVersion::V1 => Versioned::V1(items.iter().map(ItemV1::from).collect()),
+            ApiVersion::V2 => Versioned::V2(items),
+        }
+    }
+
+    /// `e` in the terms of a client that sent an item in this version
+    ///
+    /// Items are checked once they're converted up to version 2,
+    /// so a version 1 client is told about the fields it sent rather than what they became
+    pub fn error(self, e: Error) -> Error {
+        match (self, e) {
+            (ApiVersion::V1, Error::Validation { errors }) => Error::Validation {
+                errors: errors
+                    .into_iter()
+                    .map(|error| FieldError {
+                        field: v1_field(&error.field),
+                        ..error
+                    })
+                    .collect(),
+            },
+            (ApiVersion::V1, Error::BatchFailed { errors }) => Error::BatchFailed {
+                errors: errors
+                    .into_iter()
+                    .map(|failed| OperationError::new(failed.operation, self.error(failed.error)))
+                    .collect(),
+            },
+            (_, e) => e,
+        }
+    }
+}
+
+/// A response in one version or the other, serialized as whichever it holds
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Versioned<V2, V1> {
+    V1(V1),
+    V2(V2),
+}
+
+/// A request body that had a different shape in version 1
+pub trait Upgrade: DeserializeOwned + 'static {
+    type V1: DeserializeOwned + Into<Self>;
+}
+
+impl Upgrade for InventoryItem {
+    type V1 = ItemV1;
+}
+
+impl Upgrade for NewInventoryItem {
+    type V1 = NewItemV1;
+}
+
+/// A json request body, parsed as the version named by its `Content-Type`, which it keeps
+pub struct Body<T>(pub T, pub ApiVersion);
+
+impl<T> Body<T> {
+    /// The version the body was sent in, see `ApiVersion::error`
+    pub fn version(&self) -> ApiVersion {
+        self.1
+    }
+}
+
+impl<T> Deref for Body<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// A JSON Merge Patch of an item, in the version named by its `Content-Type`
+///
+/// `application/merge-patch+json` is read as version 1, like plain json
+pub struct Patch {
+    pub version: ApiVersion,
+    pub patch: serde_json::Value,
+}
+
+impl Patch {
+    /// The item with the patch applied
+    pub fn apply(&self, item: InventoryItem) -> Result<InventoryItem> {
+        match self.version {
+            ApiVersion::V2 => {
+                let mut value = to_json(&item)?;
+                merge_patch(&mut value, &self.patch);
+                from_json(value)
+            }
+            // Only the fields the patch changed are converted back up
+            ApiVersion::V1 => {
+                let mut value = to_json(&ItemV1::from(&item))?;
+                merge_patch(&mut value, &self.patch);
+                let after: ItemV1 = from_json(value)?;
+                Ok(after.onto(&item))
+            }
+        }
+    }
+}
+
+/// An item sent to replace the stored one, in the version named by its `Content-Type`
+///
+/// It's only turned into the item to store once the stored one is known,
+/// so the fields version 1 rounds aren't overwritten by the rounded values
+// Read as version 2, `Body` and batches read version 1 themselves, and sent as whichever it holds
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged, from = "InventoryItem")]
+pub enum ItemUpdate {
+    V1(ItemV1),
+    V2(InventoryItem),
+}
+
+impl ItemUpdate {
+    pub fn id(&self) -> i32 {
+        match self {
+            ItemUpdate::V1(item) => item.id,
+            ItemUpdate::V2(item) => item.id,
+        }
+    }
+
+    /// The item to store in place of `stored`
+    pub fn apply(&self, stored: &InventoryItem) -> InventoryItem {
+        match self {
+            ItemUpdate::V1(item) => item.onto(stored),
+            ItemUpdate::V2(item) => item.clone(),
+        }
+    }
+}
+
+impl From<InventoryItem> for ItemUpdate {
+    fn from(item: InventoryItem) -> Self {
+        ItemUpdate::V2(item)
+    }
+}
+
+impl From<ItemV1> for ItemUpdate {
+    fn from(item: ItemV1) -> Self {
+        ItemUpdate::V1(item)
+    }
+}
+
+impl Upgrade for ItemUpdate {
+    type V1 = ItemV1;
+}
+
+fn to_json(value: &impl Serialize) -> Result<serde_json::Value> {
+    serde_json::to_value(value).map_err(|e| Error::Internal {
+        detail: format!("Couldn't serialize the item: {e}"),
+    })
+}
+
+// The patch is what made it invalid, so it's the client's mistake
+fn from_json<T: DeserializeOwned>(value: serde_json::Value) -> Result<T> {
+    serde_json::from_value(value).map_err(|e| Error::InvalidRequest {
+        detail: e.to_string(),
+    })
+}
+
+/// An item as version 1 saw it
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ItemV1 {
+    pub id: i32,
+    pub warehouse: Option<i32>,
+    #[serde(default)]
+    pub quantity: i32,
+    pub weight: i64,              // Weight in kg
+    pub value: i64,               // Value in $
+    pub transport: Transport,     // Transportation method
+    pub dimensions: DimensionsV1, // Dimensions in m
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(read_only)]
+    pub deleted_at: Option<DateTime<Utc>>, // When it was moved to the trash, missing until then
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewItemV1 {
+    pub warehouse: Option<i32>,
+    #[serde(default)]
+    pub quantity: Option<i32>,
+    pub weight: i64,
+    pub value: i64,
+    pub transport: Transport,
+    pub dimensions: DimensionsV1,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DimensionsV1 {
+    pub width: i32,
+    pub height: i32,
+    pub depth: i32,
+}
+
+// Whole units convert up exactly, precise units convert down to the nearest whole unit.
+// Dimensions round up, so an item never looks smaller than it is (or 0m wide),
+// and values lose their currency, version 1 assumed dollars
+
+impl From<ItemV1> for InventoryItem {
+    fn from(item: ItemV1) -> Self {
+        InventoryItem {
+            id: item.id,
+            warehouse: item.warehouse,
+            quantity: item.quantity,
+            weight_g: item.weight.saturating_mul(1000),
+            value: dollars(item.value),
+            transport: item.transport,
+            dimensions_mm: item.dimensions.into(),
+            version: 0, // Version 1 never had one, it's only sent as an `ETag`
+            deleted_at: None,
+        }
+    }
+}
+
+impl From<NewItemV1> for NewInventoryItem {
+    fn from(item: NewItemV1) -> Self {
+        NewInventoryItem {
+            warehouse: item.warehouse,
+            quantity: item.quantity,
+            weight_g: item.weight.saturating_mul(1000),
+            value: dollars(item.value),
+            transport: item.transport,
+            dimensions_mm: item.dimensions.into(),
+        }
+    }
+}
+
+impl From<&InventoryItem> for ItemV1 {
+    fn from(item: &InventoryItem) -> Self {
+        ItemV1 {
+            id: item.id,
+            warehouse: item.warehouse,
+            quantity: item.quantity,
+            weight: item.weight_g.saturating_add(500) / 1000,
+            value: round(&item.value.amount),
+            transport: item.transport.clone(),
+            dimensions: (&item.dimensions_mm).into(),
+            deleted_at: item.deleted_at,
+        }
+    }
+}
+
+impl ItemV1 {
+    /// `item` with the changes this version 1 view of it makes
+    ///
+    /// Only the fields that read differently from `item`'s, rounded, are converted back up,
+    /// the rest keep the precision version 1 can't show, and the value keeps its currency
+    pub fn onto(&self, item: &InventoryItem) -> InventoryItem {
+        let before = ItemV1::from(item);
+        let (dims, old_dims) = (&self.dimensions, &before.dimensions);
+        let axis = |new: i32, old: i32, mm: i32| {
+            if new == old {
+                mm
+            } else {
+                new.saturating_mul(1000)
+            }
+        };
+
+        InventoryItem {
+            id: self.id,
+            warehouse: self.warehouse,
+            quantity: self.quantity,
+            weight_g: if self.weight == before.weight {
+                item.weight_g
+            } else {
+                self.weight.saturating_mul(1000)
+            },
+            value: if self.value == before.value {
+                item.value.clone()
+            } else {
+                Money {
+                    amount: BigDecimal::from(self.value),
+                    currency: item.value.currency.clone(),
+                }
+            },
+            transport: self.transport.clone(),
+            dimensions_mm: Dimensions {
+                width: axis(dims.width, old_dims.width, item.dimensions_mm.width),
+                height: axis(dims.height, old_dims.height, item.dimensions_mm.height),
+                depth: axis(dims.depth, old_dims.depth, item.dimensions_mm.depth),
+            },
+            version: item.version,
+            deleted_at: item.deleted_at,
+        }
+    }
+}
+
+impl From<DimensionsV1> for Dimensions {
+    fn from(dims: DimensionsV1) -> Self {
+        Dimensions {
+            width: dims.width.saturating_mul(1000),
+            height: dims.height.saturating_mul(1000),
+            depth: dims.depth.saturating_mul(1000),
+        }
+    }
+}
+
+impl From<&Dimensions> for DimensionsV1 {
+    fn from(dims: &Dimensions) -> Self {
+        let metres = |mm: i32| mm.saturating_add(999) / 1000;
+        DimensionsV1 {
+            width: metres(dims.width),
+            height: metres(dims.height),
+            depth: metres(dims.depth),
+        }
+    }
+}
+
+fn dollars(amount: i64) -> Money {
+    Money {
+        amount: BigDecimal::from(amount),
+        currency: V1_CURRENCY.to_string(),
+    }
+}
+
+// What version 1 calls a field of an item
+fn v1_field(field: &str) -> String {
+    match field.split_once('.') {
+        None if field == "weight_g" => "weight".to_string(),
+        // The amount, version 1 never sends a currency
+        Some(("value", _)) => "value".to_string(),
+        Some(("dimensions_mm", axis)) => format!("dimensions.{axis}"),
+        _ => field.to_string(),
+    }
+}
+
+// Amounts are never negative and always fit an `i64`, the database checks both
+fn round(amount: &BigDecimal) -> i64 {
+    let tenths = (amount * BigDecimal::from(10)).with_scale(0);
+    tenths.to_i64().unwrap_or(i64::MAX).saturating_add(5) / 10
+}
+
+// How handlers get their `ApiVersion`, `Body`, and `Patch`
+#[cfg(feature = "actix")]
+mod extract {
+    use std::{
+        future::{ready, Future, Ready},
+        pin::Pin,
+    };
+
+    use actix_web::{
+        dev::Payload,
+        http::{header, HeaderName},
+        web, FromRequest, HttpRequest,
+    };
+
+    use super::{ApiVersion, Body, Patch, Upgrade};
+    use crate::models::Error;
+
+    fn from_header(req: &HttpRequest, name: HeaderName) -> ApiVersion {
+        ApiVersion::from_media_types(
+            req.headers()
+                .get_all(name)
+                .filter_map(|value| value.to_str().ok()),
+        )
+    }
+
+    /// The version a client wants its response in, read from `Accept`
+    impl FromRequest for ApiVersion {
+        type Error = actix_web::Error;
+        type Future = Ready<Result<Self, Self::Error>>;
+        type Config = ();
+
+        fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+            ready(Ok(from_header(req, header::ACCEPT)))
+        }
+    }
+
+    impl<T: Upgrade> FromRequest for Body<T> {
+        type Error = actix_web::Error;
+        type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+        type Config = ();
+
+        fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+            let version = from_header(req, header::CONTENT_TYPE);
+            // Reading the body as plain json first keeps `JsonConfig`'s limits and error handler
+            let json = web::Json::<serde_json::Value>::from_request(req, payload);
+
+            Box::pin(async move {
+                let value = json.await?.into_inner();
+                let body = match version {
+                    ApiVersion::V1 => serde_json::from_value::<T::V1>(value).map(Into::into),
+                    ApiVersion::V2 => serde_json::from_value::<T>(value),
+                };
+
+                body.map(|body| Body(body, version)).map_err(|e| {
+                    Error::InvalidRequest {
+                        detail: e.to_string(),
+                    }
+                    .into()
+                })
+            })
+        }
+    }
+
+    impl FromRequest for Patch {
+        type Error = actix_web::Error;
+        type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+        type Config = ();
+
+        fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+            let version = from_header(req, header::CONTENT_TYPE);
+            let json = web::Json::<serde_json::Value>::from_request(req, payload);
+
+            Box::pin(async move {
+                let patch = json.await?.into_inner();
+                Ok(Patch { version, patch })
+            })
+        }
+    }
+}